@@ -1,15 +1,14 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use flodgatt::config;
 use flodgatt::request::{Content::*, Reach::*, Stream::*, Timeline};
-use flodgatt::response::{Event, Manager, RedisMsg, RedisParseOutput};
+use flodgatt::response::{Event, RedisInput, RedisMsg, RedisParseOutput};
 use flodgatt::Id;
-use futures::{Async, Stream};
 use lru::LruCache;
 use std::convert::TryFrom;
 use std::fs;
 
 fn parse_long_redis_input<'a>(input: &'a str) -> RedisMsg<'a> {
-    if let RedisParseOutput::Msg(msg) = RedisParseOutput::try_from(input).unwrap() {
+    if let RedisParseOutput::Msg(msg) = RedisParseOutput::try_from(input.as_bytes()).unwrap() {
         assert_eq!(msg.timeline_txt, "timeline:1");
         msg
     } else {
@@ -35,7 +34,7 @@ fn redis_msg_to_event_string(msg: RedisMsg) -> String {
     msg.event_txt.to_string()
 }
 
-fn string_to_checked_event(event_txt: &String) -> Event {
+fn string_to_checked_event(event_txt: &str) -> Event {
     Event::TypeSafe(serde_json::from_str(event_txt).unwrap())
 }
 
@@ -46,6 +45,36 @@ fn input_msg(i: usize) -> Vec<u8> {
         .to_vec()
 }
 
+/// The six test inputs as one buffer, as Redis would send them to a busy connection
+fn all_input_msgs() -> Vec<u8> {
+    (1..=6).flat_map(input_msg).collect()
+}
+
+/// A reply that isn't an event, with a payload that isn't UTF-8 (and contains `\r\n`)
+fn binary_reply() -> Vec<u8> {
+    let payload: Vec<u8> = (0..=255).chain(b"\r\n".iter().copied()).collect();
+    [
+        format!("${}\r\n", payload.len()).as_bytes(),
+        &payload[..],
+        b"\r\n",
+    ]
+    .concat()
+}
+
+/// Feed `input` to a `RedisInput` in reads of `read_size` bytes (splitting replies wherever
+/// a read ends), parsing after each read as Flodgatt does; returns the number of events
+fn parse_in_reads(input: &[u8], read_size: usize) -> usize {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    let mut events = 0;
+    for read in input.chunks(read_size) {
+        redis.add(read);
+        while let Ok(Some((_tl, _event))) = redis.next_event() {
+            events += 1;
+        }
+    }
+    events
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     let input = ONE_MESSAGE_FOR_THE_USER_TIMLINE_FROM_REDIS;
     let mut group = c.benchmark_group("Parse redis RESP array");
@@ -77,31 +106,51 @@ fn criterion_benchmark(c: &mut Criterion) {
     group.bench_function("parse six messages from Redis", |b| {
         b.iter_batched(
             || {
-                let mut manager = Manager::try_from(&config::Redis::default()).expect("bench");
+                let mut redis = RedisInput::new(&config::Redis::default());
                 for i in 1..=6 {
-                    manager.redis_conn.add(&input_msg(i));
+                    redis.add(&input_msg(i));
                 }
-                manager
+                redis
             },
-            |mut m| {
-                black_box({
-                    let mut i = 1;
-                    while let Ok(Async::Ready(Some(len))) = m.redis_conn.poll_redis(m.unread_idx.1)
-                    {
-                        m.unread_idx = (0, m.unread_idx.1 + len);
-                        while let Ok(Async::Ready(Some((_tl, event)))) = m.poll() {
-                            //                  println!("Parsing Event #{:03}", i + 1);
-                            //        assert_eq!(event, output(i));
-                            i += 1;
-                        }
-                    }
-
-                    assert_eq!(i, 7)
-                })
+            |mut redis| {
+                let mut i = 1;
+                while let Ok(Some((_tl, _event))) = black_box(redis.next_event()) {
+                    i += 1;
+                }
+
+                assert_eq!(i, 7);
             },
             criterion::BatchSize::SmallInput,
         )
     });
+    group.finish();
+
+    let mut group = c.benchmark_group("Parse redis RESP bytes");
+    let input = ONE_MESSAGE_FOR_THE_USER_TIMLINE_FROM_REDIS;
+    let (first_half, _) = input.as_bytes().split_at(input.len() / 2);
+    group.bench_function("parse a reply split mid-payload", |b| {
+        b.iter(|| {
+            let partial = RedisParseOutput::try_from(black_box(first_half));
+            assert!(partial.is_err());
+            black_box(parse_long_redis_input(input));
+        })
+    });
+
+    let input = all_input_msgs();
+    for read_size in &[64, 1024, 8192] {
+        group.bench_function(
+            format!("parse six messages from {}-byte reads", read_size),
+            |b| b.iter(|| assert_eq!(black_box(parse_in_reads(&input, *read_size)), 6)),
+        );
+    }
+
+    let input = [binary_reply(), all_input_msgs()].concat();
+    group.bench_function("skip a binary reply before six messages", |b| {
+        b.iter(|| assert_eq!(black_box(parse_in_reads(&input, 1024)), 6))
+    });
+    group.bench_function("skip a binary reply split mid-payload", |b| {
+        b.iter(|| assert_eq!(black_box(parse_in_reads(&input, 100)), 6))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
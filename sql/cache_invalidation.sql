@@ -0,0 +1,54 @@
+-- Triggers that notify Flodgatt when a user or block it may have cached (with DB_CACHE_TTL)
+-- changes.  Flodgatt only enables its cache once these are installed:
+--
+--     psql -d <DB_NAME> -f sql/cache_invalidation.sql
+--
+-- If you set DB_CACHE_CHANNEL, replace `flodgatt_cache` below with its value first.  Running
+-- this file again (such as after changing the channel) replaces the installed triggers.
+
+CREATE OR REPLACE FUNCTION flodgatt_invalidate_cache() RETURNS trigger AS $$
+DECLARE
+  channel CONSTANT text := 'flodgatt_cache';
+BEGIN
+  CASE TG_TABLE_NAME
+    WHEN 'oauth_access_tokens' THEN
+      PERFORM pg_notify(channel, 'token:' || OLD.token);
+    WHEN 'users' THEN
+      PERFORM pg_notify(channel, 'account:' || OLD.account_id);
+    WHEN 'blocks' THEN
+      IF TG_OP = 'DELETE' THEN
+        PERFORM pg_notify(channel, 'account:' || OLD.account_id);
+        PERFORM pg_notify(channel, 'account:' || OLD.target_account_id);
+      ELSE
+        PERFORM pg_notify(channel, 'account:' || NEW.account_id);
+        PERFORM pg_notify(channel, 'account:' || NEW.target_account_id);
+      END IF;
+    ELSE -- mutes and account_domain_blocks
+      IF TG_OP = 'DELETE' THEN
+        PERFORM pg_notify(channel, 'account:' || OLD.account_id);
+      ELSE
+        PERFORM pg_notify(channel, 'account:' || NEW.account_id);
+      END IF;
+  END CASE;
+  RETURN NULL;
+END $$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS flodgatt_invalidate_cache ON oauth_access_tokens;
+CREATE TRIGGER flodgatt_invalidate_cache AFTER UPDATE OR DELETE ON oauth_access_tokens
+  FOR EACH ROW EXECUTE PROCEDURE flodgatt_invalidate_cache();
+
+DROP TRIGGER IF EXISTS flodgatt_invalidate_cache ON users;
+CREATE TRIGGER flodgatt_invalidate_cache AFTER UPDATE OR DELETE ON users
+  FOR EACH ROW EXECUTE PROCEDURE flodgatt_invalidate_cache();
+
+DROP TRIGGER IF EXISTS flodgatt_invalidate_cache ON blocks;
+CREATE TRIGGER flodgatt_invalidate_cache AFTER INSERT OR DELETE ON blocks
+  FOR EACH ROW EXECUTE PROCEDURE flodgatt_invalidate_cache();
+
+DROP TRIGGER IF EXISTS flodgatt_invalidate_cache ON mutes;
+CREATE TRIGGER flodgatt_invalidate_cache AFTER INSERT OR DELETE ON mutes
+  FOR EACH ROW EXECUTE PROCEDURE flodgatt_invalidate_cache();
+
+DROP TRIGGER IF EXISTS flodgatt_invalidate_cache ON account_domain_blocks;
+CREATE TRIGGER flodgatt_invalidate_cache AFTER INSERT OR DELETE ON account_domain_blocks
+  FOR EACH ROW EXECUTE PROCEDURE flodgatt_invalidate_cache();
@@ -1,6 +1,9 @@
 pub use self::deployment_cfg::Deployment;
+pub use self::deployment_cfg_types::EventSourceInner;
 pub use self::postgres_cfg::Postgres;
+pub(crate) use self::postgres_cfg_types::PgSslInner;
 pub use self::redis_cfg::Redis;
+pub(crate) use self::redis_cfg_types::SlowConsumerInner;
 
 use self::environmental_variables::EnvVar;
 
@@ -17,6 +20,7 @@ mod redis_cfg_types;
 
 type Result<T> = std::result::Result<T, Error>;
 
+#[allow(clippy::unnecessary_debug_formatting)] // quote the path in messages
 pub fn merge_dotenv() -> Result<()> {
     let env_file = match env::var("ENV").ok().as_deref() {
         Some("production") => ".env.production",
@@ -66,6 +70,13 @@ pub fn from_env<'a>(
     log::info!("Configuration for {:#?},", &redis_cfg);
     let deployment_cfg = Deployment::from_env(&env_vars)?;
     log::info!("Configuration for {:#?}", &deployment_cfg);
+    if *deployment_cfg.event_source == EventSourceInner::Replay && redis_cfg.replay.is_none() {
+        Err(Error::config(
+            "EVENT_SOURCE",
+            "replay",
+            "`redis` or `postgres` unless REPLAY_REDIS is set",
+        ))?;
+    }
 
     Ok((pg_cfg, redis_cfg, deployment_cfg))
 }
@@ -85,7 +96,7 @@ impl fmt::Display for Error {
             f,
             "{}",
             match self {
-                Self::Config(e) => e.to_string(),
+                Self::Config(e) => e.clone(),
                 Self::UrlEncoding(e) => format!("could not parse POSTGRES_URL.\n{:7}{:?}", "", e),
                 Self::UrlParse(e) => format!("could parse Postgres URL.\n{:7}{}", "", e),
             }
@@ -4,12 +4,13 @@ use super::{EnvVar, Error};
 #[derive(Debug, Default)]
 pub struct Deployment<'a> {
     pub(crate) env: Env,
-    pub(crate) log_level: LogLevel,
+    pub log_level: LogLevel,
     pub address: FlodgattAddr,
     pub port: Port,
     pub unix_socket: Socket,
     pub cors: Cors<'a>,
     pub whitelist_mode: WhitelistMode,
+    pub event_source: EventSource,
 }
 
 impl Deployment<'_> {
@@ -21,6 +22,7 @@ impl Deployment<'_> {
             port: Port::default().maybe_update(env.get("PORT"))?,
             unix_socket: Socket::default().maybe_update(env.get("SOCKET"))?,
             whitelist_mode: WhitelistMode::default().maybe_update(env.get("WHITELIST_MODE"))?,
+            event_source: EventSource::default().maybe_update(env.get("EVENT_SOURCE"))?,
             cors: Cors::default(),
         };
         cfg.env = cfg.env.maybe_update(env.get("RUST_ENV"))?;
@@ -14,7 +14,7 @@ from_env_var!(
 from_env_var!(
     /// The address to run Flodgatt on
     let name = FlodgattAddr;
-    let default: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    let default: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
     let (env_var, allowed_values) = ("BIND", "a valid address (e.g., 127.0.0.1)");
     let from_str = |s| match s {
         "localhost" => Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
@@ -52,6 +52,14 @@ from_env_var!(
     let (env_var, allowed_values) = ("WHITELIST_MODE", "true or false");
     let from_str = |s| s.parse().ok();
 );
+from_env_var!(
+    /// Where to receive events from: Redis (like Mastodon's own streaming server),
+    /// notifications on Postgres's `DB_NOTIFY_CHANNELS`, or a Redis recording (`REPLAY_REDIS`)
+    let name = EventSource;
+    let default: EventSourceInner = EventSourceInner::Redis;
+    let (env_var, allowed_values) = ("EVENT_SOURCE", &format!("one of: {:?}", EventSourceInner::variants()));
+    let from_str = |s| EventSourceInner::from_str(s).ok();
+);
 /// Permissions for Cross Origin Resource Sharing (CORS)
 pub struct Cors<'a> {
     pub allowed_headers: Vec<&'a str>,
@@ -85,6 +93,14 @@ pub enum LogLevelInner {
     Error,
 }
 
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum EventSourceInner {
+    Redis,
+    Postgres,
+    Replay,
+}
+
 #[derive(EnumString, EnumVariantNames, Debug, Clone)]
 #[strum(serialize_all = "snake_case")]
 pub enum EnvInner {
@@ -35,6 +35,7 @@ impl fmt::Display for EnvVar {
             "BIND",
             "PORT",
             "SOCKET",
+            "EVENT_SOURCE",
             "SSE_FREQ",
             "WS_FREQ",
             "DATABASE_URL",
@@ -44,17 +45,48 @@ impl fmt::Display for EnvVar {
             "DB_HOST",
             "DB_PASS",
             "DB_NAME",
+            "DB_POOL",
+            "DB_POOL_MIN_IDLE",
+            "DB_CONNECTION_TIMEOUT",
+            "DB_IDLE_TIMEOUT",
+            "DB_STATEMENT_TIMEOUT",
+            "DB_CACHE_TTL",
+            "DB_CACHE_SIZE",
+            "DB_CACHE_CHANNEL",
             "DB_SSLMODE",
+            "DB_SSLROOTCERT",
+            "DB_SSLCERT",
+            "DB_SSLKEY",
+            "DB_NOTIFY_CHANNELS",
             "REDIS_HOST",
             "REDIS_USER",
             "REDIS_PORT",
+            "REDIS_SOCKET",
             "REDIS_PASSWORD",
             "REDIS_USER",
             "REDIS_DB",
             "REDIS_FREQ",
+            "REDIS_TLS",
+            "REDIS_TLS_CA_FILE",
+            "REDIS_TLS_CERT_FILE",
+            "REDIS_TLS_KEY_FILE",
+            "REDIS_TLS_INSECURE",
+            "REDIS_SENTINELS",
+            "REDIS_SENTINEL_MASTER",
+            "REDIS_PSUBSCRIBE",
+            "REDIS_STREAMS",
+            "REDIS_STREAM_SHARDS",
+            "REDIS_UNSUBSCRIBE_DELAY",
+            "REDIS_REPLAY_BUFFER",
+            "RECORD_REDIS",
+            "REPLAY_REDIS",
+            "REPLAY_SPEED",
+            "SLOW_CONSUMER_POLICY",
+            "SLOW_CONSUMER_QUEUE",
+            "SLOW_CONSUMER_MAX_DROPPED",
         ] {
             if let Some(value) = self.get(&(*env_var).to_string()) {
-                result = format!("{}\n    {}: {}", result, env_var, value)
+                result = format!("{}\n    {}: {}", result, env_var, value);
             }
         }
         write!(f, "{}", result)
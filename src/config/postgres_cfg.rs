@@ -15,7 +15,19 @@ pub struct Postgres {
     /// The name of the postgres database to connect to
     pub database: PgDatabase,
     pub(crate) port: PgPort,
+    pub(crate) pool_size: PgPoolSize,
+    pub(crate) pool_min_idle: PgPoolMinIdle,
+    pub(crate) connection_timeout: PgConnectionTimeout,
+    pub(crate) idle_timeout: PgIdleTimeout,
+    pub(crate) statement_timeout: PgStatementTimeout,
+    pub(crate) cache_ttl: PgCacheTtl,
+    pub(crate) cache_size: PgCacheSize,
+    pub(crate) cache_channel: PgCacheChannel,
     pub(crate) ssl_mode: PgSslMode,
+    pub(crate) ssl_root_cert: PgSslRootCert,
+    pub(crate) ssl_cert: PgSslCert,
+    pub(crate) ssl_key: PgSslKey,
+    pub(crate) notify_channels: PgNotifyChannels,
 }
 
 impl EnvVar {
@@ -24,15 +36,19 @@ impl EnvVar {
         let none_if_empty = |s: String| if s.is_empty() { None } else { Some(s) };
 
         for (k, v) in url.query_pairs().into_owned() {
-            match k.to_string().as_str() {
-                "user" => self.maybe_add_env_var("DB_USER", Some(v.to_string())),
-                "password" => self.maybe_add_env_var("DB_PASS", Some(v.to_string())),
-                "host" => self.maybe_add_env_var("DB_HOST", Some(v.to_string())),
-                "sslmode" => self.maybe_add_env_var("DB_SSLMODE", Some(v.to_string())),
+            match k.as_str() {
+                "user" => self.maybe_add_env_var("DB_USER", Some(v.clone())),
+                "password" => self.maybe_add_env_var("DB_PASS", Some(v.clone())),
+                "host" => self.maybe_add_env_var("DB_HOST", Some(v.clone())),
+                "sslmode" => self.maybe_add_env_var("DB_SSLMODE", Some(v.clone())),
+                "sslrootcert" => self.maybe_add_env_var("DB_SSLROOTCERT", Some(v.clone())),
+                "sslcert" => self.maybe_add_env_var("DB_SSLCERT", Some(v.clone())),
+                "sslkey" => self.maybe_add_env_var("DB_SSLKEY", Some(v.clone())),
                 _ => Err(Error::config(
                     "POSTGRES_URL",
                     &k,
-                    "a URL with parameters `password`, `user`, `host`, and `sslmode` only",
+                    "a URL with parameters `password`, `user`, `host`, `sslmode`, `sslrootcert`, \
+                     `sslcert`, and `sslkey` only",
                 ))?,
             }
         }
@@ -65,15 +81,31 @@ impl Postgres {
             password: PgPass::default().maybe_update(env.get("DB_PASS"))?,
             database: PgDatabase::default().maybe_update(env.get("DB_NAME"))?,
             port: PgPort::default().maybe_update(env.get("DB_PORT"))?,
+            pool_size: PgPoolSize::default().maybe_update(env.get("DB_POOL"))?,
+            pool_min_idle: PgPoolMinIdle::default().maybe_update(env.get("DB_POOL_MIN_IDLE"))?,
+            connection_timeout: PgConnectionTimeout::default()
+                .maybe_update(env.get("DB_CONNECTION_TIMEOUT"))?,
+            idle_timeout: PgIdleTimeout::default().maybe_update(env.get("DB_IDLE_TIMEOUT"))?,
+            statement_timeout: PgStatementTimeout::default()
+                .maybe_update(env.get("DB_STATEMENT_TIMEOUT"))?,
+            cache_ttl: PgCacheTtl::default().maybe_update(env.get("DB_CACHE_TTL"))?,
+            cache_size: PgCacheSize::default().maybe_update(env.get("DB_CACHE_SIZE"))?,
+            cache_channel: PgCacheChannel::default().maybe_update(env.get("DB_CACHE_CHANNEL"))?,
             ssl_mode: PgSslMode::default().maybe_update(env.get("DB_SSLMODE"))?,
+            ssl_root_cert: PgSslRootCert::default().maybe_update(env.get("DB_SSLROOTCERT"))?,
+            ssl_cert: PgSslCert::default().maybe_update(env.get("DB_SSLCERT"))?,
+            ssl_key: PgSslKey::default().maybe_update(env.get("DB_SSLKEY"))?,
+            notify_channels: PgNotifyChannels::default()
+                .maybe_update(env.get("DB_NOTIFY_CHANNELS"))?,
         };
-        Ok(cfg)
-    }
 
-    //     // use openssl::ssl::{SslConnector, SslMethod};
-    //     // use postgres_openssl::MakeTlsConnector;
-    //     // let mut builder = SslConnector::builder(SslMethod::tls())?;
-    //     // builder.set_ca_file("/etc/ssl/cert.pem")?;
-    //     // let connector = MakeTlsConnector::new(builder.build());
-    //     // TODO: add TLS support, remove `NoTls`
+        match *cfg.pool_min_idle {
+            Some(min_idle) if min_idle > *cfg.pool_size => Err(Error::config(
+                "DB_POOL_MIN_IDLE".to_string(),
+                min_idle.to_string(),
+                format!("no greater than DB_POOL ({})", *cfg.pool_size),
+            ))?,
+            _ => Ok(cfg),
+        }
+    }
 }
@@ -1,5 +1,6 @@
 use crate::from_env_var;
 use std::str::FromStr;
+use std::time::Duration;
 use strum_macros::{EnumString, EnumVariantNames};
 
 from_env_var!(
@@ -43,14 +44,126 @@ from_env_var!(
 );
 
 from_env_var!(
+    /// The channels to `LISTEN` on for events (with `EVENT_SOURCE=postgres`).  Each
+    /// notification's payload is a timeline and an event separated by a space, such as
+    /// `timeline:public {"event":"delete","payload":"1038647"}`.  Postgres limits payloads to
+    /// less than 8000 bytes, so larger events can't be sent.
+    let name = PgNotifyChannels;
+    let default: Vec<String> = vec!["flodgatt".to_string()];
+    let (env_var, allowed_values) = ("DB_NOTIFY_CHANNELS", "a comma-separated list of channel names");
+    let from_str = |s| Some(s.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect());
+);
+
+from_env_var!(
+    /// The most connections to Postgres to keep open (and the number of threads that wait on
+    /// them)
+    let name = PgPoolSize;
+    let default: u32 = 10;
+    let (env_var, allowed_values) = ("DB_POOL", "a number greater than 0");
+    let from_str = |s| s.parse::<u32>().ok().filter(|size| *size > 0);
+);
+
+from_env_var!(
+    /// How many idle connections to Postgres to keep open (by default, all of them)
+    let name = PgPoolMinIdle;
+    let default: Option<u32> = None;
+    let (env_var, allowed_values) = ("DB_POOL_MIN_IDLE", "a number no greater than DB_POOL");
+    let from_str = |s| s.parse().ok().map(Some);
+);
+
+from_env_var!(
+    /// How long a request waits for a connection to Postgres before it is rejected with a 503
+    let name = PgConnectionTimeout;
+    let default: Duration = Duration::from_secs(5);
+    let (env_var, allowed_values) = ("DB_CONNECTION_TIMEOUT", "a number of seconds greater than 0");
+    let from_str = |s| s.parse::<u64>().ok().filter(|secs| *secs > 0).map(Duration::from_secs);
+);
+
+from_env_var!(
+    /// How long a connection to Postgres can be idle before it is closed (0 keeps idle
+    /// connections open)
+    let name = PgIdleTimeout;
+    let default: Option<Duration> = Some(Duration::from_secs(600));
+    let (env_var, allowed_values) = ("DB_IDLE_TIMEOUT", "a number of seconds");
+    let from_str = |s| s.parse::<u64>().ok().map(|secs| Some(Duration::from_secs(secs)).filter(|_| secs > 0));
+);
+
+from_env_var!(
+    /// How long Postgres can take to run a query before canceling it (0 lets queries run
+    /// indefinitely)
+    let name = PgStatementTimeout;
+    let default: Option<Duration> = None;
+    let (env_var, allowed_values) = ("DB_STATEMENT_TIMEOUT", "a number of milliseconds");
+    let from_str = |s| s.parse::<u64>().ok().map(|ms| Some(Duration::from_millis(ms)).filter(|_| ms > 0));
+);
+
+from_env_var!(
+    /// How long to cache the users and blocks looked up for each client (0 disables the cache).
+    /// Mastodon doesn't notify us of changes to cached users and blocks, so the cache is only
+    /// enabled if the triggers in `sql/cache_invalidation.sql` are installed.
+    let name = PgCacheTtl;
+    let default: Option<Duration> = None;
+    let (env_var, allowed_values) = ("DB_CACHE_TTL", "a number of seconds");
+    let from_str = |s| s.parse::<u64>().ok().map(|secs| Some(Duration::from_secs(secs)).filter(|_| secs > 0));
+);
+
+from_env_var!(
+    /// How many users (and how many users' blocks) to cache
+    let name = PgCacheSize;
+    let default: usize = 10_000;
+    let (env_var, allowed_values) = ("DB_CACHE_SIZE", "a number greater than 0");
+    let from_str = |s| s.parse::<usize>().ok().filter(|size| *size > 0);
+);
+
+from_env_var!(
+    /// The channel to `LISTEN` on for changes that invalidate cached users and blocks.  Each
+    /// notification's payload is `token:<access token>` or `account:<account id>`.
+    let name = PgCacheChannel;
+    let default: String = "flodgatt_cache".to_string();
+    let (env_var, allowed_values) = ("DB_CACHE_CHANNEL", "any string");
+    let from_str = |s| Some(s.to_string());
+);
+
+from_env_var!(
+    /// Whether to connect to Postgres over TLS and how to verify its certificate (as with
+    /// libpq's `sslmode`; `require` also verifies the certificate if `DB_SSLROOTCERT` is set)
     let name = PgSslMode;
     let default: PgSslInner = PgSslInner::Prefer;
     let (env_var, allowed_values) = ("DB_SSLMODE", &format!("one of: {:?}", PgSslInner::variants()));
     let from_str = |s| PgSslInner::from_str(s).ok();
 );
 
-#[derive(EnumString, EnumVariantNames, Debug, Clone)]
-#[strum(serialize_all = "snake_case")]
+from_env_var!(
+    /// A PEM file of CA certificates to trust for Postgres TLS connections (in addition to the
+    /// system's default certificates)
+    let name = PgSslRootCert;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("DB_SSLROOTCERT", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+
+from_env_var!(
+    /// A PEM client certificate to present to Postgres (requires `DB_SSLKEY`)
+    let name = PgSslCert;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("DB_SSLCERT", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+
+from_env_var!(
+    /// The PEM private key for `DB_SSLCERT`
+    let name = PgSslKey;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("DB_SSLKEY", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "kebab_case")]
 pub enum PgSslInner {
+    Disable,
     Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
 }
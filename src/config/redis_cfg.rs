@@ -11,12 +11,27 @@ pub struct Redis {
     pub(crate) password: RedisPass,
     pub(crate) port: RedisPort,
     pub(crate) host: RedisHost,
+    pub(crate) socket: RedisSocket,
     pub(crate) db: RedisDb,
     pub(crate) namespace: RedisNamespace,
-    // **NOTE**:  Polling Redis is much more time consuming than polling the `Receiver` (~1ms
-    // compared to ~50μs).  Thus, changing this setting with REDIS_POLL_INTERVAL may be a good
-    // place to start for performance improvements at the cost of delaying all updates.
-    pub polling_interval: RedisInterval,
+    pub(crate) tls: RedisTls,
+    pub(crate) tls_ca_file: RedisTlsCaFile,
+    pub(crate) tls_cert_file: RedisTlsCertFile,
+    pub(crate) tls_key_file: RedisTlsKeyFile,
+    pub(crate) tls_insecure: RedisTlsInsecure,
+    pub(crate) sentinels: RedisSentinels,
+    pub(crate) sentinel_master: RedisSentinelMaster,
+    pub(crate) psubscribe: RedisPsubscribe,
+    pub(crate) streams: RedisStreams,
+    pub(crate) stream_shards: RedisStreamShards,
+    pub(crate) unsubscribe_delay: RedisUnsubscribeDelay,
+    pub(crate) replay_buffer: RedisReplayBuffer,
+    pub(crate) record: RecordRedis,
+    pub(crate) replay: ReplayRedis,
+    pub(crate) replay_speed: ReplaySpeed,
+    pub(crate) slow_consumer_policy: SlowConsumerPolicy,
+    pub(crate) slow_consumer_queue: SlowConsumerQueue,
+    pub(crate) slow_consumer_max_dropped: SlowConsumerMaxDropped,
 }
 
 impl EnvVar {
@@ -24,14 +39,28 @@ impl EnvVar {
         let url = Url::parse(url_str)?;
         let none_if_empty = |s: String| if s.is_empty() { None } else { Some(s) };
 
-        self.maybe_add_env_var("REDIS_PORT", url.port());
+        match url.scheme() {
+            "redis" | "rediss" => {
+                if url.scheme() == "rediss" {
+                    self.maybe_add_env_var("REDIS_TLS", Some("true"));
+                }
+                self.maybe_add_env_var("REDIS_HOST", url.host_str());
+                self.maybe_add_env_var("REDIS_PORT", url.port());
+                self.maybe_add_env_var("REDIS_DB", none_if_empty(url.path()[1..].to_string()));
+            }
+            "unix" => self.maybe_add_env_var("REDIS_SOCKET", Some(url.path())),
+            _ => Err(Error::config(
+                "REDIS_URL",
+                url_str,
+                "a `redis://`, `rediss://`, or `unix://` URL",
+            ))?,
+        }
         self.maybe_add_env_var("REDIS_PASSWORD", url.password());
-        self.maybe_add_env_var("REDIS_USERNAME", none_if_empty(url.username().to_string()));
-        self.maybe_add_env_var("REDIS_DB", none_if_empty(url.path()[1..].to_string()));
+        self.maybe_add_env_var("REDIS_USER", none_if_empty(url.username().to_string()));
         for (k, v) in url.query_pairs().into_owned() {
-            match k.to_string().as_str() {
-                "password" => self.maybe_add_env_var("REDIS_PASSWORD", Some(v.to_string())),
-                "db" => self.maybe_add_env_var("REDIS_DB", Some(v.to_string())),
+            match k.as_str() {
+                "password" => self.maybe_add_env_var("REDIS_PASSWORD", Some(v.clone())),
+                "db" => self.maybe_add_env_var("REDIS_DB", Some(v.clone())),
                 _ => Err(Error::config(
                     "REDIS_URL",
                     &k,
@@ -45,9 +74,20 @@ impl EnvVar {
 
 impl Redis {
     const USER_SET_WARNING: &'static str =
-        "Redis user specified, but Redis did not ask for a username.  Ignoring it.";
+        "Redis user specified without a Redis password.  Ignoring it.";
     const DB_SET_WARNING: &'static str = r"Redis database specified, but PubSub connections do not use databases.
 For similar functionality, you may wish to set a REDIS_NAMESPACE";
+    const SENTINELS_SET_WARNING: &'static str =
+        "Redis Sentinels specified; ignoring REDIS_HOST and REDIS_PORT in favor of the master \
+         address reported by the Sentinels.";
+    const SOCKET_SET_WARNING: &'static str =
+        "Redis socket specified; ignoring REDIS_HOST and REDIS_PORT and connecting to Redis \
+         over the Unix socket.";
+    const STREAMS_SET_WARNING: &'static str =
+        "REDIS_STREAMS and REDIS_PSUBSCRIBE specified; reading events from Redis Streams and \
+         ignoring REDIS_PSUBSCRIBE.";
+    const FREQ_SET_WARNING: &'static str =
+        "REDIS_FREQ specified, but Redis is no longer polled on a timer.  Ignoring it.";
 
     pub(crate) fn from_env(env: EnvVar) -> Result<Self> {
         let env = match env.get("REDIS_URL").cloned() {
@@ -60,17 +100,61 @@ For similar functionality, you may wish to set a REDIS_NAMESPACE";
             password: RedisPass::default().maybe_update(env.get("REDIS_PASSWORD"))?,
             port: RedisPort::default().maybe_update(env.get("REDIS_PORT"))?,
             host: RedisHost::default().maybe_update(env.get("REDIS_HOST"))?,
+            socket: RedisSocket::default().maybe_update(env.get("REDIS_SOCKET"))?,
             db: RedisDb::default().maybe_update(env.get("REDIS_DB"))?,
             namespace: RedisNamespace::default().maybe_update(env.get("REDIS_NAMESPACE"))?,
-            polling_interval: RedisInterval::default().maybe_update(env.get("REDIS_FREQ"))?,
+            tls: RedisTls::default().maybe_update(env.get("REDIS_TLS"))?,
+            tls_ca_file: RedisTlsCaFile::default().maybe_update(env.get("REDIS_TLS_CA_FILE"))?,
+            tls_cert_file: RedisTlsCertFile::default()
+                .maybe_update(env.get("REDIS_TLS_CERT_FILE"))?,
+            tls_key_file: RedisTlsKeyFile::default().maybe_update(env.get("REDIS_TLS_KEY_FILE"))?,
+            tls_insecure: RedisTlsInsecure::default()
+                .maybe_update(env.get("REDIS_TLS_INSECURE"))?,
+            sentinels: RedisSentinels::default().maybe_update(env.get("REDIS_SENTINELS"))?,
+            sentinel_master: RedisSentinelMaster::default()
+                .maybe_update(env.get("REDIS_SENTINEL_MASTER"))?,
+            psubscribe: RedisPsubscribe::default().maybe_update(env.get("REDIS_PSUBSCRIBE"))?,
+            streams: RedisStreams::default().maybe_update(env.get("REDIS_STREAMS"))?,
+            stream_shards: RedisStreamShards::default()
+                .maybe_update(env.get("REDIS_STREAM_SHARDS"))?,
+            unsubscribe_delay: RedisUnsubscribeDelay::default()
+                .maybe_update(env.get("REDIS_UNSUBSCRIBE_DELAY"))?,
+            replay_buffer: RedisReplayBuffer::default()
+                .maybe_update(env.get("REDIS_REPLAY_BUFFER"))?,
+            record: RecordRedis::default().maybe_update(env.get("RECORD_REDIS"))?,
+            replay: ReplayRedis::default().maybe_update(env.get("REPLAY_REDIS"))?,
+            replay_speed: ReplaySpeed::default().maybe_update(env.get("REPLAY_SPEED"))?,
+            slow_consumer_policy: SlowConsumerPolicy::default()
+                .maybe_update(env.get("SLOW_CONSUMER_POLICY"))?,
+            slow_consumer_queue: SlowConsumerQueue::default()
+                .maybe_update(env.get("SLOW_CONSUMER_QUEUE"))?,
+            slow_consumer_max_dropped: SlowConsumerMaxDropped::default()
+                .maybe_update(env.get("SLOW_CONSUMER_MAX_DROPPED"))?,
         };
 
         if cfg.db.is_some() {
             log::warn!("{}", Self::DB_SET_WARNING);
         }
-        if cfg.user.is_some() {
+        if cfg.user.is_some() && cfg.password.is_none() {
             log::warn!("{}", Self::USER_SET_WARNING);
         }
+        if !cfg.sentinels.is_empty()
+            && (env.get("REDIS_HOST").is_some() || env.get("REDIS_PORT").is_some())
+        {
+            log::warn!("{}", Self::SENTINELS_SET_WARNING);
+        }
+        if cfg.socket.is_some()
+            && cfg.sentinels.is_empty()
+            && (env.get("REDIS_HOST").is_some() || env.get("REDIS_PORT").is_some())
+        {
+            log::warn!("{}", Self::SOCKET_SET_WARNING);
+        }
+        if *cfg.streams && *cfg.psubscribe {
+            log::warn!("{}", Self::STREAMS_SET_WARNING);
+        }
+        if env.get("REDIS_FREQ").is_some() {
+            log::warn!("{}", Self::FREQ_SET_WARNING);
+        }
         Ok(cfg)
     }
 }
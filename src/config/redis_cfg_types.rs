@@ -1,7 +1,7 @@
 use crate::from_env_var; //macro
+use std::str::FromStr;
 use std::time::Duration;
-//use std::{fmt, net::IpAddr, os::unix::net::UnixListener, str::FromStr, time::Duration};
-//use strum_macros::{EnumString, EnumVariantNames};
+use strum_macros::{EnumString, EnumVariantNames};
 
 from_env_var!(
     /// The host address where Redis is running
@@ -18,11 +18,11 @@ from_env_var!(
     let from_str = |s| s.parse().ok();
 );
 from_env_var!(
-    /// How frequently to poll Redis
-    let name = RedisInterval;
-    let default: Duration = Duration::from_millis(100);
-    let (env_var, allowed_values) = ("REDIS_FREQ", "a number of milliseconds");
-    let from_str = |s| s.parse().map(Duration::from_millis).ok();
+    /// A Unix socket to connect to Redis through (instead of `REDIS_HOST` and `REDIS_PORT`)
+    let name = RedisSocket;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_SOCKET", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
 );
 from_env_var!(
     /// The password to use for Redis
@@ -39,7 +39,7 @@ from_env_var!(
     let from_str = |s| Some(Some(s.to_string()));
 );
 from_env_var!(
-    /// A user for Redis (not supported)
+    /// A user for Redis 6 ACL authentication (requires `REDIS_PASSWORD`)
     let name = RedisUser;
     let default: Option<String> = None;
     let (env_var, allowed_values) = ("REDIS_USER", "any string");
@@ -52,3 +52,149 @@ from_env_var!(
     let (env_var, allowed_values) = ("REDIS_DB", "any string");
     let from_str = |s| Some(Some(s.to_string()));
 );
+from_env_var!(
+    /// Whether to connect to Redis over TLS (also set by a `rediss://` `REDIS_URL`)
+    let name = RedisTls;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("REDIS_TLS", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// A PEM file of CA certificates to trust for Redis TLS connections (in addition to the
+    /// system's default certificates)
+    let name = RedisTlsCaFile;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_TLS_CA_FILE", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// A PEM client certificate to present to Redis (requires `REDIS_TLS_KEY_FILE`)
+    let name = RedisTlsCertFile;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_TLS_CERT_FILE", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// The PEM private key for `REDIS_TLS_CERT_FILE`
+    let name = RedisTlsKeyFile;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REDIS_TLS_KEY_FILE", "a file path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// Skip verifying Redis's TLS certificate (insecure; for testing only)
+    let name = RedisTlsInsecure;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("REDIS_TLS_INSECURE", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// Redis Sentinels to ask for the address of the current Redis master (if any are set,
+    /// `REDIS_HOST` and `REDIS_PORT` are ignored)
+    let name = RedisSentinels;
+    let default: Vec<String> = Vec::new();
+    let (env_var, allowed_values) = ("REDIS_SENTINELS", "a comma-separated list of host:port addresses");
+    let from_str = |s| Some(s.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect());
+);
+from_env_var!(
+    /// The name of the master that the Redis Sentinels monitor
+    let name = RedisSentinelMaster;
+    let default: String = "mymaster".to_string();
+    let (env_var, allowed_values) = ("REDIS_SENTINEL_MASTER", "any string");
+    let from_str = |s| Some(s.to_string());
+);
+from_env_var!(
+    /// Whether to receive every timeline through a single `PSUBSCRIBE` (and discard events for
+    /// timelines without clients) instead of sending a `SUBSCRIBE` for each timeline
+    let name = RedisPsubscribe;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("REDIS_PSUBSCRIBE", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// Whether to read events from Redis Streams (with `XREAD`) instead of pub/sub, so that
+    /// events published while we are reconnecting to Redis aren't lost.  Each entry's `event`
+    /// field holds the same JSON that would otherwise be published.
+    let name = RedisStreams;
+    let default: bool = false;
+    let (env_var, allowed_values) = ("REDIS_STREAMS", "true or false");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// With `REDIS_STREAMS`, the number of streams (`timeline:shard:0`, `timeline:shard:1`,
+    /// ...) that all timelines share, with each entry's `timeline` field naming its timeline.
+    /// With 0, each timeline has its own stream, named like its pub/sub channel.
+    let name = RedisStreamShards;
+    let default: usize = 0;
+    let (env_var, allowed_values) = ("REDIS_STREAM_SHARDS", "a number of streams");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// How long to stay subscribed to a timeline after its last client disconnects, so that
+    /// clients that quickly reconnect don't cause Redis to unsubscribe and resubscribe
+    let name = RedisUnsubscribeDelay;
+    let default: Duration = Duration::from_secs(60);
+    let (env_var, allowed_values) = ("REDIS_UNSUBSCRIBE_DELAY", "a number of seconds");
+    let from_str = |s| s.parse().map(Duration::from_secs).ok();
+);
+from_env_var!(
+    /// How many recent events to keep for each timeline so that SSE clients reconnecting
+    /// with a `Last-Event-ID` can be sent the events they missed (0 disables replay)
+    let name = RedisReplayBuffer;
+    let default: usize = 50;
+    let (env_var, allowed_values) = ("REDIS_REPLAY_BUFFER", "a number of events");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// A file to record everything read from Redis to (for replaying with
+    /// `EVENT_SOURCE=replay`).  The file holds the raw RESP input, like the `.resp` files in
+    /// `test_data`; when each read arrived is recorded in `<path>.timestamps`.
+    let name = RecordRedis;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("RECORD_REDIS", "any filesystem path");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// The recording (made with `RECORD_REDIS`) to read events from with `EVENT_SOURCE=replay`
+    let name = ReplayRedis;
+    let default: Option<String> = None;
+    let (env_var, allowed_values) = ("REPLAY_REDIS", "the path to a recording");
+    let from_str = |s| Some(Some(s.to_string()));
+);
+from_env_var!(
+    /// How many times faster than it was recorded to replay a recording (e.g., 10 to replay
+    /// an hour of events in six minutes)
+    let name = ReplaySpeed;
+    let default: f64 = 1.0;
+    let (env_var, allowed_values) = ("REPLAY_SPEED", "a positive number");
+    let from_str = |s| s.parse().ok().filter(|speed: &f64| *speed > 0.0);
+);
+from_env_var!(
+    /// What to do with a client that can't keep up with the events sent to it
+    let name = SlowConsumerPolicy;
+    let default: SlowConsumerInner = SlowConsumerInner::DropOldest;
+    let (env_var, allowed_values) = ("SLOW_CONSUMER_POLICY", &format!("one of: {:?}", SlowConsumerInner::variants()));
+    let from_str = |s| SlowConsumerInner::from_str(s).ok();
+);
+from_env_var!(
+    /// How many events to queue for a slow client before applying the `SLOW_CONSUMER_POLICY`
+    let name = SlowConsumerQueue;
+    let default: usize = 100;
+    let (env_var, allowed_values) = ("SLOW_CONSUMER_QUEUE", "a number of events");
+    let from_str = |s| s.parse().ok();
+);
+from_env_var!(
+    /// How many events to drop before disconnecting a slow client (with the `disconnect` policy)
+    let name = SlowConsumerMaxDropped;
+    let default: usize = 100;
+    let (env_var, allowed_values) = ("SLOW_CONSUMER_MAX_DROPPED", "a number of events");
+    let from_str = |s| s.parse().ok();
+);
+
+#[derive(EnumString, EnumVariantNames, Debug, Clone, Copy)]
+#[strum(serialize_all = "snake_case")]
+pub enum SlowConsumerInner {
+    DropOldest,
+    DropNewest,
+    Disconnect,
+}
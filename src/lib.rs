@@ -3,22 +3,22 @@
 //!
 //! This server provides live, streaming updates for Mastodon clients.  Specifically, when a
 //! server is running this sever, Mastodon clients can use either Server Sent Events or
-//! WebSockets to connect to the server with the API described [in Mastodon's public API
+//! `WebSockets` to connect to the server with the API described [in Mastodon's public API
 //! documentation](https://docs.joinmastodon.org/api/streaming/).
 //!
 //! # Data Flow
 //! * **Parsing the client request** When the client request first comes in, it is
-//! parsed based on the endpoint it targets (for server sent events), its query parameters,
-//! and its headers (for WebSocket).  Based on this data, we authenticate the user, retrieve
-//! relevant user data from Postgres, and determine the timeline targeted by the request.
-//! Successfully parsing the client request results in generating a `User` corresponding to
-//! the request.  If any requests are invalid/not authorized, we reject them in this stage.
+//!   parsed based on the endpoint it targets (for server sent events), its query parameters,
+//!   and its headers (for WebSocket).  Based on this data, we authenticate the user, retrieve
+//!   relevant user data from Postgres, and determine the timeline targeted by the request.
+//!   Successfully parsing the client request results in generating a `User` corresponding to
+//!   the request.  If any requests are invalid/not authorized, we reject them in this stage.
 //! * **Streaming update from Redis to the client**: After the user request is parsed, we pass
-//! the `User` data on to the `ClientAgent`.  The `ClientAgent` is responsible for
-//! communicating the user's request to the `Receiver`, polling the `Receiver` for any
-//! updates, and then for wording those updates on to the client.  The `Receiver`, in tern, is
-//! responsible for managing the Redis subscriptions, periodically polling Redis, and sorting
-//! the replies from Redis into queues for when it is polled by the `ClientAgent`.
+//!   the `User` data on to the `ClientAgent`.  The `ClientAgent` is responsible for
+//!   communicating the user's request to the `Receiver`, polling the `Receiver` for any
+//!   updates, and then for wording those updates on to the client.  The `Receiver`, in tern, is
+//!   responsible for managing the Redis subscriptions, reading from Redis whenever the tokio
+//!   reactor reports that Redis has sent data, and sorting the replies from Redis into queues for when it is polled by the `ClientAgent`.
 //!
 //! # Concurrency
 //! The `Receiver` is created when the server is first initialized, and there is only one
@@ -31,13 +31,22 @@
 //!
 //! # Configuration By default, the server uses config values from the `config.rs` module;
 //! these values can be overwritten with environmental variables or in the `.env` file.  The
-//! most important settings for performance control the frequency with which the `ClientAgent`
-//! polls the `Receiver` and the frequency with which the `Receiver` polls Redis.
+//! most important settings for performance control how many events are queued for slow
+//! clients and how many are kept for clients that reconnect.
 //!
 
 #![warn(clippy::pedantic)]
 #![allow(clippy::try_err, clippy::match_bool)]
 #![allow(clippy::large_enum_variant)]
+#![allow(clippy::uninlined_format_args, clippy::enum_glob_use, clippy::wildcard_imports)]
+#![allow(clippy::must_use_candidate, clippy::missing_errors_doc, clippy::missing_panics_doc)]
+#![allow(clippy::unnecessary_wraps)]
+#![allow(clippy::needless_pub_self, clippy::module_inception, clippy::duration_suboptimal_units)]
+#![allow(clippy::manual_let_else, clippy::single_match_else, clippy::format_push_string)]
+#![allow(clippy::ref_option, clippy::needless_continue, clippy::match_same_arms)]
+#![allow(clippy::items_after_statements)]
+#![cfg_attr(test, allow(clippy::unit_arg))]
+#![cfg_attr(test, allow(clippy::unreadable_literal, clippy::useless_vec, clippy::manual_string_new))] // for `test_data`
 
 pub use err::Error;
 
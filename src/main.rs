@@ -1,17 +1,20 @@
-use flodgatt::config;
+use flodgatt::config::{self, EventSourceInner};
 use flodgatt::request::{Handler, Subscription};
-use flodgatt::response::{RedisManager, SseStream, WsStream};
+use flodgatt::response::{PostgresSource, RedisManager, ReplaySource, SseStream, WsStream};
 use flodgatt::Error;
 
-use futures::future::lazy;
-use futures::stream::Stream as _;
+use futures::future::{self, lazy, Future};
+use futures::stream::Stream;
 use std::fs;
 use std::net::SocketAddr;
 use std::os::unix::fs::PermissionsExt;
-use std::time::Instant;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::net::UnixListener;
+use tokio::prelude::FutureExt;
 use tokio::sync::mpsc;
-use tokio::timer::Interval;
+use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
 use warp::ws::Ws2;
 use warp::Filter;
 
@@ -19,43 +22,52 @@ fn main() -> Result<(), Error> {
     config::merge_dotenv()?;
     pretty_env_logger::try_init_timed()?;
     let (postgres_cfg, redis_cfg, cfg) = config::from_env(dotenv::vars().collect())?;
-    let poll_freq = *redis_cfg.polling_interval;
 
+    let shared_manager = match *cfg.event_source {
+        EventSourceInner::Redis => RedisManager::try_from(&redis_cfg)?,
+        EventSourceInner::Postgres => {
+            RedisManager::with_source(PostgresSource::new(&postgres_cfg)?, &redis_cfg)
+        }
+        EventSourceInner::Replay => {
+            RedisManager::with_source(ReplaySource::new(&redis_cfg)?, &redis_cfg)
+        }
+    }
+    .into_arc();
     let request = Handler::new(&postgres_cfg, *cfg.whitelist_mode)?;
-    let shared_manager = RedisManager::try_from(&redis_cfg)?.into_arc();
 
     // Server Sent Events
     let sse_manager = shared_manager.clone();
     let sse = request
         .sse_subscription()
         .and(warp::sse())
-        .map(move |subscription: Subscription, sse: warp::sse::Sse| {
-            log::info!("Incoming SSE request for {:?}", subscription.timeline);
-            let mut manager = sse_manager.lock().unwrap_or_else(RedisManager::recover);
-            let (event_tx, event_rx) = mpsc::channel(10);
-            manager.subscribe(&subscription, event_tx);
-            let sse_stream = SseStream::new(subscription);
-            sse_stream.send_events(sse, event_rx)
-        })
+        .and(warp::header::optional::<u64>("last-event-id"))
+        .map(
+            move |subscription: Subscription, sse: warp::sse::Sse, last_id: Option<u64>| {
+                log::info!("Incoming SSE request for {:?}", subscription.timeline);
+                let mut manager = sse_manager.lock().unwrap_or_else(RedisManager::recover);
+                let (event_tx, event_rx) = mpsc::channel(10);
+                let client_id = manager.add_client(event_tx);
+                manager.subscribe(client_id, &subscription);
+                let missed =
+                    last_id.map_or_else(Vec::new, |id| manager.replay(subscription.timeline, id));
+                let sse_stream = SseStream::new(subscription);
+                sse_stream.send_events(sse, event_rx, missed)
+            },
+        )
         .with(warp::reply::with::header("Connection", "keep-alive"));
 
     // WebSocket
     let ws_manager = shared_manager.clone();
+    let ws_handler = request.clone();
     let ws = request
         .ws_subscription()
         .and(warp::ws::ws2())
         .map(move |subscription: Subscription, ws: Ws2| {
             log::info!("Incoming websocket request for {:?}", subscription.timeline);
-            let mut manager = ws_manager.lock().unwrap_or_else(RedisManager::recover);
-            let (event_tx, event_rx) = mpsc::channel(10);
-            manager.subscribe(&subscription, event_tx);
             let token = subscription.access_token.clone().unwrap_or_default(); // token sent for security
-            let ws_stream = WsStream::new(subscription);
+            let ws_stream = WsStream::new(subscription, ws_manager.clone(), ws_handler.clone());
 
-            (
-                ws.on_upgrade(move |ws| ws_stream.send_to(ws, event_rx)),
-                token,
-            )
+            (ws.on_upgrade(move |ws| ws_stream.send_to(ws)), token)
         })
         .map(|(reply, token)| warp::reply::with_header(reply, "sec-websocket-protocol", token));
 
@@ -63,6 +75,7 @@ fn main() -> Result<(), Error> {
     #[rustfmt::skip]
     let status = {
         let (r1, r2, r3) = (shared_manager.clone(), shared_manager.clone(), shared_manager.clone());
+        let pg = request.clone();
         request.health().map(|| "OK")
             .or(request.status()
                 .map(move || r1.lock().unwrap_or_else(RedisManager::recover).count()))
@@ -70,6 +83,7 @@ fn main() -> Result<(), Error> {
                 .map(move || r2.lock().unwrap_or_else(RedisManager::recover).backpresure()))
             .or(request.status_per_timeline()
                 .map(move || r3.lock().unwrap_or_else(RedisManager::recover).list()))
+            .or(request.status_cache().map(move || pg.cache_status()))
     };
     #[cfg(not(feature = "stub_status"))]
     let status = request.health().map(|| "OK");
@@ -80,19 +94,19 @@ fn main() -> Result<(), Error> {
         .allow_headers(cfg.cors.allowed_headers);
 
     let streaming_server = move || {
+        warp::spawn(unsubscribe_on_shutdown(shared_manager.clone()));
         let manager = shared_manager.clone();
-        let stream = Interval::new(Instant::now(), poll_freq)
-            .map_err(|e| log::error!("{}", e))
-            .for_each(move |_| {
-                match manager
-                    .lock()
-                    .unwrap_or_else(RedisManager::recover)
-                    .send_msgs()
-                {
-                    Err(e) => Ok(log::error!("{}", e)),
-                    Ok(_) => Ok(()),
-                }
-            });
+        // Woken by the reactor whenever Redis sends data (and by the timer for pings)
+        let stream = future::poll_fn(move || loop {
+            match manager
+                .lock()
+                .unwrap_or_else(RedisManager::recover)
+                .send_msgs()
+            {
+                Err(e) => log::error!("{}", e),
+                Ok(ready) => return Ok::<_, ()>(ready),
+            }
+        });
 
         warp::spawn(lazy(move || stream));
         warp::serve(ws.or(sse).with(cors).or(status).recover(Handler::err))
@@ -110,3 +124,41 @@ fn main() -> Result<(), Error> {
     }
     Err(Error::Unrecoverable) // only reached if poll_broadcast encounters an unrecoverable error
 }
+
+/// How long to wait for Redis to accept our `UNSUBSCRIBE` and `DEL` commands when shutting down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unsubscribe from Redis (removing our `subscribed:` keys) and exit on SIGINT or SIGTERM.
+///
+/// This must run on the runtime: the signals are handled for the whole process (whichever
+/// thread receives them), and the `DEL`s for the keys are sent by polling the `Manager`.
+fn unsubscribe_on_shutdown(
+    manager: Arc<Mutex<RedisManager>>,
+) -> impl Future<Item = (), Error = ()> {
+    let signals = Signal::new(SIGINT)
+        .flatten_stream()
+        .select(Signal::new(SIGTERM).flatten_stream());
+    signals
+        .into_future()
+        .map_err(|(e, _)| log::error!("Could not listen for shutdown signals: {}", e))
+        .and_then(move |(signal, _)| {
+            log::info!("Received signal {:?}; shutting down", signal);
+            manager
+                .lock()
+                .unwrap_or_else(RedisManager::recover)
+                .unsubscribe_all();
+            future::poll_fn(move || {
+                manager
+                    .lock()
+                    .unwrap_or_else(RedisManager::recover)
+                    .poll_flush()
+            })
+            .timeout(SHUTDOWN_TIMEOUT)
+            .then(|flushed| -> Result<(), ()> {
+                if let Err(e) = flushed {
+                    log::error!("Could not unsubscribe from Redis before exiting: {}", e);
+                }
+                process::exit(0)
+            })
+        })
+}
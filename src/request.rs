@@ -1,4 +1,6 @@
 //! Parse the client request and return a Subscription
+mod cache;
+mod listener;
 mod postgres;
 mod query;
 mod timeline;
@@ -7,7 +9,7 @@ mod err;
 mod subscription;
 
 pub use err::{Error, Timeline as TimelineErr};
-pub use subscription::{Blocks, Subscription};
+pub use subscription::{Blocks, Subscription, WsCmd};
 pub use timeline::Timeline;
 
 #[cfg(feature = "bench")]
@@ -16,13 +18,15 @@ pub use timeline::{Content, Reach, Stream};
 #[cfg(not(feature = "bench"))]
 use timeline::{Content, Reach, Stream};
 
+pub(crate) use self::listener::Listener;
 pub use self::postgres::PgPool;
-use self::query::Query;
+use self::query::{Query, WsMsg};
 use crate::config::Postgres;
+use futures::future::{self, Either, Future};
 use warp::filters::BoxedFilter;
-use warp::http::StatusCode;
+use warp::http::header::{self, HeaderValue};
+use warp::http::{Response, StatusCode};
 use warp::path;
-use warp::reply;
 use warp::{Filter, Rejection};
 
 #[cfg(test)]
@@ -109,6 +113,36 @@ impl Handler {
             .boxed()
     }
 
+    /// Authorize a `subscribe` or `unsubscribe` message sent over the WebSocket opened with
+    /// `conn`
+    pub fn ws_cmd(
+        &self,
+        msg: &str,
+        conn: &Subscription,
+    ) -> impl Future<Item = WsCmd, Error = Rejection> {
+        let (pg_conn, access_token, user) = (
+            self.pg_conn.clone(),
+            conn.access_token.clone(),
+            conn.user.clone(),
+        );
+        let msg = serde_json::from_str::<WsMsg>(msg).map_err(|e| {
+            log::info!("Could not parse WebSocket message: {}", e);
+            warp::reject::custom(Query::BAD_WS_MSG)
+        });
+        future::result(msg).and_then(move |msg| match msg {
+            WsMsg::Subscribe(stream) => Either::A(
+                future::result(stream.into_query(access_token))
+                    .and_then(move |q| Subscription::query_postgres(q, pg_conn))
+                    .map(WsCmd::Subscribe),
+            ),
+            WsMsg::Unsubscribe(stream) => Either::B(
+                future::result(stream.into_query(access_token))
+                    .and_then(move |q| Subscription::unsubscribe_timeline(q, &user, &pg_conn))
+                    .map(WsCmd::Unsubscribe),
+            ),
+        })
+    }
+
     pub fn health(&self) -> BoxedFilter<()> {
         warp::path!("api" / "v1" / "streaming" / "health").boxed()
     }
@@ -127,24 +161,73 @@ impl Handler {
         warp::path!("api" / "v1" / "streaming" / "status" / "backpresure").boxed()
     }
 
+    pub fn status_cache(&self) -> BoxedFilter<()> {
+        warp::path!("api" / "v1" / "streaming" / "status" / "cache").boxed()
+    }
+
+    /// The size and hit rate of the cache of users and blocks (with `DB_CACHE_TTL`)
+    pub fn cache_status(&self) -> String {
+        self.pg_conn.cache().status()
+    }
+
     pub fn err(r: Rejection) -> std::result::Result<impl warp::Reply, warp::Rejection> {
         use StatusCode as Code;
-        let (msg, code) = match &r.cause().map(|cause| cause.to_string()).as_deref() {
-            Some(PgPool::BAD_TOKEN) => (PgPool::BAD_TOKEN, Code::UNAUTHORIZED),
-            Some(PgPool::PG_NULL) => (PgPool::PG_NULL, Code::BAD_REQUEST),
-            Some(PgPool::MISSING_HASHTAG) => (PgPool::MISSING_HASHTAG, Code::BAD_REQUEST),
-            Some(PgPool::SERVER_ERR) | Some(_) => (PgPool::SERVER_ERR, Code::INTERNAL_SERVER_ERROR),
-            None if r.is_not_found() => return Err(r),
-
-            None => (PgPool::SERVER_ERR, Code::INTERNAL_SERVER_ERROR),
+        let (msg, code) = match Self::msg_and_code(&r) {
+            Some(msg_and_code) => msg_and_code,
+            None => return Err(r),
         };
 
         if code == Code::INTERNAL_SERVER_ERROR {
             log::error!("Internal error: {:?}", &r);
         } else {
             log::info!("Request rejected: {} - {:?}", code, &r);
-        };
-        Ok(reply::with_status(reply::json(&msg), code))
+        }
+
+        // Built by hand (not with `warp::reply::with_status`) so that a 503 can add a header
+        let mut res = Response::new(serde_json::to_string(&msg).unwrap_or_default());
+        *res.status_mut() = code;
+        let headers = res.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        if code == Code::SERVICE_UNAVAILABLE {
+            headers.insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(PgPool::RETRY_AFTER_SECS),
+            );
+        }
+        Ok(res)
+    }
+
+    /// The error frame to send over a WebSocket in reply to a message that was rejected (in
+    /// the form Mastodon uses: `{"error":"<message>","status":<status code>}`)
+    pub fn ws_err(r: &Rejection) -> String {
+        let (msg, code) = Self::msg_and_code(r)
+            .unwrap_or((PgPool::SERVER_ERR, StatusCode::INTERNAL_SERVER_ERROR));
+        if code == StatusCode::INTERNAL_SERVER_ERROR {
+            log::error!("Internal error: {:?}", r);
+        } else {
+            log::info!("WebSocket message rejected: {} - {:?}", code, r);
+        }
+        serde_json::json!({ "error": msg, "status": code.as_u16() }).to_string()
+    }
+
+    /// The message and status code to reply to a rejection with (or `None` for a rejection
+    /// that just means no route matched)
+    fn msg_and_code(r: &Rejection) -> Option<(&'static str, StatusCode)> {
+        use StatusCode as Code;
+        Some(match &r.cause().map(ToString::to_string).as_deref() {
+            Some(PgPool::BAD_TOKEN) => (PgPool::BAD_TOKEN, Code::UNAUTHORIZED),
+            Some(PgPool::PG_NULL) => (PgPool::PG_NULL, Code::BAD_REQUEST),
+            Some(PgPool::MISSING_HASHTAG) => (PgPool::MISSING_HASHTAG, Code::BAD_REQUEST),
+            Some(Query::BAD_LIST_ID) => (Query::BAD_LIST_ID, Code::BAD_REQUEST),
+            Some(Query::UNKNOWN_STREAM) => (Query::UNKNOWN_STREAM, Code::BAD_REQUEST),
+            Some(Query::BAD_WS_MSG) => (Query::BAD_WS_MSG, Code::BAD_REQUEST),
+            Some(PgPool::POOL_EXHAUSTED) => (PgPool::POOL_EXHAUSTED, Code::SERVICE_UNAVAILABLE),
+            None if r.is_not_found() => return None,
+            _ => (PgPool::SERVER_ERR, Code::INTERNAL_SERVER_ERROR),
+        })
     }
 }
 
@@ -152,7 +235,7 @@ fn parse_ws_query() -> BoxedFilter<(Query,)> {
     use query::*;
     path!("api" / "v1" / "streaming")
         .and(path::end())
-        .and(warp::query())
+        .and(Stream::to_filter())
         .and(Auth::to_filter())
         .and(Media::to_filter())
         .and(Hashtag::to_filter())
@@ -0,0 +1,297 @@
+//! A cache of the users and blocks looked up in Postgres (enabled with `DB_CACHE_TTL`), so
+//! that clients that reconnect often don't repeat the same queries each time.
+//!
+//! Cached entries are removed as soon as Postgres sends a notification on `DB_CACHE_CHANNEL`
+//! with the payload `token:<access token>` or `account:<account id>`.  Mastodon doesn't send
+//! these notifications itself, so they come from the triggers in `sql/cache_invalidation.sql`;
+//! the cache is only enabled if those triggers are installed on each of the `WATCHED_TABLES`.
+//!
+//! While the connection we `LISTEN` on is down, notifications are lost, so the cache is
+//! emptied and bypassed until we are listening again.
+use super::timeline::UserData;
+use super::Blocks;
+use crate::Id;
+
+use hashbrown::HashMap;
+use lru::LruCache;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The cache shared by every copy of a `PgPool` (which does nothing if it is disabled)
+#[derive(Clone, Default)]
+pub(crate) struct Cache(Option<Arc<Inner>>);
+
+struct Inner {
+    users: Mutex<LruCache<String, Entry<UserData>>>,
+    blocks: Mutex<LruCache<Id, Entry<Blocks>>>,
+    ttl: Duration,
+    size: usize,
+    /// Incremented by each invalidation, so that results of queries made before an
+    /// invalidation (which may be out of date) aren't cached after it
+    generation: AtomicUsize,
+    /// The `generation` at which each token or account was last invalidated
+    invalidated: Mutex<HashMap<Key, usize>>,
+    /// Queries started before this `generation` are never cached (e.g., after a `clear`)
+    oldest_cacheable: AtomicUsize,
+    /// Whether the cache is in use (it isn't while we aren't receiving invalidations)
+    enabled: AtomicBool,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+/// What an invalidation names
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Token(String),
+    Account(Id),
+}
+
+struct Entry<T> {
+    value: T,
+    expires: Instant,
+}
+
+/// The `generation` of the cache when a query was started
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Generation(usize);
+
+impl Cache {
+    /// The tables whose changes can make a cached user or block out of date
+    pub(crate) const WATCHED_TABLES: [&'static str; 5] = [
+        "oauth_access_tokens",
+        "users",
+        "blocks",
+        "mutes",
+        "account_domain_blocks",
+    ];
+    /// The name of the triggers (and their function) in `sql/cache_invalidation.sql`
+    pub(crate) const TRIGGER: &'static str = "flodgatt_invalidate_cache";
+
+    /// The `WATCHED_TABLES` that have no trigger sending notifications on `channel`, given
+    /// the table and function source of each installed `TRIGGER`
+    pub(crate) fn missing_triggers(
+        installed: &[(String, String)],
+        channel: &str,
+    ) -> Vec<&'static str> {
+        let channel = format!("'{}'", channel.replace('\'', "''"));
+        Self::WATCHED_TABLES
+            .iter()
+            .filter(|table| {
+                !installed
+                    .iter()
+                    .any(|(installed, src)| installed == *table && src.contains(&channel))
+            })
+            .copied()
+            .collect()
+    }
+
+    pub(crate) fn new(ttl: Duration, size: usize) -> Self {
+        Self(Some(Arc::new(Inner {
+            users: Mutex::new(LruCache::new(size)),
+            blocks: Mutex::new(LruCache::new(size)),
+            ttl,
+            size,
+            generation: AtomicUsize::new(0),
+            invalidated: Mutex::new(HashMap::new()),
+            oldest_cacheable: AtomicUsize::new(0),
+            enabled: AtomicBool::new(true),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })))
+    }
+
+    pub(crate) fn user(&self, token: &str) -> Option<UserData> {
+        let inner = self.enabled()?;
+        inner.get(&inner.users, &token.to_string())
+    }
+
+    pub(crate) fn blocks(&self, id: Id) -> Option<Blocks> {
+        let inner = self.enabled()?;
+        inner.get(&inner.blocks, &id)
+    }
+
+    /// Call before querying Postgres for a value to cache (and pass to `put_user` or
+    /// `put_blocks` afterwards)
+    pub(crate) fn generation(&self) -> Generation {
+        Generation(
+            self.0
+                .as_ref()
+                .map_or(0, |inner| inner.generation.load(Ordering::SeqCst)),
+        )
+    }
+
+    pub(crate) fn put_user(&self, token: String, user: UserData, generation: Generation) {
+        if let Some(inner) = self.enabled() {
+            let keys = [Key::Token(token.clone()), Key::Account(user.id)];
+            inner.put(&inner.users, token, user, &keys, generation);
+        }
+    }
+
+    pub(crate) fn put_blocks(&self, id: Id, blocks: Blocks, generation: Generation) {
+        if let Some(inner) = self.enabled() {
+            inner.put(&inner.blocks, id, blocks, &[Key::Account(id)], generation);
+        }
+    }
+
+    /// Remove the entries named by a notification's payload (`token:<access token>` or
+    /// `account:<account id>`)
+    pub(crate) fn invalidate(&self, payload: &str) {
+        let inner = match &self.0 {
+            Some(inner) => inner,
+            None => return,
+        };
+
+        let mut parts = payload.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("token"), Some(token)) => {
+                inner.invalidate(Key::Token(token.to_string()));
+                lock(&inner.users).pop(&token.to_string());
+            }
+            (Some("account"), Some(id)) => match id.parse() {
+                Ok(id) => {
+                    let id = Id(id);
+                    inner.invalidate(Key::Account(id));
+                    lock(&inner.blocks).pop(&id);
+                    let mut users = lock(&inner.users);
+                    let tokens: Vec<String> = users
+                        .iter()
+                        .filter(|(_, entry)| entry.value.id == id)
+                        .map(|(token, _)| token.clone())
+                        .collect();
+                    for token in tokens {
+                        users.pop(&token);
+                    }
+                }
+                Err(_) => log::warn!("Ignoring cache invalidation `{}`", payload),
+            },
+            _ => log::warn!("Ignoring cache invalidation `{}`", payload),
+        }
+    }
+
+    /// Remove every entry (such as after missing notifications while reconnecting)
+    pub(crate) fn clear(&self) {
+        if let Some(inner) = &self.0 {
+            inner.forget_invalidations();
+            *lock(&inner.users) = LruCache::new(inner.size);
+            *lock(&inner.blocks) = LruCache::new(inner.size);
+        }
+    }
+
+    /// Empty the cache and stop using it until `enable` is called (such as while we can't
+    /// receive invalidations)
+    pub(crate) fn disable(&self) {
+        if let Some(inner) = &self.0 {
+            inner.enabled.store(false, Ordering::SeqCst);
+        }
+        self.clear();
+    }
+
+    pub(crate) fn enable(&self) {
+        if let Some(inner) = &self.0 {
+            inner.enabled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn enabled(&self) -> Option<&Inner> {
+        self.0
+            .as_deref()
+            .filter(|inner| inner.enabled.load(Ordering::SeqCst))
+    }
+
+    pub(crate) fn status(&self) -> String {
+        match &self.0 {
+            Some(inner) if !inner.enabled.load(Ordering::SeqCst) => {
+                "Cache bypassed (not receiving invalidations)".to_string()
+            }
+            Some(inner) => format!(
+                "Cached users: {}\n\
+                 Cached blocks: {}\n\
+                 Cache hits: {}\n\
+                 Cache misses: {}",
+                lock(&inner.users).len(),
+                lock(&inner.blocks).len(),
+                inner.hits.load(Ordering::Relaxed),
+                inner.misses.load(Ordering::Relaxed),
+            ),
+            None => "Cache disabled".to_string(),
+        }
+    }
+}
+
+impl Inner {
+    fn get<K: Hash + Eq, T: Clone>(
+        &self,
+        cache: &Mutex<LruCache<K, Entry<T>>>,
+        key: &K,
+    ) -> Option<T> {
+        let mut cache = lock(cache);
+        let value = match cache.get(key) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.value.clone()),
+            Some(_) => {
+                cache.pop(key);
+                None
+            }
+            None => None,
+        };
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    /// Cache `value` unless one of the `invalidated_by` keys has been invalidated since its
+    /// query started
+    fn put<K: Hash + Eq, T>(
+        &self,
+        cache: &Mutex<LruCache<K, Entry<T>>>,
+        key: K,
+        value: T,
+        invalidated_by: &[Key],
+        generation: Generation,
+    ) {
+        let mut cache = lock(cache);
+        // Checked while holding the lock, so an invalidation can't slip in before the `put`
+        let invalidated = lock(&self.invalidated);
+        let stale = generation.0 < self.oldest_cacheable.load(Ordering::SeqCst)
+            || invalidated_by
+                .iter()
+                .any(|key| invalidated.get(key).is_some_and(|g| *g > generation.0));
+        if !stale {
+            let expires = Instant::now() + self.ttl;
+            cache.put(key, Entry { value, expires });
+        }
+    }
+
+    fn invalidate(&self, key: Key) {
+        let mut invalidated = lock(&self.invalidated);
+        if invalidated.len() >= self.size {
+            // Rather than remembering every invalidation, treat older ones as invalidating
+            // everything (so only queries in progress aren't cached)
+            drop(invalidated);
+            self.forget_invalidations();
+            invalidated = lock(&self.invalidated);
+        }
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        invalidated.insert(key, generation);
+    }
+
+    /// Treat every query started before now as out of date
+    fn forget_invalidations(&self) {
+        let mut invalidated = lock(&self.invalidated);
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.oldest_cacheable.store(generation, Ordering::SeqCst);
+        invalidated.clear();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,141 @@
+use super::*;
+use hashbrown::HashSet;
+
+fn user(id: i64) -> UserData {
+    UserData {
+        id: Id(id),
+        ..UserData::public()
+    }
+}
+
+fn blocks(blocked_user: i64) -> Blocks {
+    Blocks {
+        blocked_users: vec![Id(blocked_user)].into_iter().collect::<HashSet<_>>(),
+        ..Blocks::default()
+    }
+}
+
+#[test]
+fn cache_returns_entries_until_they_expire() {
+    let cache = Cache::new(Duration::from_millis(50), 10);
+    assert!(cache.user("TOKEN").is_none());
+
+    cache.put_user("TOKEN".to_string(), user(1), cache.generation());
+    cache.put_blocks(Id(1), blocks(2), cache.generation());
+    assert_eq!(cache.user("TOKEN").map(|user| user.id), Some(Id(1)));
+    assert_eq!(cache.blocks(Id(1)), Some(blocks(2)));
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(cache.user("TOKEN").is_none());
+    assert!(cache.blocks(Id(1)).is_none());
+    assert!(cache.status().contains("Cache hits: 2\nCache misses: 3"));
+}
+
+#[test]
+fn cache_invalidates_tokens_and_accounts() {
+    let cache = Cache::new(Duration::from_secs(60), 10);
+    cache.put_user("TOKEN_1".to_string(), user(1), cache.generation());
+    cache.put_user("TOKEN_2".to_string(), user(2), cache.generation());
+    cache.put_blocks(Id(2), blocks(1), cache.generation());
+
+    cache.invalidate("token:TOKEN_1");
+    assert!(cache.user("TOKEN_1").is_none());
+    assert!(cache.user("TOKEN_2").is_some());
+
+    cache.invalidate("account:2");
+    assert!(cache.user("TOKEN_2").is_none());
+    assert!(cache.blocks(Id(2)).is_none());
+
+    cache.invalidate("account:not_an_id"); // ignored
+}
+
+#[test]
+fn cache_ignores_queries_started_before_an_invalidation() {
+    let cache = Cache::new(Duration::from_secs(60), 10);
+    let generation = cache.generation();
+    cache.invalidate("account:1"); // e.g., the user blocks someone while we query Postgres
+    cache.put_blocks(Id(1), blocks(2), generation);
+    assert!(cache.blocks(Id(1)).is_none());
+
+    cache.put_blocks(Id(1), blocks(3), cache.generation());
+    cache.clear();
+    assert!(cache.blocks(Id(1)).is_none());
+}
+
+#[test]
+fn disabled_cache_caches_nothing() {
+    let cache = Cache::default();
+    cache.put_user("TOKEN".to_string(), user(1), cache.generation());
+    assert!(cache.user("TOKEN").is_none());
+    assert_eq!(cache.status(), "Cache disabled");
+}
+
+#[test]
+fn cache_only_ignores_queries_for_the_invalidated_account_or_token() {
+    let cache = Cache::new(Duration::from_secs(60), 10);
+    let generation = cache.generation();
+    cache.invalidate("account:1");
+    cache.invalidate("token:TOKEN_1");
+    cache.put_blocks(Id(2), blocks(1), generation);
+    cache.put_user("TOKEN_2".to_string(), user(2), generation);
+    assert!(cache.blocks(Id(2)).is_some());
+    assert!(cache.user("TOKEN_2").is_some());
+
+    cache.put_user("TOKEN_1".to_string(), user(3), generation);
+    cache.put_user("TOKEN_3".to_string(), user(1), generation);
+    assert!(cache.user("TOKEN_1").is_none());
+    assert!(cache.user("TOKEN_3").is_none());
+}
+
+#[test]
+fn cache_is_bypassed_while_disabled() {
+    let cache = Cache::new(Duration::from_secs(60), 10);
+    cache.put_user("TOKEN".to_string(), user(1), cache.generation());
+    let generation = cache.generation();
+
+    cache.disable(); // e.g., we lost the connection we receive invalidations on
+    assert!(cache.user("TOKEN").is_none());
+    cache.put_user("TOKEN".to_string(), user(1), cache.generation());
+    assert!(cache.status().starts_with("Cache bypassed"));
+
+    cache.enable();
+    assert!(cache.user("TOKEN").is_none());
+    cache.put_user("TOKEN".to_string(), user(1), generation); // queried before `disable`
+    assert!(cache.user("TOKEN").is_none());
+    cache.put_user("TOKEN".to_string(), user(1), cache.generation());
+    assert!(cache.user("TOKEN").is_some());
+}
+
+#[test]
+fn shipped_triggers_cover_every_watched_table() {
+    let sql = include_str!("../../../sql/cache_invalidation.sql");
+    let function_src = sql.split("$$").nth(1).expect("a function body");
+    let installed: Vec<(String, String)> = sql
+        .lines()
+        .filter(|line| line.starts_with(&format!("CREATE TRIGGER {} ", Cache::TRIGGER)))
+        .filter_map(|line| line.split(" ON ").nth(1))
+        .map(|table| (table.trim().to_string(), function_src.to_string()))
+        .collect();
+
+    assert!(Cache::missing_triggers(&installed, "flodgatt_cache").is_empty());
+    // The triggers notify the default `DB_CACHE_CHANNEL` unless they are edited
+    assert_eq!(
+        Cache::missing_triggers(&installed, "other_channel"),
+        Cache::WATCHED_TABLES.to_vec()
+    );
+}
+
+#[test]
+fn cache_needs_a_trigger_on_every_watched_table() {
+    let src = "PERFORM pg_notify('flodgatt_cache', 'account:' || NEW.account_id);";
+    let installed: Vec<(String, String)> = vec!["users", "blocks", "mutes"]
+        .into_iter()
+        .map(|table| (table.to_string(), src.to_string()))
+        .collect();
+
+    assert_eq!(
+        Cache::missing_triggers(&installed, "flodgatt_cache"),
+        vec!["oauth_access_tokens", "account_domain_blocks"]
+    );
+    assert_eq!(Cache::missing_triggers(&[], "flodgatt_cache").len(), 5);
+}
@@ -4,6 +4,7 @@ use std::fmt;
 pub enum Error {
     PgPool(r2d2::Error),
     Pg(postgres::Error),
+    Tls(String),
 }
 
 impl std::error::Error for Error {}
@@ -14,6 +15,7 @@ impl fmt::Display for Error {
         let msg = match self {
             PgPool(e) => format!("{}", e),
             Pg(e) => format!("{}", e),
+            Tls(msg) => format!("Could not set up TLS for connecting to Postgres: {}", msg),
         };
         write!(f, "{}", msg)
     }
@@ -29,6 +31,11 @@ impl From<postgres::Error> for Error {
         Self::Pg(e)
     }
 }
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        Self::Tls(e.to_string())
+    }
+}
 
 #[derive(Debug)]
 pub enum Timeline {
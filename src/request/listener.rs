@@ -0,0 +1,105 @@
+//! A Postgres connection that `LISTEN`s for notifications
+use super::err::Error;
+use super::PgPool;
+use crate::config;
+
+use ::postgres::fallible_iterator::FallibleIterator;
+use ::postgres::{self, Client};
+use postgres_openssl::MakeTlsConnector;
+use std::thread;
+use std::time::Duration;
+
+/// `LISTEN`s on Postgres channels, reconnecting whenever the connection is lost.
+///
+/// The `postgres` client only blocks, so notifications should be `receive`d on their own thread.
+pub(crate) struct Listener {
+    client: Client,
+    cfg: postgres::Config,
+    tls: MakeTlsConnector,
+    channels: Vec<String>,
+}
+
+impl Listener {
+    const MIN_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+    const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+    /// Connect to Postgres and `LISTEN` on each of the `channels` (failing immediately if
+    /// Postgres isn't running)
+    pub(crate) fn new(pg_cfg: &config::Postgres, channels: Vec<String>) -> Result<Self, Error> {
+        let cfg = PgPool::connection_cfg(pg_cfg);
+        let tls = PgPool::tls_connector(pg_cfg)?;
+        let client = Self::listen(&cfg, &tls, &channels)?;
+        Ok(Self {
+            client,
+            cfg,
+            tls,
+            channels,
+        })
+    }
+
+    fn listen(
+        cfg: &postgres::Config,
+        tls: &MakeTlsConnector,
+        channels: &[String],
+    ) -> Result<Client, postgres::Error> {
+        let mut client = cfg.connect(tls.clone())?;
+        for channel in channels {
+            // Channel names are identifiers, so they are quoted rather than passed as parameters
+            let channel = channel.replace('"', "\"\"");
+            client.batch_execute(&format!("LISTEN \"{}\"", channel))?;
+        }
+        Ok(client)
+    }
+
+    /// Call `on_notification` with the payload of every notification, reconnecting (with
+    /// exponential backoff) whenever the connection is lost.  Notifications sent while we are
+    /// disconnected are lost, so `on_disconnect` is called as soon as the connection is lost
+    /// and `on_reconnect` once we are listening again.
+    ///
+    /// Returns once `on_notification` returns `false`.
+    pub(crate) fn receive<F, D, R>(
+        mut self,
+        mut on_notification: F,
+        mut on_disconnect: D,
+        mut on_reconnect: R,
+    ) where
+        F: FnMut(&str) -> bool,
+        D: FnMut(),
+        R: FnMut(),
+    {
+        let mut backoff = Self::MIN_RECONNECT_BACKOFF;
+        loop {
+            {
+                let mut notifications = self.client.notifications();
+                let mut notifications = notifications.blocking_iter();
+                loop {
+                    match notifications.next() {
+                        Ok(Some(notification)) => {
+                            if !on_notification(notification.payload()) {
+                                return;
+                            }
+                            backoff = Self::MIN_RECONNECT_BACKOFF;
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            log::error!("{}", e);
+                            break;
+                        }
+                    }
+                }
+            } // drop the notifications, which borrow the client
+
+            log::error!("Lost the connection to Postgres.  Reconnecting...");
+            on_disconnect();
+            self.client = loop {
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(Self::MAX_RECONNECT_BACKOFF);
+                match Self::listen(&self.cfg, &self.tls, &self.channels) {
+                    Ok(client) => break client,
+                    Err(e) => log::error!("Could not reconnect to Postgres: {}", e),
+                }
+            };
+            on_reconnect();
+        }
+    }
+}
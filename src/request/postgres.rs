@@ -1,19 +1,40 @@
 //! Postgres queries
+use super::cache::Cache;
 use super::err;
+use super::listener::Listener;
 use super::timeline::{Scope, UserData};
-use crate::config;
+use crate::config::{self, PgSslInner};
 use crate::Id;
 
-use ::postgres::{self, SimpleQueryMessage};
-use hashbrown::HashSet;
+use ::postgres::config::SslMode;
+use ::postgres::types::{FromSql, ToSql};
+use ::postgres::{self, Row, Statement};
+use futures::{Async, Future, Poll};
+use futures_cpupool::{CpuFuture, CpuPool};
+use hashbrown::{HashMap, HashSet};
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use postgres_openssl::MakeTlsConnector;
+use r2d2::{ManageConnection, PooledConnection};
 use r2d2_postgres::PostgresConnectionManager;
 use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
 #[allow(deprecated)] // one fn is deprecated, not whole module
 use warp::reject;
 
+/// A pool of connections to Postgres, along with the threads that block on them.
+///
+/// The `postgres` client blocks, so queries are `run` on threads reserved for them instead of
+/// on the executor (where a burst of clients connecting would delay sending events).
 #[derive(Clone)]
 pub struct PgPool {
-    conn: r2d2::Pool<PostgresConnectionManager<postgres::NoTls>>,
+    conn: r2d2::Pool<ConnectionManager>,
+    blocking: CpuPool,
+    connection_timeout: Duration,
+    cache: Cache,
     whitelist_mode: bool,
 }
 
@@ -25,68 +46,213 @@ impl PgPool {
     pub(crate) const SERVER_ERR: &'static str = "Error: Internal server error";
     pub(crate) const PG_NULL: &'static str = "Error: Unexpected null from Postgres";
     pub(crate) const MISSING_HASHTAG: &'static str = "Error: Hashtag does not exist";
+    pub(crate) const POOL_EXHAUSTED: &'static str = "Error: Too many requests; try again later";
+    /// How long to tell clients to wait before retrying when every connection is in use
+    pub(crate) const RETRY_AFTER_SECS: u64 = 5;
 
     pub(crate) fn new(pg_cfg: &config::Postgres, whitelist_mode: bool) -> Result<Self> {
+        let mut cfg = Self::connection_cfg(pg_cfg);
+        if let Some(timeout) = *pg_cfg.statement_timeout {
+            cfg.options(&format!("-c statement_timeout={}", timeout.as_millis()));
+        }
+        let tls = Self::tls_connector(pg_cfg)?;
+        // Test connection, letting us immediately exit with an error when Postgres isn't
+        // running instead of timing out below
+        let mut client = cfg.connect(tls.clone())?;
+        let manager = ConnectionManager(PostgresConnectionManager::new(cfg, tls));
+
+        let cache = Self::new_cache(pg_cfg, &mut client)?;
+
+        Ok(Self {
+            conn: r2d2::Pool::builder()
+                .max_size(*pg_cfg.pool_size)
+                .min_idle(*pg_cfg.pool_min_idle)
+                .connection_timeout(*pg_cfg.connection_timeout)
+                .idle_timeout(*pg_cfg.idle_timeout)
+                .build(manager)?,
+            blocking: futures_cpupool::Builder::new()
+                .pool_size(*pg_cfg.pool_size as usize)
+                .name_prefix("postgres-")
+                .create(),
+            connection_timeout: *pg_cfg.connection_timeout,
+            cache,
+            whitelist_mode,
+        })
+    }
+
+    /// The cache (with `DB_CACHE_TTL`), along with a thread that keeps it up to date.  It is
+    /// only enabled if Postgres has the triggers that send that thread notifications.
+    fn new_cache(pg_cfg: &config::Postgres, client: &mut postgres::Client) -> Result<Cache> {
+        let ttl = match *pg_cfg.cache_ttl {
+            Some(ttl) => ttl,
+            None => return Ok(Cache::default()),
+        };
+        let missing = Self::missing_cache_triggers(client, &pg_cfg.cache_channel)?;
+        if !missing.is_empty() {
+            log::error!(
+                "Not caching users and blocks (despite DB_CACHE_TTL): Postgres has no triggers \
+                 sending notifications on `{}` for changes to {}, so cached users and blocks \
+                 would go stale.  Install them with `sql/cache_invalidation.sql`.",
+                *pg_cfg.cache_channel,
+                missing.join(", ")
+            );
+            return Ok(Cache::default());
+        }
+
+        log::info!("Caching users and blocks for {:?}", ttl);
+        let cache = Cache::new(ttl, *pg_cfg.cache_size);
+        let listener = Listener::new(pg_cfg, vec![pg_cfg.cache_channel.clone().0])?;
+        let invalidated = cache.clone();
+        thread::spawn(move || {
+            listener.receive(
+                |payload| {
+                    invalidated.invalidate(payload);
+                    true
+                },
+                || invalidated.disable(),
+                || invalidated.enable(),
+            );
+        });
+        Ok(cache)
+    }
+
+    /// The tables without the triggers from `sql/cache_invalidation.sql`, which send the
+    /// notifications that keep the cache up to date
+    fn missing_cache_triggers(
+        client: &mut postgres::Client,
+        channel: &str,
+    ) -> Result<Vec<&'static str>> {
+        let installed = client.query(
+            "
+SELECT pg_class.relname::text, pg_proc.prosrc
+  FROM pg_trigger
+INNER JOIN pg_class ON pg_trigger.tgrelid = pg_class.oid
+INNER JOIN pg_proc ON pg_trigger.tgfoid = pg_proc.oid
+  WHERE pg_trigger.tgname = $1 AND pg_trigger.tgenabled <> 'D'",
+            &[&Cache::TRIGGER],
+        )?;
+        let installed: Vec<(String, String)> = installed
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        Ok(Cache::missing_triggers(&installed, channel))
+    }
+
+    /// Run `query` on one of the threads reserved for blocking on Postgres.  If none of them
+    /// is free to start it within `DB_CONNECTION_TIMEOUT`, the query is canceled and the
+    /// request is rejected so that the client can retry later.
+    pub(crate) fn run<T, F>(&self, query: F) -> Queued<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(PgPool) -> Rejectable<T> + Send + 'static,
+    {
+        let (pool, started) = (self.clone(), Arc::new(AtomicBool::new(false)));
+        let query_started = started.clone();
+        Queued {
+            query: self.blocking.spawn_fn(move || {
+                query_started.store(true, Ordering::SeqCst);
+                query(pool)
+            }),
+            started,
+            deadline: Some(Delay::new(Instant::now() + self.connection_timeout)),
+        }
+    }
+
+    /// The users and blocks cached from earlier queries (with `DB_CACHE_TTL`)
+    pub(crate) fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    /// A connection from the pool.  If none frees up within `DB_CONNECTION_TIMEOUT`, the request
+    /// is rejected so that the client can retry later.
+    fn conn(&self) -> Rejectable<PooledConnection<ConnectionManager>> {
+        self.conn.get().map_err(|e| {
+            log::warn!("Could not get a connection to Postgres: {}", e);
+            reject::custom(Self::POOL_EXHAUSTED)
+        })
+    }
+
+    /// The settings for connecting to Postgres (shared with the Postgres `EventSource`)
+    pub(crate) fn connection_cfg(pg_cfg: &config::Postgres) -> postgres::Config {
         let mut cfg = postgres::Config::new();
         cfg.user(&pg_cfg.user)
-            .host(&*pg_cfg.host.to_string())
+            .host(&pg_cfg.host.to_string())
             .port(*pg_cfg.port)
             .dbname(&pg_cfg.database);
         if let Some(password) = &*pg_cfg.password {
             cfg.password(password);
-        };
-
-        cfg.connect(postgres::NoTls)?; // Test connection, letting us immediately exit with an error
-                                       // when Postgres isn't running instead of timing out below
-        let manager = PostgresConnectionManager::new(cfg, postgres::NoTls);
-
-        Ok(Self {
-            conn: r2d2::Pool::builder().max_size(10).build(manager)?,
-            whitelist_mode,
-        })
+        }
+        cfg.ssl_mode(match *pg_cfg.ssl_mode {
+            PgSslInner::Disable => SslMode::Disable,
+            PgSslInner::Prefer => SslMode::Prefer,
+            PgSslInner::Require | PgSslInner::VerifyCa | PgSslInner::VerifyFull => SslMode::Require,
+        });
+        cfg
     }
 
-    fn is_safe(txt: &str) -> bool {
-        txt.chars()
-            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    /// The TLS settings from `DB_SSLMODE` and the other `DB_SSL*` environmental variables
+    /// (shared with the Postgres `EventSource`)
+    pub(crate) fn tls_connector(pg_cfg: &config::Postgres) -> Result<MakeTlsConnector> {
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+        if let Some(root_cert) = &*pg_cfg.ssl_root_cert {
+            builder.set_ca_file(root_cert)?;
+        }
+        match (&*pg_cfg.ssl_cert, &*pg_cfg.ssl_key) {
+            (Some(cert), Some(key)) => {
+                builder.set_certificate_chain_file(cert)?;
+                builder.set_private_key_file(key, SslFiletype::PEM)?;
+                builder.check_private_key()?;
+            }
+            (None, None) => (),
+            _ => Err(err::Error::Tls(
+                "DB_SSLCERT and DB_SSLKEY must be set together".to_string(),
+            ))?,
+        }
+
+        // `verify-ca` checks that the certificate is signed by a trusted CA, and `verify-full`
+        // also checks that it is for the host we connected to
+        let (verify, verify_hostname) = match *pg_cfg.ssl_mode {
+            PgSslInner::VerifyFull => (true, true),
+            PgSslInner::VerifyCa => (true, false),
+            PgSslInner::Require if pg_cfg.ssl_root_cert.is_some() => (true, false),
+            PgSslInner::Disable | PgSslInner::Prefer | PgSslInner::Require => (false, false),
+        };
+        if !verify {
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+        let mut connector = MakeTlsConnector::new(builder.build());
+        connector.set_callback(move |connect_cfg, _domain| {
+            connect_cfg.set_verify_hostname(verify_hostname);
+            Ok(())
+        });
+        Ok(connector)
     }
 
     pub(crate) fn select_user(self, token: &Option<String>) -> Rejectable<UserData> {
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-
         if let Some(token) = token {
-            if !Self::is_safe(token) {
-                Err(reject::custom(Self::BAD_TOKEN))?;
-            };
-
-            let rows = conn
-                .simple_query(&format!("
+            if let Some(user) = self.cache.user(token) {
+                return Ok(user);
+            }
+            let generation = self.cache.generation();
+            let mut conn = self.conn()?;
+            let rows = conn.query("
 SELECT oauth_access_tokens.resource_owner_id, users.account_id, users.chosen_languages, oauth_access_tokens.scopes
   FROM oauth_access_tokens
 INNER JOIN users ON oauth_access_tokens.resource_owner_id = users.id
-  WHERE oauth_access_tokens.token='{}' AND oauth_access_tokens.revoked_at IS NULL
-LIMIT 1", &token.to_owned())
-                ).map_err(reject::custom)?;
-
-            let row = match rows.get(0) {
-                Some(postgres::SimpleQueryMessage::Row(row)) => row,
-                _ => Err(reject::custom(Self::PG_NULL))?, // Wildcard required by #[non_exhaustive]
-            };
+  WHERE oauth_access_tokens.token = $1 AND oauth_access_tokens.revoked_at IS NULL
+LIMIT 1",
+                &[token],
+            )?;
+            let row = rows.first().ok_or_else(|| reject::custom(Self::PG_NULL))?;
 
-            let id = Id(get_col_or_reject(row, 1)?.parse().map_err(reject::custom)?);
+            let id = Id(get_col_or_reject(row, 1)?);
 
             let allowed_langs: HashSet<_> = row
-                .try_get(2)
-                .map_err(reject::custom)? // looks like `Some("{en,eo,es}")`
-                .map_or_else(HashSet::new, |str| {
-                    str.trim_start_matches('{')
-                        .trim_end_matches('}')
-                        .split(',')
-                        .map(String::from)
-                        .collect()
-                });
-
-            let mut scopes: HashSet<Scope> = get_col_or_reject(row, 3)?
+                .try_get::<_, Option<Vec<String>>>(2)
+                .map_err(reject::custom)?
+                .map_or_else(HashSet::new, |langs| langs.into_iter().collect());
+
+            let mut scopes: HashSet<Scope> = get_col_or_reject::<String>(row, 3)?
                 .split(' ')
                 .filter_map(|scope| Scope::try_from(scope).ok())
                 .collect();
@@ -94,14 +260,16 @@ LIMIT 1", &token.to_owned())
             if scopes.contains(&Scope::Read) {
                 scopes = vec![Scope::Statuses, Scope::Notifications, Scope::Lists]
                     .into_iter()
-                    .collect()
+                    .collect();
             }
 
-            Ok(UserData {
+            let user = UserData {
                 id,
                 allowed_langs,
                 scopes,
-            })
+            };
+            self.cache.put_user(token.clone(), user.clone(), generation);
+            Ok(user)
         } else if self.whitelist_mode {
             Err(reject::custom(Self::BAD_TOKEN))
         } else {
@@ -110,22 +278,12 @@ LIMIT 1", &token.to_owned())
     }
 
     pub(crate) fn select_hashtag_id(self, tag_name: &str) -> Rejectable<i64> {
-        if !Self::is_safe(tag_name) {
-            Err(reject::custom(Self::MISSING_HASHTAG))?;
-        };
-
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-        let rows = conn
-            .simple_query(&format!(
-                "SELECT id FROM tags WHERE name='{}' LIMIT 1",
-                &tag_name
-            ))
-            .map_err(reject::custom)?;
-        match rows.get(0).ok_or_else(|| reject::custom(Self::PG_NULL))? {
-            SimpleQueryMessage::Row(row) => get_col_or_reject(row, 0),
-            _ => Err(reject::custom(Self::MISSING_HASHTAG))?,
+        let mut conn = self.conn()?;
+        let rows = conn.query("SELECT id FROM tags WHERE name = $1 LIMIT 1", &[&tag_name])?;
+        match rows.first() {
+            Some(row) => get_col_or_reject(row, 0),
+            None => Err(reject::custom(Self::MISSING_HASHTAG)),
         }
-        .map(|s| s.parse().map_err(reject::custom))?
     }
 
     /// Query Postgres for everyone the user has blocked or muted
@@ -133,21 +291,15 @@ LIMIT 1", &token.to_owned())
     /// **NOTE**: because we check this when the user connects, it will not include any blocks
     /// the user adds until they refresh/reconnect.
     pub(crate) fn select_blocked_users(self, user_id: Id) -> Rejectable<HashSet<Id>> {
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-        conn.simple_query(&format!(
-            "SELECT target_account_id FROM blocks WHERE account_id = {0}
-                 UNION SELECT target_account_id FROM mutes WHERE account_id = {0}",
-            &*user_id
-        ))
-        .map_err(reject::custom)?
+        let mut conn = self.conn()?;
+        conn.query(
+            "SELECT target_account_id FROM blocks WHERE account_id = $1
+                 UNION SELECT target_account_id FROM mutes WHERE account_id = $1",
+            &[&*user_id],
+        )?
         .iter()
-        .try_fold(HashSet::new(), |mut set, row| match row {
-            SimpleQueryMessage::Row(row) => {
-                set.insert(get_col_or_reject(row, 0)?.parse().map_err(reject::custom)?);
-                Ok(set)
-            }
-            _ => Ok(set),
-        })
+        .map(|row| Ok(Id(get_col_or_reject(row, 0)?)))
+        .collect()
     }
 
     /// Query Postgres for everyone who has blocked the user
@@ -155,20 +307,14 @@ LIMIT 1", &token.to_owned())
     /// **NOTE**: because we check this when the user connects, it will not include any blocks
     /// the user adds until they refresh/reconnect.
     pub(crate) fn select_blocking_users(self, user_id: Id) -> Rejectable<HashSet<Id>> {
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-        conn.simple_query(&format!(
-            "SELECT account_id FROM blocks WHERE target_account_id = {}",
-            &*user_id
-        ))
-        .map_err(reject::custom)?
+        let mut conn = self.conn()?;
+        conn.query(
+            "SELECT account_id FROM blocks WHERE target_account_id = $1",
+            &[&*user_id],
+        )?
         .iter()
-        .try_fold(HashSet::new(), |mut set, row| match row {
-            SimpleQueryMessage::Row(row) => {
-                set.insert(get_col_or_reject(row, 0)?.parse().map_err(reject::custom)?);
-                Ok(set)
-            }
-            _ => Ok(set),
-        })
+        .map(|row| Ok(Id(get_col_or_reject(row, 0)?)))
+        .collect()
     }
 
     /// Query Postgres for all current domain blocks
@@ -176,45 +322,125 @@ LIMIT 1", &token.to_owned())
     /// **NOTE**: because we check this when the user connects, it will not include any blocks
     /// the user adds until they refresh/reconnect.
     pub(crate) fn select_blocked_domains(self, user_id: Id) -> Rejectable<HashSet<String>> {
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-        conn.simple_query(&format!(
-            "SELECT domain FROM account_domain_blocks WHERE account_id = {}",
-            &*user_id,
-        ))
-        .map_err(reject::custom)?
+        let mut conn = self.conn()?;
+        conn.query(
+            "SELECT domain FROM account_domain_blocks WHERE account_id = $1",
+            &[&*user_id],
+        )?
         .iter()
-        .try_fold(HashSet::new(), |mut set, row| match row {
-            SimpleQueryMessage::Row(row) => {
-                set.insert(get_col_or_reject(row, 0)?.to_string());
-                Ok(set)
-            }
-            _ => Ok(set),
-        })
+        .map(|row| get_col_or_reject(row, 0))
+        .collect()
     }
 
     /// Test whether a user owns a list
     pub(crate) fn user_owns_list(self, user_id: Id, list_id: i64) -> Rejectable<bool> {
         // For the Postgres query, `id` = list number; `account_id` = user.id
-        let mut conn = self.conn.get().map_err(reject::custom)?;
-        let rows = conn
-            .simple_query(&format!(
-                "SELECT id, account_id FROM lists WHERE id={} LIMIT 1",
-                &list_id,
-            ))
-            .map_err(reject::custom)?;
-
-        match rows.get(0).ok_or_else(|| reject::custom(Self::PG_NULL))? {
-            SimpleQueryMessage::Row(row) => {
-                Ok(Id(get_col_or_reject(row, 1)?.parse().map_err(reject::custom)?) == user_id)
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            "SELECT id, account_id FROM lists WHERE id = $1 LIMIT 1",
+            &[&list_id],
+        )?;
+
+        match rows.first() {
+            Some(row) => Ok(Id(get_col_or_reject(row, 1)?) == user_id),
+            None => Err(reject::custom(Self::MISSING_HASHTAG)),
+        }
+    }
+}
+
+/// A query waiting to be `run` (or running) on one of the `PgPool`'s threads
+pub(crate) struct Queued<T> {
+    query: CpuFuture<T, warp::Rejection>,
+    started: Arc<AtomicBool>,
+    /// When to give up if the query still hasn't started (`None` once it has)
+    deadline: Option<Delay>,
+}
+
+impl<T: Send + 'static> Future for Queued<T> {
+    type Item = T;
+    type Error = warp::Rejection;
+
+    fn poll(&mut self) -> Poll<T, warp::Rejection> {
+        if let Async::Ready(result) = self.query.poll()? {
+            return Ok(Async::Ready(result));
+        }
+        if self.started.load(Ordering::SeqCst) {
+            self.deadline = None;
+        }
+        let expired = match self.deadline.as_mut().map(Delay::poll) {
+            Some(Ok(Async::Ready(()))) => true,
+            Some(Ok(Async::NotReady)) | None => false,
+            Some(Err(e)) => {
+                log::error!("Could not time the wait for a Postgres thread: {}", e);
+                self.deadline = None;
+                false
             }
-            _ => Err(reject::custom(Self::MISSING_HASHTAG))?,
+        };
+        // Checked again now, in case the query started while we were checking the deadline
+        if expired && !self.started.load(Ordering::SeqCst) {
+            log::warn!("Timed out waiting for a free connection to Postgres");
+            return Err(reject::custom(PgPool::POOL_EXHAUSTED)); // dropping `query` cancels it
         }
+        Ok(Async::NotReady)
     }
 }
 
-fn get_col_or_reject(row: &postgres::row::SimpleQueryRow, col: usize) -> Rejectable<&str> {
-    Ok(row
-        .try_get(col)
+/// A pooled connection to Postgres.  Each query is prepared the first time it is run on the
+/// connection, and the prepared statement is reused for as long as the connection lasts.
+struct Connection {
+    client: postgres::Client,
+    statements: HashMap<&'static str, Statement>,
+}
+
+impl Connection {
+    fn query(
+        &mut self,
+        query: &'static str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Rejectable<Vec<Row>> {
+        let statement = match self.statements.get(query) {
+            Some(statement) => statement.clone(),
+            None => {
+                let statement = self.client.prepare(query).map_err(reject::custom)?;
+                self.statements.insert(query, statement.clone());
+                statement
+            }
+        };
+        self.client
+            .query(&statement, params)
+            .map_err(reject::custom)
+    }
+}
+
+/// Manages `Connection`s for the pool (with `PostgresConnectionManager` doing the connecting)
+struct ConnectionManager(PostgresConnectionManager<MakeTlsConnector>);
+
+impl ManageConnection for ConnectionManager {
+    type Connection = Connection;
+    type Error = postgres::Error;
+
+    fn connect(&self) -> std::result::Result<Connection, postgres::Error> {
+        Ok(Connection {
+            client: self.0.connect()?,
+            statements: HashMap::new(),
+        })
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> std::result::Result<(), postgres::Error> {
+        self.0.is_valid(&mut conn.client)
+    }
+
+    fn has_broken(&self, conn: &mut Connection) -> bool {
+        self.0.has_broken(&mut conn.client)
+    }
+}
+
+/// Get a column that should never be null (rejecting the request if it is)
+fn get_col_or_reject<'a, T: FromSql<'a>>(row: &'a Row, col: usize) -> Rejectable<T> {
+    row.try_get::<_, Option<T>>(col)
         .map_err(reject::custom)?
-        .ok_or_else(|| reject::custom(PgPool::PG_NULL))?)
+        .ok_or_else(|| reject::custom(PgPool::PG_NULL))
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,54 @@
+use super::*;
+use crate::request::Handler;
+use std::sync::mpsc;
+use warp::http::StatusCode;
+use warp::Filter;
+
+/// A pool that never connects to Postgres (so it can only `run` queries that don't query it)
+fn pool(threads: usize, connection_timeout: Duration) -> PgPool {
+    let tls = SslConnector::builder(SslMethod::tls())
+        .expect("TLS")
+        .build();
+    let manager =
+        PostgresConnectionManager::new(postgres::Config::new(), MakeTlsConnector::new(tls));
+    PgPool {
+        conn: r2d2::Pool::builder()
+            .min_idle(Some(0))
+            .build_unchecked(ConnectionManager(manager)),
+        blocking: futures_cpupool::Builder::new().pool_size(threads).create(),
+        connection_timeout,
+        cache: Cache::default(),
+        whitelist_mode: false,
+    }
+}
+
+#[test]
+fn pool_rejects_with_503_when_no_thread_frees_up() {
+    let pool = pool(1, Duration::from_millis(50));
+    let (release, wait) = mpsc::channel::<()>();
+    let busy = pool.run(move |_| Ok(wait.recv().ok())); // occupies the only thread
+
+    let filter = warp::any()
+        .and_then(move || pool.run(|_| Ok("queried")))
+        .recover(Handler::err);
+    let res = warp::test::request().reply(&filter);
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers()["retry-after"], "5");
+
+    release.send(()).expect("the busy query to be waiting");
+    drop(busy);
+}
+
+#[test]
+fn pool_runs_queries_once_a_thread_frees_up() {
+    let pool = pool(1, Duration::from_secs(5));
+    let (release, wait) = mpsc::channel::<()>();
+    let busy = pool.run(move |_| Ok(wait.recv().ok()));
+    release.send(()).expect("the busy query to be waiting");
+
+    let filter = warp::any().and_then(move || pool.run(|_| Ok("queried")));
+    let res = warp::test::request().reply(&filter);
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.body(), "queried");
+    drop(busy);
+}
@@ -1,7 +1,9 @@
 //! Validate query prarams with type checking
+use std::fmt;
+
 use serde_derive::Deserialize;
 use warp::filters::BoxedFilter;
-use warp::Filter as WarpFilter;
+use warp::{Filter as WarpFilter, Rejection};
 
 #[derive(Debug)]
 pub(crate) struct Query {
@@ -13,6 +15,10 @@ pub(crate) struct Query {
 }
 
 impl Query {
+    pub(crate) const BAD_LIST_ID: &'static str = "Error: List ID must be a number";
+    pub(crate) const UNKNOWN_STREAM: &'static str = "Error: Nonexistent endpoint";
+    pub(crate) const BAD_WS_MSG: &'static str = "Error: Unrecognized WebSocket message";
+
     pub(crate) fn update_access_token(
         self,
         token: Option<String>,
@@ -28,12 +34,6 @@ impl Query {
 }
 
 macro_rules! make_query_type {
-    (Stream => $parameter:tt:$type:ty) => {
-        #[derive(Deserialize, Debug, Default)]
-        pub(crate) struct Stream {
-            pub(crate) $parameter: $type,
-        }
-    };
     ($name:tt => $parameter:tt:$type:ty) => {
         #[derive(Deserialize, Debug, Default)]
         pub(crate) struct $name {
@@ -59,9 +59,62 @@ make_query_type!(Hashtag => tag: String);
 make_query_type!(List => list: i64);
 make_query_type!(Auth => access_token: Option<String>);
 make_query_type!(Stream => stream: String);
-impl ToString for Stream {
-    fn to_string(&self) -> String {
-        format!("{:?}", self)
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A `subscribe` or `unsubscribe` message sent by the client over an open WebSocket
+///
+/// These follow Mastodon's multiplexing protocol and look like
+/// `{"type":"subscribe","stream":"hashtag","tag":"rust"}`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub(crate) enum WsMsg {
+    Subscribe(WsStream),
+    Unsubscribe(WsStream),
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct WsStream {
+    stream: String,
+    #[serde(default)]
+    tag: String,
+    #[serde(default)]
+    list: Option<ListId>,
+}
+
+/// Mastodon clients send list IDs as strings, but we also accept plain numbers
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ListId {
+    Num(i64),
+    Txt(String),
+}
+
+impl WsStream {
+    /// The `Query` for this stream, or a rejection if it names no stream or its list ID isn't
+    /// a number
+    pub(crate) fn into_query(self, access_token: Option<String>) -> Result<Query, Rejection> {
+        // Unlike the query that opens a WebSocket, a message must name the stream it is for
+        if self.stream.is_empty() {
+            Err(warp::reject::custom(Query::UNKNOWN_STREAM))?;
+        }
+        let list = match self.list {
+            Some(ListId::Num(id)) => id,
+            Some(ListId::Txt(id)) => id
+                .parse()
+                .map_err(|_| warp::reject::custom(Query::BAD_LIST_ID))?,
+            None => 0,
+        };
+        Ok(Query {
+            access_token,
+            stream: self.stream,
+            media: false,
+            hashtag: self.tag,
+            list,
+        })
     }
 }
 
@@ -69,12 +122,8 @@ pub(super) struct OptionalAccessToken;
 
 impl OptionalAccessToken {
     pub(super) fn from_sse_header() -> warp::filters::BoxedFilter<(Option<String>,)> {
-        let from_header = warp::header::header::<String>("authorization").map(|auth: String| {
-            match auth.split(' ').nth(1) {
-                Some(s) => Some(s.to_string()),
-                None => None,
-            }
-        });
+        let from_header = warp::header::header::<String>("authorization")
+            .map(|auth: String| auth.split(' ').nth(1).map(ToString::to_string));
         let no_token = warp::any().map(|| None);
 
         from_header.or(no_token).unify().boxed()
@@ -86,3 +135,6 @@ impl OptionalAccessToken {
         from_header.or(no_token).unify().boxed()
     }
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,67 @@
+use super::*;
+use crate::request::timeline::UserData;
+use crate::request::{Handler, Timeline};
+
+fn list_query(msg: &str) -> Result<i64, Option<String>> {
+    let stream = match serde_json::from_str(msg).expect("a valid message") {
+        WsMsg::Subscribe(stream) => stream,
+        WsMsg::Unsubscribe(stream) => stream,
+    };
+    stream
+        .into_query(None)
+        .map(|q| q.list)
+        .map_err(|r| r.cause().map(ToString::to_string))
+}
+
+#[test]
+fn ws_messages_accept_numeric_list_ids() {
+    let txt = list_query(r#"{"type":"subscribe","stream":"list","list":"12"}"#);
+    let num = list_query(r#"{"type":"unsubscribe","stream":"list","list":12}"#);
+    assert_eq!(txt, Ok(12));
+    assert_eq!(num, Ok(12));
+}
+
+#[test]
+fn ws_messages_reject_malformed_list_ids() {
+    let bad = list_query(r#"{"type":"subscribe","stream":"list","list":"12abc"}"#);
+    assert_eq!(bad, Err(Some(Query::BAD_LIST_ID.to_string())));
+}
+
+fn subscribed_timeline(msg: &str) -> Result<Timeline, Option<String>> {
+    let stream = match serde_json::from_str(msg).expect("a valid message") {
+        WsMsg::Subscribe(stream) => stream,
+        WsMsg::Unsubscribe(stream) => stream,
+    };
+    stream
+        .into_query(None)
+        .and_then(|q| Timeline::from_query_and_user(&q, &UserData::public()))
+        .map_err(|r| r.cause().map(ToString::to_string))
+}
+
+#[test]
+fn ws_messages_reject_empty_streams() {
+    let empty = subscribed_timeline(r#"{"type":"subscribe","stream":""}"#);
+    assert_eq!(empty, Err(Some(Query::UNKNOWN_STREAM.to_string())));
+}
+
+#[test]
+fn ws_messages_reject_unknown_streams() {
+    let unknown = subscribed_timeline(r#"{"type":"subscribe","stream":"nonsense"}"#);
+    assert_eq!(unknown, Err(Some(Query::UNKNOWN_STREAM.to_string())));
+}
+
+#[test]
+fn ws_messages_accept_known_streams() {
+    let public = subscribed_timeline(r#"{"type":"subscribe","stream":"public:local"}"#);
+    let stream = public.ok().and_then(|tl| tl.to_stream(None).ok());
+    assert_eq!(stream, Some(vec!["public:local".to_string()]));
+}
+
+#[test]
+fn rejected_ws_messages_are_answered_with_an_error_frame() {
+    let rejection = warp::reject::custom(Query::UNKNOWN_STREAM);
+    assert_eq!(
+        Handler::ws_err(&rejection),
+        r#"{"error":"Error: Nonexistent endpoint","status":400}"#
+    );
+}
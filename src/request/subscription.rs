@@ -7,9 +7,11 @@
 
 use super::postgres::PgPool;
 use super::query::Query;
+use super::timeline::UserData;
 use super::{Content, Reach, Stream, Timeline};
 use crate::Id;
 
+use futures::future::{self, Either, Future};
 use hashbrown::HashSet;
 
 use warp::reject::Rejection;
@@ -22,6 +24,16 @@ pub struct Subscription {
     pub blocks: Blocks,
     pub hashtag_name: Option<String>,
     pub access_token: Option<String>,
+    /// The user who connected, so that a WebSocket's later `unsubscribe` messages can be
+    /// handled without authenticating them again
+    pub(crate) user: UserData,
+}
+
+/// A change to the timelines of an open WebSocket, requested by a client message
+#[derive(Clone, Debug, PartialEq)]
+pub enum WsCmd {
+    Subscribe(Subscription),
+    Unsubscribe(Timeline),
 }
 
 /// Blocked and muted users and domains
@@ -40,44 +52,112 @@ impl Default for Subscription {
             blocks: Blocks::default(),
             hashtag_name: None,
             access_token: None,
+            user: UserData::public(),
         }
     }
 }
 
 impl Subscription {
-    pub(super) fn query_postgres(q: Query, pool: PgPool) -> Result<Self, Rejection> {
-        let user = pool.clone().select_user(&q.access_token)?;
-        let timeline = {
-            let tl = Timeline::from_query_and_user(&q, &user)?;
-            let pool = pool.clone();
-            use Stream::*;
-            match tl {
-                Timeline(Hashtag(_), reach, stream) => {
-                    let tag = pool.select_hashtag_id(&q.hashtag)?;
-                    Timeline(Hashtag(tag), reach, stream)
-                }
-                Timeline(List(list_id), _, _) if !pool.user_owns_list(user.id, list_id)? => {
-                    Err(warp::reject::custom("Error: Missing access token"))?
-                }
-                other_tl => other_tl,
-            }
-        };
+    /// Authenticate the user and look up the timeline and blocks for `q`.
+    ///
+    /// The queries run on the `PgPool`'s own threads; once the user is known, the queries
+    /// for the timeline and for each kind of block run concurrently.  Users and blocks are
+    /// cached with `DB_CACHE_TTL`.
+    pub(super) fn query_postgres(
+        q: Query,
+        pool: PgPool,
+    ) -> impl Future<Item = Self, Error = Rejection> {
+        let token = q.access_token.clone();
+        pool.run(move |pool| pool.select_user(&token))
+            .and_then(move |user| {
+                let tl = Timeline::from_query_and_user(&q, &user)?;
+                Ok((q, user, tl, pool))
+            })
+            .and_then(|(q, user, tl, pool)| {
+                let (user_id, hashtag) = (user.id, q.hashtag.clone());
+                let timeline =
+                    pool.run(move |pool| Self::check_timeline(tl, &hashtag, user_id, pool));
+                let blocks = Self::select_blocks(user_id, &pool);
 
-        let hashtag_name = match timeline {
-            Timeline(Stream::Hashtag(_), _, _) => Some(q.hashtag),
-            _non_hashtag_timeline => None,
-        };
+                timeline.join(blocks).map(move |(timeline, blocks)| {
+                    let hashtag_name = match timeline {
+                        Timeline(Stream::Hashtag(_), _, _) => Some(q.hashtag),
+                        _non_hashtag_timeline => None,
+                    };
+
+                    Subscription {
+                        timeline,
+                        allowed_langs: user.allowed_langs.clone(),
+                        blocks,
+                        hashtag_name,
+                        access_token: q.access_token,
+                        user,
+                    }
+                })
+            })
+    }
+
+    /// Look up everyone the user blocks or is blocked by (unless that is cached)
+    fn select_blocks(user_id: Id, pool: &PgPool) -> impl Future<Item = Blocks, Error = Rejection> {
+        let cache = pool.cache().clone();
+        if let Some(blocks) = cache.blocks(user_id) {
+            return Either::A(future::ok(blocks));
+        }
 
-        Ok(Subscription {
-            timeline,
-            allowed_langs: user.allowed_langs,
-            blocks: Blocks {
-                blocking_users: pool.clone().select_blocking_users(user.id)?,
-                blocked_users: pool.clone().select_blocked_users(user.id)?,
-                blocked_domains: pool.select_blocked_domains(user.id)?,
-            },
-            hashtag_name,
-            access_token: q.access_token,
+        let generation = cache.generation();
+        let blocks = pool
+            .run(move |pool| pool.select_blocking_users(user_id))
+            .join3(
+                pool.run(move |pool| pool.select_blocked_users(user_id)),
+                pool.run(move |pool| pool.select_blocked_domains(user_id)),
+            )
+            .map(move |(blocking_users, blocked_users, blocked_domains)| {
+                let blocks = Blocks {
+                    blocked_domains,
+                    blocked_users,
+                    blocking_users,
+                };
+                cache.put_blocks(user_id, blocks.clone(), generation);
+                blocks
+            });
+        Either::B(blocks)
+    }
+
+    /// The timeline to remove for an `unsubscribe` message sent by `user`.
+    ///
+    /// Clients can only unsubscribe from timelines they were allowed to subscribe to, so this
+    /// doesn't check list ownership or look up blocks; only a hashtag's ID needs Postgres.
+    pub(super) fn unsubscribe_timeline(
+        q: Query,
+        user: &UserData,
+        pool: &PgPool,
+    ) -> impl Future<Item = Timeline, Error = Rejection> {
+        match Timeline::from_query_and_user(&q, user) {
+            Ok(Timeline(Stream::Hashtag(_), reach, content)) => Either::A(
+                pool.run(move |pool| pool.select_hashtag_id(&q.hashtag))
+                    .map(move |tag| Timeline(Stream::Hashtag(tag), reach, content)),
+            ),
+            tl => Either::B(future::result(tl)),
+        }
+    }
+
+    /// Look up the ID of a hashtag timeline's tag, or check that the user owns a list timeline
+    fn check_timeline(
+        tl: Timeline,
+        hashtag: &str,
+        user_id: Id,
+        pool: PgPool,
+    ) -> Result<Timeline, Rejection> {
+        use Stream::*;
+        Ok(match tl {
+            Timeline(Hashtag(_), reach, stream) => {
+                let tag = pool.select_hashtag_id(hashtag)?;
+                Timeline(Hashtag(tag), reach, stream)
+            }
+            Timeline(List(list_id), _, _) if !pool.user_owns_list(user_id, list_id)? => {
+                Err(warp::reject::custom("Error: Missing access token"))?
+            }
+            other_tl => other_tl,
         })
     }
 }
@@ -20,11 +20,7 @@ impl Timeline {
     }
 
     pub(crate) fn is_public(&self) -> bool {
-        if let Self(Stream::Public, _, _) = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, Self(Stream::Public, _, _))
     }
 
     pub(crate) fn tag(&self) -> Option<i64> {
@@ -35,7 +31,7 @@ impl Timeline {
         }
     }
 
-    pub(crate) fn to_redis_raw_timeline(&self, hashtag: Option<&String>) -> Result<String> {
+    pub(crate) fn to_redis_raw_timeline(self, hashtag: Option<&String>) -> Result<String> {
         use {Content::*, Error::*, Reach::*, Stream::*};
 
         Ok(match self {
@@ -62,6 +58,35 @@ impl Timeline {
         })
     }
 
+    /// The `stream` Mastodon clients use to identify this timeline (e.g., `["hashtag", "rust"]`)
+    pub(crate) fn to_stream(self, hashtag: Option<&String>) -> Result<Vec<String>> {
+        use {Content::*, Error::*, Reach::*, Stream::*};
+
+        Ok(match self {
+            Timeline(Public, Federated, All) => vec!["public".to_string()],
+            Timeline(Public, Local, All) => vec!["public:local".to_string()],
+            Timeline(Public, Federated, Media) => vec!["public:media".to_string()],
+            Timeline(Public, Local, Media) => vec!["public:local:media".to_string()],
+            Timeline(Hashtag(_id), Federated, All) => {
+                vec![
+                    "hashtag".to_string(),
+                    hashtag.ok_or(MissingHashtag)?.clone(),
+                ]
+            }
+            Timeline(Hashtag(_id), Local, All) => {
+                vec![
+                    "hashtag:local".to_string(),
+                    hashtag.ok_or(MissingHashtag)?.clone(),
+                ]
+            }
+            Timeline(User(_id), Federated, All) => vec!["user".to_string()],
+            Timeline(User(_id), Federated, Notification) => vec!["user:notification".to_string()],
+            Timeline(List(id), Federated, All) => vec!["list".to_string(), id.to_string()],
+            Timeline(Direct(_id), Federated, All) => vec!["direct".to_string()],
+            Timeline(_one, _two, _three) => Err(Error::InvalidInput)?,
+        })
+    }
+
     pub fn from_redis_text(timeline: &str, cache: &mut LruCache<String, i64>) -> Result<Self> {
         use {Content::*, Error::*, Reach::*, Stream::*};
         let mut tag_id = |t: &str| cache.get(&t.to_string()).map_or(Err(BadTag), |id| Ok(*id));
@@ -99,6 +124,8 @@ impl Timeline {
             "public:media" => Timeline(Public, Federated, Media),
             "public:local:media" => Timeline(Public, Local, Media),
 
+            // WebSocket clients may connect without a stream and `subscribe` to one later
+            "" => Timeline::empty(),
             "hashtag" => Timeline(Hashtag(0), Federated, All),
             "hashtag:local" => Timeline(Hashtag(0), Local, All),
             "user" => match user.scopes.contains(&Statuses) {
@@ -119,8 +146,11 @@ impl Timeline {
             },
             other => {
                 log::warn!("Request for nonexistent endpoint: `{}`", other);
-                Err(custom("Error: Nonexistent endpoint"))?
+                Err(custom(Query::UNKNOWN_STREAM))?
             }
         })
     }
 }
+
+#[cfg(test)]
+mod test;
@@ -53,7 +53,7 @@ impl TryFrom<&str> for Scope {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) struct UserData {
     pub(crate) id: Id,
     pub(crate) allowed_langs: HashSet<String>,
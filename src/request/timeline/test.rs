@@ -0,0 +1,80 @@
+use super::*;
+use crate::Id;
+use Content::*;
+use Reach::*;
+use Stream::*;
+
+fn stream(tl: Timeline, hashtag: Option<&str>) -> Result<Vec<String>> {
+    tl.to_stream(hashtag.map(String::from).as_ref())
+}
+
+#[test]
+fn public_streams() -> Result<()> {
+    assert_eq!(
+        stream(Timeline(Public, Federated, All), None)?,
+        vec!["public"]
+    );
+    assert_eq!(
+        stream(Timeline(Public, Local, All), None)?,
+        vec!["public:local"]
+    );
+    assert_eq!(
+        stream(Timeline(Public, Federated, Media), None)?,
+        vec!["public:media"]
+    );
+    assert_eq!(
+        stream(Timeline(Public, Local, Media), None)?,
+        vec!["public:local:media"]
+    );
+    Ok(())
+}
+
+#[test]
+fn hashtag_streams_include_the_tag_name() -> Result<()> {
+    let federated = Timeline(Hashtag(1), Federated, All);
+    let local = Timeline(Hashtag(1), Local, All);
+    assert_eq!(stream(federated, Some("rust"))?, vec!["hashtag", "rust"]);
+    assert_eq!(stream(local, Some("rust"))?, vec!["hashtag:local", "rust"]);
+    Ok(())
+}
+
+#[test]
+fn hashtag_streams_need_the_tag_name() {
+    assert!(stream(Timeline(Hashtag(1), Federated, All), None).is_err());
+    assert!(stream(Timeline(Hashtag(1), Local, All), None).is_err());
+}
+
+#[test]
+fn user_streams() -> Result<()> {
+    assert_eq!(
+        stream(Timeline(User(Id(1)), Federated, All), None)?,
+        vec!["user"]
+    );
+    let notifications = Timeline(User(Id(1)), Federated, Notification);
+    assert_eq!(stream(notifications, None)?, vec!["user:notification"]);
+    Ok(())
+}
+
+#[test]
+fn list_streams_include_the_list_id() -> Result<()> {
+    assert_eq!(
+        stream(Timeline(List(7), Federated, All), None)?,
+        vec!["list", "7"]
+    );
+    Ok(())
+}
+
+#[test]
+fn direct_streams() -> Result<()> {
+    assert_eq!(
+        stream(Timeline(Direct(1), Federated, All), None)?,
+        vec!["direct"]
+    );
+    Ok(())
+}
+
+#[test]
+fn timelines_without_a_stream_are_invalid() {
+    assert!(stream(Timeline::empty(), None).is_err());
+    assert!(stream(Timeline(User(Id(1)), Local, All), None).is_err());
+}
@@ -2,6 +2,8 @@
 
 pub use event::Event;
 pub use redis::Manager as RedisManager;
+pub use redis::ReplaySource;
+pub use source::{EventSource, MemorySender, MemorySource, PostgresSource};
 pub use stream::{Sse as SseStream, Ws as WsStream};
 
 pub(self) use event::err::Event as EventErr;
@@ -9,6 +11,7 @@ pub(self) use event::Payload;
 
 pub(crate) mod event;
 mod redis;
+mod source;
 mod stream;
 
 pub use redis::Error;
@@ -16,4 +19,4 @@ pub use redis::Error;
 #[cfg(feature = "bench")]
 pub use event::EventKind;
 #[cfg(feature = "bench")]
-pub use redis::{Manager, RedisMsg, RedisParseOutput};
+pub use redis::{Manager, RedisInput, RedisMsg, RedisParseOutput};
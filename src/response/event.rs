@@ -31,33 +31,46 @@ pub(crate) trait Payload {
 }
 
 impl Event {
-    pub(crate) fn to_json_string(&self) -> String {
+    /// Serialize the `Event` for a WebSocket, optionally tagged with the `stream` it came from
+    pub(crate) fn to_json_string(&self, stream: Option<&[String]>) -> String {
         if let Event::Ping = self {
             "{}".to_string()
         } else {
             let event = &self.event_name();
             let sendable_event = match self.payload() {
-                Some(payload) => SendableEvent::WithPayload { event, payload },
-                None => SendableEvent::NoPayload { event },
+                Some(payload) => SendableEvent::WithPayload {
+                    stream,
+                    event,
+                    payload,
+                },
+                None => SendableEvent::NoPayload { stream, event },
             };
             serde_json::to_string(&sendable_event).expect("Guaranteed: SendableEvent is Serialize")
         }
     }
 
-    pub(crate) fn to_warp_reply(&self) -> Option<(impl ServerSentEvent, impl ServerSentEvent)> {
+    pub(crate) fn to_warp_reply(
+        &self,
+        id: u64,
+    ) -> Option<(
+        impl ServerSentEvent,
+        impl ServerSentEvent,
+        impl ServerSentEvent,
+    )> {
         if let Event::Ping = self {
             None
         } else {
             Some((
+                warp::sse::id(id.to_string()),
                 warp::sse::event(self.event_name()),
-                warp::sse::data(self.payload().unwrap_or_else(String::new)),
+                warp::sse::data(self.payload().unwrap_or_default()),
             ))
         }
     }
 
     pub(crate) fn update_payload(&self) -> Option<&checked_event::Status> {
         if let Self::TypeSafe(CheckedEvent::Update { payload, .. }) = self {
-            Some(&payload)
+            Some(payload)
         } else {
             None
         }
@@ -69,7 +82,7 @@ impl Event {
             ..
         }) = self
         {
-            Some(&s)
+            Some(s)
         } else {
             None
         }
@@ -136,7 +149,7 @@ impl TryFrom<&str> for Event {
                              Forwarding Redis payload without type checking it.",
                     e
                 );
-                let dyn_event: DynEvent = serde_json::from_str(&event_txt)?;
+                let dyn_event: DynEvent = serde_json::from_str(event_txt)?;
                 Ok(Event::Dynamic(dyn_event.set_update()?))
             }
         }
@@ -146,10 +159,22 @@ impl TryFrom<&str> for Event {
 #[derive(Serialize, Debug, Clone)]
 #[serde(untagged)]
 enum SendableEvent<'a> {
-    WithPayload { event: &'a str, payload: String },
-    NoPayload { event: &'a str },
+    WithPayload {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stream: Option<&'a [String]>,
+        event: &'a str,
+        payload: String,
+    },
+    NoPayload {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stream: Option<&'a [String]>,
+        event: &'a str,
+    },
 }
 
 fn escaped<T: Serialize + std::fmt::Debug>(content: T) -> String {
     serde_json::to_string(&content).expect("Guaranteed by Serialize trait bound")
 }
+
+#[cfg(test)]
+mod test;
@@ -19,9 +19,9 @@ use conversation::Conversation;
 use notification::Notification;
 use serde::Deserialize;
 
-#[serde(rename_all = "snake_case", tag = "event", deny_unknown_fields)]
 #[rustfmt::skip]
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "event", deny_unknown_fields)]
 pub enum CheckedEvent {
     Update { payload: Status, queued_at: Option<i64> },
     Notification { payload: Notification },
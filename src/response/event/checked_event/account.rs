@@ -2,8 +2,8 @@ use super::{emoji::Emoji, visibility::Visibility};
 use crate::Id;
 use serde::{Deserialize, Serialize};
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Account {
     pub id: Id,
     pub(crate) username: String,
@@ -30,16 +30,16 @@ pub(crate) struct Account {
     pub(crate) last_status_at: Option<String>, // undocumented
 }
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Field {
     pub(crate) name: String,
     pub(crate) value: String,
     pub(crate) verified_at: Option<String>,
 }
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Source {
     pub(crate) note: String,
     pub(crate) fields: Vec<Field>,
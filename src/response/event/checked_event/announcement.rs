@@ -1,8 +1,8 @@
 use super::{emoji::Emoji, mention::Mention, tag::Tag, AnnouncementReaction};
 use serde::{Deserialize, Serialize};
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Announcement {
     // Fully undocumented
     id: String,
@@ -1,8 +1,8 @@
 use super::{account::Account, status::Status};
 use serde::{Deserialize, Serialize};
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Conversation {
     id: String,
     accounts: Vec<Account>,
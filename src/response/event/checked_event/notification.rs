@@ -1,8 +1,8 @@
 use super::{account::Account, status::Status};
 use serde::{Deserialize, Serialize};
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Notification {
     id: String,
     r#type: NotificationType,
@@ -11,8 +11,8 @@ pub struct Notification {
     status: Option<Status>,
 }
 
-#[serde(rename_all = "snake_case", deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
 enum NotificationType {
     Follow,
     FollowRequest, // Undocumented
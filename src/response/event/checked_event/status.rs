@@ -19,8 +19,8 @@ use serde::{Deserialize, Serialize};
 use std::boxed::Box;
 use std::string::String;
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Status {
     pub(crate) id: Id,
     pub(crate) uri: String,
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Application {
     pub(crate) name: String,
     pub(crate) website: Option<String>,
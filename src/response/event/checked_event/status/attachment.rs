@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Attachment {
     pub(crate) id: String,
     pub(crate) r#type: AttachmentType,
@@ -14,8 +14,8 @@ pub(crate) struct Attachment {
     pub(crate) blurhash: Option<String>,
 }
 
-#[serde(rename_all = "lowercase", deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
 pub(crate) enum AttachmentType {
     Unknown,
     Image,
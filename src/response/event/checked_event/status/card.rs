@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Card {
     pub(crate) url: String,
     pub(crate) title: String,
@@ -18,8 +18,8 @@ pub(crate) struct Card {
     pub(crate) embed_url: Option<String>,
 }
 
-#[serde(rename_all = "lowercase", deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
 pub(crate) enum CardType {
     Link,
     Photo,
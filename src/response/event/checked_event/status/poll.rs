@@ -1,8 +1,8 @@
 use super::super::emoji::Emoji;
 use serde::{Deserialize, Serialize};
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct Poll {
     pub(crate) id: String,
     pub(crate) expires_at: String,
@@ -16,8 +16,8 @@ pub(crate) struct Poll {
     pub(crate) emojis: Vec<Emoji>,
 }
 
-#[serde(deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct PollOptions {
     pub(crate) title: String,
     pub(crate) votes_count: Option<i32>,
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
-#[serde(rename_all = "lowercase", deny_unknown_fields)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
 pub(crate) enum Visibility {
     Public,
     Unlisted,
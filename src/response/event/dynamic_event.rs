@@ -17,18 +17,13 @@ pub struct DynEvent {
     pub(crate) queued_at: Option<i64>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum EventKind {
     Update(DynStatus),
+    #[default]
     NonUpdate,
 }
 
-impl Default for EventKind {
-    fn default() -> Self {
-        Self::NonUpdate
-    }
-}
-
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DynStatus {
     pub(crate) id: Id,
@@ -0,0 +1,43 @@
+use super::*;
+
+fn stream(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn websocket_events_name_their_stream() {
+    let event = Event::TypeSafe(CheckedEvent::Delete {
+        payload: "1".to_string(),
+    });
+
+    let hashtag = stream(&["hashtag", "rust"]);
+    assert_eq!(
+        event.to_json_string(Some(&hashtag)),
+        r#"{"stream":["hashtag","rust"],"event":"delete","payload":"1"}"#
+    );
+    let list = stream(&["list", "7"]);
+    assert_eq!(
+        event.to_json_string(Some(&list)),
+        r#"{"stream":["list","7"],"event":"delete","payload":"1"}"#
+    );
+}
+
+#[test]
+fn websocket_events_without_a_payload_name_their_stream() {
+    let event = Event::TypeSafe(CheckedEvent::FiltersChanged);
+    assert_eq!(
+        event.to_json_string(Some(&stream(&["user"]))),
+        r#"{"stream":["user"],"event":"filters_changed"}"#
+    );
+}
+
+#[test]
+fn websocket_events_from_unnamed_streams_have_no_stream_field() {
+    let event = Event::TypeSafe(CheckedEvent::Delete {
+        payload: "1".to_string(),
+    });
+    assert_eq!(
+        event.to_json_string(None),
+        r#"{"event":"delete","payload":"1"}"#
+    );
+}
@@ -1,11 +1,16 @@
 mod connection;
+mod input;
 mod manager;
 mod msg;
+mod recording;
+mod streams;
 
-pub(self) use super::{Event, EventErr};
+pub(self) use super::{Event, EventErr, EventSource};
 pub(self) use connection::RedisConn;
+pub use input::RedisInput;
 pub use manager::Error;
 pub use manager::Manager;
+pub use recording::ReplaySource;
 
 #[cfg(feature = "bench")]
 pub use msg::{RedisMsg, RedisParseOutput};
@@ -16,9 +21,16 @@ use msg::RedisParseErr;
 pub(crate) enum RedisCmd {
     Subscribe,
     Unsubscribe,
+    Psubscribe,
+    /// Extend the expiry of the `subscribed:` keys for timelines we remain subscribed to
+    Refresh,
 }
 
 impl RedisCmd {
+    /// How long a `subscribed:` key lasts without being refreshed.  This is three ping cycles,
+    /// so a key survives a delayed refresh but expires soon after Flodgatt stops running.
+    const SUBSCRIBED_KEY_EXPIRY_SECS: u64 = 90;
+
     fn into_sendable(self, timelines: &[String]) -> (Vec<u8>, Vec<u8>) {
         match self {
             RedisCmd::Subscribe => {
@@ -29,17 +41,7 @@ impl RedisCmd {
                     }
                     cmd
                 };
-                let secondary = {
-                    let mut cmd = format!("*{}\r\n$4\r\nMSET\r\n", 1 + timelines.len());
-                    for tl in timelines {
-                        cmd.push_str(&format!(
-                            "${}\r\nsubscribed:{}\r\n$1\r\n$1\r\n",
-                            "subscribed:".len() + tl.len(),
-                            tl
-                        ));
-                    }
-                    cmd
-                };
+                let secondary = Self::set_subscribed_keys(timelines);
                 (primary.as_bytes().to_vec(), secondary.as_bytes().to_vec())
             }
             RedisCmd::Unsubscribe => {
@@ -51,10 +53,10 @@ impl RedisCmd {
                     cmd
                 };
                 let secondary = {
-                    let mut cmd = format!("*{}\r\n$4\r\nMSET\r\n", 1 + timelines.len());
+                    let mut cmd = format!("*{}\r\n$3\r\nDEL\r\n", 1 + timelines.len());
                     for tl in timelines {
                         cmd.push_str(&format!(
-                            "${}\r\nsubscribed:{}\r\n$1\r\n$0\r\n",
+                            "${}\r\nsubscribed:{}\r\n",
                             "subscribed:".len() + tl.len(),
                             tl
                         ));
@@ -63,6 +65,38 @@ impl RedisCmd {
                 };
                 (primary.as_bytes().to_vec(), secondary.as_bytes().to_vec())
             }
+            RedisCmd::Psubscribe => {
+                let primary = {
+                    let mut cmd = format!("*{}\r\n$10\r\npsubscribe\r\n", 1 + timelines.len());
+                    for pattern in timelines {
+                        cmd.push_str(&format!("${}\r\n{}\r\n", pattern.len(), pattern));
+                    }
+                    cmd
+                };
+                // Pattern subscriptions don't need a `subscribed:` key
+                (primary.as_bytes().to_vec(), Vec::new())
+            }
+            RedisCmd::Refresh => {
+                let secondary = Self::set_subscribed_keys(timelines);
+                (Vec::new(), secondary.as_bytes().to_vec())
+            }
+        }
+    }
+
+    /// A `SET subscribed:<timeline> 1 EX <seconds>` for each timeline (Redis has no `MSET`
+    /// with an expiry, so these are pipelined instead)
+    fn set_subscribed_keys(timelines: &[String]) -> String {
+        let expiry = Self::SUBSCRIBED_KEY_EXPIRY_SECS.to_string();
+        let mut cmd = String::new();
+        for tl in timelines {
+            cmd.push_str(&format!(
+                "*5\r\n$3\r\nSET\r\n${}\r\nsubscribed:{}\r\n$1\r\n1\r\n$2\r\nEX\r\n${}\r\n{}\r\n",
+                "subscribed:".len() + tl.len(),
+                tl,
+                expiry.len(),
+                expiry
+            ));
         }
+        cmd
     }
 }
@@ -1,75 +1,234 @@
 mod err;
+mod sentinel;
+mod stream;
 pub(super) use connection::*;
 pub use err::RedisConnErr;
-#[cfg(any(test, feature = "bench"))]
-pub(self) use mock_connection as connection;
 
-#[cfg(not(any(test, feature = "bench")))]
 mod connection {
+    use super::super::msg::{RedisParseErr, RedisParseOutput};
+    use super::super::recording::Recorder;
+    use super::super::streams::Streams;
     use super::super::Error as ManagerErr;
-    use super::super::RedisCmd;
+    use super::super::{Event, EventSource, RedisCmd, RedisInput};
     use super::err::RedisConnErr;
+    use super::sentinel::{ReportedMaster, Sentinels};
+    use super::stream::{self, Addr, RedisStream, Tls};
     use crate::config::Redis;
     use crate::request::Timeline;
 
-    use futures::{Async, Poll};
+    use futures::sync::oneshot;
+    use futures::task::{self, Task};
+    use futures::{Async, Future, Poll};
     use lru::LruCache;
+    use std::convert::TryFrom;
     use std::io::{self, Read, Write};
-    use std::net::TcpStream;
-    use std::time::Duration;
+    use std::sync::{Arc, PoisonError};
+    use std::thread;
 
     type Result<T> = std::result::Result<T, RedisConnErr>;
+    /// The address of the master and the two connections to it, once connected and
+    /// authenticated on the thread started by `reconnect_to_master`
+    type Connected = (Addr, RedisStream, RedisStream);
 
+    /// Connections to Redis (over TCP, TLS, or a Unix socket), registered with the tokio reactor
+    /// so that the `Manager` is woken as soon as Redis sends any data.  This is the
+    /// `EventSource` that Flodgatt uses in production.
+    ///
+    /// Connecting and authenticating is done with blocking IO before the connection is handed
+    /// off to the reactor.  At startup, this blocks the current thread; when reconnecting, it
+    /// happens on a thread of its own so that it never blocks the `Manager`.
+    ///
+    /// Once connected, commands are queued and written by `poll_event` (on the `Manager`'s
+    /// task), so a command that Redis can't accept all at once is finished on a later poll.
     #[derive(Debug)]
     pub struct RedisConn {
-        primary: TcpStream,
-        secondary: TcpStream,
-        pub(in super::super) namespace: Option<String>,
+        primary: RedisStream,
+        secondary: RedisStream,
+        /// Commands not yet written to each connection
+        primary_out: Vec<u8>,
+        secondary_out: Vec<u8>,
+        /// The task that polls us for events, which writes the queued commands
+        task: Option<Task>,
+        addr: Addr,
+        user: Option<String>,
+        password: Option<String>,
+        tls: Option<Tls>,
+        sentinels: Option<Sentinels>,
+        /// The master the Sentinels (if any) most recently reported, to detect a failover
+        reported_master: Option<ReportedMaster>,
+        namespace: Option<String>,
+        /// The pattern for our single `PSUBSCRIBE`, if we subscribe to every timeline at once
+        /// instead of sending a `SUBSCRIBE` for each timeline a client is interested in
+        pattern: Option<String>,
         // TODO: eventually, it might make sense to have Mastodon publish to timelines with
         //       the tag number instead of the tag name.  This would save us from dealing
         //       with a cache here and would be consistent with how lists/users are handled.
-        pub(in super::super) tag_name_cache: LruCache<i64, String>,
-        pub(in super::super) input: Vec<u8>,
+        tag_name_cache: LruCache<i64, String>,
+        input: RedisInput,
+        recorder: Option<Recorder>,
+        reconnecting: Option<oneshot::Receiver<Result<Connected>>>,
     }
 
     impl RedisConn {
         pub(in super::super) fn new(redis_cfg: &Redis) -> Result<Self> {
-            let addr = [&*redis_cfg.host, ":", &*redis_cfg.port.to_string()].concat();
-
-            let conn = Self::new_connection(&addr, redis_cfg.password.as_ref())?;
-            conn.set_nonblocking(true)
-                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            Ok(Self {
-                primary: conn,
-                secondary: Self::new_connection(&addr, redis_cfg.password.as_ref())?,
+            let sentinels = Sentinels::from_cfg(redis_cfg);
+            let addr = match &sentinels {
+                Some(sentinels) => sentinels.master_addr()?,
+                None => match &*redis_cfg.socket {
+                    Some(path) => Addr::Unix(path.clone()),
+                    None => {
+                        Addr::Tcp([&*redis_cfg.host, ":", &*redis_cfg.port.to_string()].concat())
+                    }
+                },
+            };
+
+            let tls = Tls::from_cfg(redis_cfg)?;
+            let (user, password) = (redis_cfg.user.clone().0, redis_cfg.password.clone().0);
+            let auth = password.as_ref().map(|pass| (user.as_ref(), pass));
+            let primary = Self::new_connection(&addr, auth, tls.as_ref())?;
+            let secondary = Self::new_connection(&addr, auth, tls.as_ref())?;
+            let mut conn = Self {
+                primary: Self::make_async(&addr, primary)?,
+                secondary: Self::make_async(&addr, secondary)?,
+                primary_out: Vec::new(),
+                secondary_out: Vec::new(),
+                task: None,
+                user,
+                password,
+                tls,
+                reported_master: sentinels.as_ref().map(Sentinels::watch),
+                sentinels,
+                addr,
                 tag_name_cache: LruCache::new(1000),
                 namespace: redis_cfg.namespace.clone().0,
-                input: vec![0; 4096 * 4],
-            })
-        }
-
-        pub(in super::super) fn poll_redis(&mut self, i: usize) -> Poll<Option<usize>, ManagerErr> {
-            const BLOCK: usize = 4096 * 2;
-            if self.input.len() < i + BLOCK {
-                self.input.resize(self.input.len() * 2, 0);
-                log::info!("Resizing input buffer to {} KiB.", self.input.len() / 1024);
-                // log::info!("Current buffer: {}", String::from_utf8_lossy(&self.input));
-            }
-
-            use Async::*;
-            match self.primary.read(&mut self.input[i..i + BLOCK]) {
-                Ok(n) if n == 0 => Ok(Ready(None)),
-                Ok(n) => Ok(Ready(Some(n))),
-                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock) => Ok(NotReady),
-                Err(e) => {
-                    Ready(log::error!("{}", e));
-                    Ok(Ready(None))
+                pattern: super::pattern_for(redis_cfg),
+                input: RedisInput::new(redis_cfg),
+                recorder: match &*redis_cfg.record {
+                    Some(path) => Some(Recorder::create(path)?),
+                    None => None,
+                },
+                reconnecting: None,
+            };
+            conn.psubscribe();
+            Ok(conn)
+        }
+
+        /// Queue commands for the primary and secondary connections, waking the task that
+        /// writes them
+        fn queue(&mut self, primary_cmd: &[u8], secondary_cmd: &[u8]) {
+            self.primary_out.extend_from_slice(primary_cmd);
+            self.secondary_out.extend_from_slice(secondary_cmd);
+            if let Some(task) = &self.task {
+                task.notify();
+            }
+        }
+
+        /// Write as much of the queued commands as Redis accepts without blocking; the
+        /// reactor wakes the current task once the connections have room for the rest.
+        fn write_queued(&mut self) -> io::Result<()> {
+            stream::write_pending(&mut self.primary, &mut self.primary_out)?;
+            stream::write_pending(&mut self.secondary, &mut self.secondary_out)
+        }
+
+        /// Read (and ignore) Redis's replies to the commands we send on the secondary
+        /// connection so that they don't accumulate in the socket's buffer.
+        fn discard_secondary_replies(&mut self) {
+            let mut buffer = [0_u8; 1024];
+            while let Ok(n) = self.secondary.read(&mut buffer) {
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+
+        /// Replace both connections with new (authenticated and named) connections to the
+        /// current master.  This restores the pattern subscription (if any), but not the
+        /// subscriptions to individual timelines; any commands still queued for the old
+        /// connections are discarded.
+        ///
+        /// The new connections are opened on their own thread; this returns `NotReady` (and
+        /// wakes the current task once they are ready) until then.
+        fn reconnect_to_master(&mut self) -> Poll<(), RedisConnErr> {
+            let mut pending = match self.reconnecting.take() {
+                Some(pending) => pending,
+                None => self.connect_in_background(),
+            };
+            let (addr, primary, secondary) = match pending.poll() {
+                Ok(Async::Ready(connected)) => connected?,
+                Ok(Async::NotReady) => {
+                    self.reconnecting = Some(pending);
+                    return Ok(Async::NotReady);
+                }
+                Err(oneshot::Canceled) => Err(RedisConnErr::UnknownRedisErr(io::Error::other(
+                    "the thread connecting to Redis panicked",
+                )))?,
+            };
+            self.primary = Self::make_async(&addr, primary)?;
+            self.secondary = Self::make_async(&addr, secondary)?;
+            self.addr = addr;
+            // Any partial message (or command) on the old connection will never be completed
+            self.input.clear();
+            self.primary_out.clear();
+            self.secondary_out.clear();
+            if let Some(recorder) = &mut self.recorder {
+                if let Err(e) = recorder.reconnected() {
+                    log::error!("Could not record the input from Redis; stopping: {}", e);
+                    self.recorder = None;
+                }
+            }
+            self.psubscribe();
+            Ok(Async::Ready(()))
+        }
+
+        /// Ask the Sentinels (if any) for the current master and connect to it on a new
+        /// thread, which sends back the connections once they are ready
+        fn connect_in_background(&self) -> oneshot::Receiver<Result<Connected>> {
+            let (tx, rx) = oneshot::channel();
+            let (sentinels, addr) = (self.sentinels.clone(), self.addr.clone());
+            let (user, password, tls) =
+                (self.user.clone(), self.password.clone(), self.tls.clone());
+            thread::spawn(move || {
+                let connect = || -> Result<Connected> {
+                    let addr = match &sentinels {
+                        Some(sentinels) => sentinels.master_addr()?,
+                        None => addr,
+                    };
+                    let auth = password.as_ref().map(|pass| (user.as_ref(), pass));
+                    let primary = Self::new_connection(&addr, auth, tls.as_ref())?;
+                    let secondary = Self::new_connection(&addr, auth, tls.as_ref())?;
+                    Ok((addr, primary, secondary))
+                };
+                // Fails only if the `RedisConn` has been dropped in the meantime
+                let _ = tx.send(connect());
+            });
+            rx
+        }
+
+        /// Subscribe to every timeline with a single `PSUBSCRIBE` (in pattern mode)
+        fn psubscribe(&mut self) {
+            if let Some(pattern) = self.pattern.clone() {
+                let (primary_cmd, _) =
+                    RedisCmd::Psubscribe.into_sendable(std::slice::from_ref(&pattern));
+                self.queue(&primary_cmd, &[]);
+                log::info!("Subscribed to all timelines matching `{}`", pattern);
+            }
+        }
+
+        /// Whether the Sentinels report a different master than the one we are connected to
+        /// (that is, whether there has been a failover).  The Sentinels are asked on their own
+        /// thread, so this doesn't block.
+        fn master_has_changed(&self) -> bool {
+            match &self.reported_master {
+                Some(reported) => {
+                    let reported = reported.lock().unwrap_or_else(PoisonError::into_inner);
+                    matches!(&*reported, Some(master) if *master != self.addr)
                 }
+                None => false,
             }
         }
 
-        pub(crate) fn send_cmd(&mut self, cmd: RedisCmd, timelines: &[Timeline]) -> Result<()> {
-            let namespace = self.namespace.take();
+        fn send_cmd(&mut self, cmd: RedisCmd, timelines: &[Timeline]) -> Result<()> {
+            let namespace = self.namespace.clone();
             let timelines: Result<Vec<String>> = timelines
                 .iter()
                 .map(|tl| {
@@ -81,147 +240,274 @@ mod connection {
                 })
                 .collect();
 
-            let (primary_cmd, secondary_cmd) = cmd.into_sendable(&timelines?[..]);
-            self.primary.write_all(&primary_cmd)?;
+            let timelines = timelines?;
+            if let Some(streams) = &mut self.input.streams {
+                match cmd {
+                    RedisCmd::Subscribe => streams.subscribe(&timelines),
+                    RedisCmd::Unsubscribe => streams.unsubscribe(&timelines),
+                    RedisCmd::Refresh => streams.resume(),
+                    RedisCmd::Psubscribe => (),
+                }
+            }
+
+            let (mut primary_cmd, secondary_cmd) = cmd.into_sendable(&timelines[..]);
+            // In pattern mode, we already receive every timeline through our `PSUBSCRIBE`,
+            // and with Redis Streams, we `XREAD` instead of subscribing
+            if self.pattern.is_some() || self.input.streams.is_some() {
+                primary_cmd.clear();
+            }
 
             // We also need to set a key to tell the Puma server that we've subscribed or
             // unsubscribed to the channel because it stops publishing updates when it thinks
             // no one is subscribed.
             // (Documented in [PR #3278](https://github.com/tootsuite/mastodon/pull/3278))
             // Question: why can't the Puma server just use NUMSUB for this?
-            self.secondary.write_all(&secondary_cmd)?;
+            self.queue(&primary_cmd, &secondary_cmd);
             Ok(())
         }
 
-        fn new_connection(addr: &str, pass: Option<&String>) -> Result<TcpStream> {
-            let mut conn = TcpStream::connect(&addr)?;
-            if let Some(password) = pass {
-                Self::auth_connection(&mut conn, &addr, password)?;
+        /// Open a blocking connection to `addr` and authenticate, validate, and name it
+        fn new_connection(
+            addr: &Addr,
+            auth: Option<(Option<&String>, &String)>,
+            tls: Option<&Tls>,
+        ) -> Result<RedisStream> {
+            let mut conn = RedisStream::connect(addr, tls)?;
+            if let Some((user, password)) = auth {
+                Self::auth_connection(&mut conn, addr, user, password)?;
             }
 
-            Self::validate_connection(&mut conn, &addr)?;
-            conn.set_read_timeout(Some(Duration::from_millis(10)))
-                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            Self::set_connection_name(&mut conn, &addr)?;
+            Self::validate_connection(&mut conn, addr)?;
+            Self::negotiate_resp3(&mut conn, addr)?;
+            Self::set_connection_name(&mut conn, addr)?;
+            Ok(conn)
+        }
+
+        /// Register a connection with the reactor; this must happen on the reactor's thread
+        /// rather than the one that opened the connection.
+        fn make_async(addr: &Addr, mut conn: RedisStream) -> Result<RedisStream> {
+            conn.make_async()
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
             Ok(conn)
         }
 
-        fn auth_connection(conn: &mut TcpStream, addr: &str, pass: &str) -> Result<()> {
-            conn.write_all(
-                &[
-                    b"*2\r\n$4\r\nauth\r\n$",
-                    pass.len().to_string().as_bytes(),
-                    b"\r\n",
-                    pass.as_bytes(),
-                    b"\r\n",
-                ]
-                .concat(),
-            )
-            .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            let mut buffer = vec![0_u8; 5];
-            conn.read_exact(&mut buffer)
-                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            if String::from_utf8_lossy(&buffer) != "+OK\r\n" {
-                Err(RedisConnErr::IncorrectPassword(pass.to_string()))?
+        /// Authenticate with `AUTH <pass>` or, if a user is set, with the Redis 6 ACL form
+        /// `AUTH <user> <pass>`
+        pub(super) fn auth_connection(
+            conn: &mut RedisStream,
+            addr: &Addr,
+            user: Option<&String>,
+            pass: &str,
+        ) -> Result<()> {
+            let args: Vec<&str> = match user {
+                Some(user) => vec!["auth", user, pass],
+                None => vec!["auth", pass],
+            };
+            let mut cmd = format!("*{}\r\n", args.len());
+            for arg in args {
+                cmd.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+            }
+            conn.write_all(cmd.as_bytes())
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+
+            let reply = Self::read_reply(conn, addr)?;
+            match &*reply {
+                "+OK\r\n" => Ok(()),
+                r if r.starts_with("-WRONGPASS") => Err(RedisConnErr::WrongPass(user.cloned())),
+                // Redis versions before 6 reply to a bad password with a generic error
+                r if r.starts_with("-ERR invalid password") => Err(RedisConnErr::IncorrectPassword),
+                r if r.starts_with("-NOPERM") => Err(RedisConnErr::no_perm(r)),
+                r => Err(RedisConnErr::InvalidRedisReply(r.trim_end().to_string())),
             }
-            Ok(())
         }
 
-        fn validate_connection(conn: &mut TcpStream, addr: &str) -> Result<()> {
+        fn validate_connection(conn: &mut RedisStream, addr: &Addr) -> Result<()> {
             conn.write_all(b"PING\r\n")
-                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            let mut buffer = vec![0_u8; 100];
-            conn.read(&mut buffer)
-                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            let reply = String::from_utf8_lossy(&buffer);
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+            let reply = Self::read_reply(conn, addr)?;
             match &*reply {
-                r if r.starts_with("+PONG\r\n") => Ok(()),
+                "+PONG\r\n" => Ok(()),
                 r if r.starts_with("-NOAUTH") => Err(RedisConnErr::MissingPassword),
+                r if r.starts_with("-NOPERM") => Err(RedisConnErr::no_perm(r)),
                 r if r.starts_with("HTTP/1.") => Err(RedisConnErr::NotRedis(addr.to_string())),
-                _ => Err(RedisConnErr::InvalidRedisReply(reply.to_string())),
+                r => Err(RedisConnErr::InvalidRedisReply(r.trim_end().to_string())),
+            }
+        }
+
+        /// Ask Redis to use RESP3 (in which pub/sub messages arrive as push frames, and other
+        /// replies can share the connection).  Redis versions before 6 don't support RESP3 and
+        /// reply with an error, in which case we continue with RESP2.
+        fn negotiate_resp3(conn: &mut RedisStream, addr: &Addr) -> Result<()> {
+            conn.write_all(b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n")
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+
+            // The reply is a map of server properties, which can span several reads
+            let mut reply = Vec::new();
+            let mut buffer = vec![0_u8; 1024];
+            loop {
+                let n = conn
+                    .read(&mut buffer)
+                    .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+                if n == 0 {
+                    Err(RedisConnErr::InvalidRedisReply(
+                        String::from_utf8_lossy(&reply).to_string(),
+                    ))?;
+                }
+                reply.extend_from_slice(&buffer[..n]);
+
+                if reply.starts_with(b"-") {
+                    if reply.ends_with(b"\r\n") {
+                        log::info!("Redis does not support RESP3; using RESP2");
+                        return Ok(());
+                    }
+                    continue;
+                }
+                match RedisParseOutput::try_from(&reply[..]) {
+                    Ok(_) => {
+                        log::info!("Using RESP3 to communicate with Redis");
+                        return Ok(());
+                    }
+                    Err(RedisParseErr::Incomplete) => continue,
+                    Err(_) => Err(RedisConnErr::InvalidRedisReply(
+                        String::from_utf8_lossy(&reply).to_string(),
+                    ))?,
+                }
             }
         }
 
-        fn set_connection_name(conn: &mut TcpStream, addr: &str) -> Result<()> {
+        fn set_connection_name(conn: &mut RedisStream, addr: &Addr) -> Result<()> {
             conn.write_all(b"*3\r\n$6\r\nCLIENT\r\n$7\r\nSETNAME\r\n$8\r\nflodgatt\r\n")
-                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            let mut buffer = vec![0_u8; 100];
-            conn.read(&mut buffer)
-                .map_err(|e| RedisConnErr::with_addr(&addr, e))?;
-            let reply = String::from_utf8_lossy(&buffer);
+                .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+            let reply = Self::read_reply(conn, addr)?;
             match &*reply {
-                r if r.starts_with("+OK\r\n") => Ok(()),
-                _ => Err(RedisConnErr::InvalidRedisReply(reply.to_string())),
+                "+OK\r\n" => Ok(()),
+                r if r.starts_with("-NOPERM") => Err(RedisConnErr::no_perm(r)),
+                r => Err(RedisConnErr::InvalidRedisReply(r.trim_end().to_string())),
             }
         }
-    }
-}
-#[cfg(any(test, feature = "bench"))]
-mod mock_connection {
-    use super::super::Error as ManagerErr;
-    use super::super::RedisCmd;
-    use super::err::RedisConnErr;
-    use crate::config::Redis;
-    use crate::request::Timeline;
-
-    use futures::{Async, Poll};
-    use lru::LruCache;
-    use std::collections::VecDeque;
 
-    type Result<T> = std::result::Result<T, RedisConnErr>;
-
-    #[derive(Debug)]
-    pub struct RedisConn {
-        pub(in super::super) namespace: Option<String>,
-        pub(in super::super) tag_name_cache: LruCache<i64, String>,
-        pub(in super::super) input: Vec<u8>,
-        pub(in super::super) test_input: VecDeque<u8>,
+        /// Read a one-line reply (a simple string or an error), which can span several reads
+        fn read_reply(conn: &mut RedisStream, addr: &Addr) -> Result<String> {
+            let mut reply = Vec::new();
+            let mut buffer = vec![0_u8; 100];
+            while !reply.ends_with(b"\r\n") {
+                let n = conn
+                    .read(&mut buffer)
+                    .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+                if n == 0 {
+                    Err(RedisConnErr::InvalidRedisReply(
+                        String::from_utf8_lossy(&reply).to_string(),
+                    ))?;
+                }
+                reply.extend_from_slice(&buffer[..n]);
+            }
+            Ok(String::from_utf8_lossy(&reply).to_string())
+        }
     }
 
-    impl RedisConn {
-        pub(in super::super) fn new(redis_cfg: &Redis) -> Result<Self> {
-            Ok(Self {
-                tag_name_cache: LruCache::new(1000),
-                namespace: redis_cfg.namespace.clone().0,
-                input: vec![0; 4096 * 4],
-                test_input: VecDeque::new(),
-            })
+    impl EventSource for RedisConn {
+        fn subscribe(&mut self, timelines: &[Timeline]) -> std::result::Result<(), ManagerErr> {
+            if let Some(wanted) = &mut self.input.pattern_timelines {
+                wanted.extend(timelines);
+            }
+            Ok(self.send_cmd(RedisCmd::Subscribe, timelines)?)
         }
 
-        pub fn poll_redis(&mut self, start: usize) -> Poll<Option<usize>, ManagerErr> {
-            const BLOCK: usize = 4096 * 2;
-            if self.input.len() < start + BLOCK {
-                self.input.resize(self.input.len() * 2, 0);
-                log::info!("Resizing input buffer to {} KiB.", self.input.len() / 1024);
+        fn unsubscribe(&mut self, timelines: &[Timeline]) -> std::result::Result<(), ManagerErr> {
+            if let Some(wanted) = &mut self.input.pattern_timelines {
+                for tl in timelines {
+                    wanted.remove(tl);
+                }
             }
+            Ok(self.send_cmd(RedisCmd::Unsubscribe, timelines)?)
+        }
+
+        fn refresh(&mut self, timelines: &[Timeline]) -> std::result::Result<(), ManagerErr> {
+            Ok(self.send_cmd(RedisCmd::Refresh, timelines)?)
+        }
 
-            for i in 0..BLOCK {
-                if let Some(byte) = self.test_input.pop_front() {
-                    self.input[start + i] = byte;
-                } else if i > 0 {
-                    return Ok(Async::Ready(Some(i)));
-                } else {
+        fn poll_event(&mut self) -> Poll<Option<(Timeline, Arc<Event>)>, ManagerErr> {
+            self.task = Some(task::current());
+            self.discard_secondary_replies();
+            loop {
+                if let Some(event) = self.input.next_event()? {
+                    return Ok(Async::Ready(Some(event)));
+                }
+                if let Some(xread) = self.input.streams.as_mut().and_then(Streams::next_xread) {
+                    self.primary_out.extend_from_slice(&xread);
+                }
+                if let Err(e) = self.write_queued() {
+                    log::error!("{}", e);
                     return Ok(Async::Ready(None));
                 }
+                match self
+                    .input
+                    .read_from(&mut Recorder::tee(&mut self.recorder, &mut self.primary))
+                {
+                    Ok(0) => return Ok(Async::Ready(None)),
+                    Ok(_) => continue,
+                    // The reactor will wake us when data arrives
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock) => {
+                        return Ok(Async::NotReady)
+                    }
+                    Err(e) => {
+                        log::error!("{}", e);
+                        return Ok(Async::Ready(None));
+                    }
+                }
             }
-            Ok(Async::Ready(Some(BLOCK)))
         }
 
-        pub fn add(&mut self, input: &[u8]) {
-            for byte in input {
-                self.test_input.push_back(*byte)
+        fn poll_flush(&mut self) -> Poll<(), ManagerErr> {
+            self.write_queued()
+                .map_err(|e| RedisConnErr::with_addr(&self.addr, e))?;
+            if self.primary_out.is_empty() && self.secondary_out.is_empty() {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
             }
         }
-        pub(crate) fn send_cmd(&mut self, cmd: RedisCmd, timelines: &[Timeline]) -> Result<()> {
-            // stub - does nothing; silences some unused-code warnings
-            let timelines: Result<Vec<String>> = timelines
-                .iter()
-                .map(|tl| Ok(tl.to_redis_raw_timeline(None).expect("test")))
-                .collect();
 
-            let _ = cmd.into_sendable(&timelines?);
+        fn reconnect(&mut self) -> Poll<(), ManagerErr> {
+            Ok(self.reconnect_to_master()?)
+        }
 
-            Ok(())
+        fn needs_reconnect(&self) -> bool {
+            self.master_has_changed()
+        }
+
+        fn cache_hashtag(&mut self, id: i64, name: &str) {
+            self.input.tag_id_cache.put(name.to_string(), id);
+            self.tag_name_cache.put(id, name.to_string());
+        }
+
+        fn unread_len(&self) -> usize {
+            self.input.unread_len()
         }
     }
 }
+
+/// The `PSUBSCRIBE` pattern matching every timeline in our namespace (if `REDIS_PSUBSCRIBE`
+/// is set)
+fn pattern_for(redis_cfg: &crate::config::Redis) -> Option<String> {
+    if !*redis_cfg.psubscribe || *redis_cfg.streams {
+        return None;
+    }
+    // Escape any glob characters in the namespace so that they only match themselves
+    let escape = |ns: &str| {
+        ns.chars().fold(String::new(), |mut acc, c| {
+            if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                acc.push('\\');
+            }
+            acc.push(c);
+            acc
+        })
+    };
+    match &*redis_cfg.namespace {
+        Some(ns) => Some(format!("{}:timeline:*", escape(ns))),
+        None => Some("timeline:*".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -6,9 +6,13 @@ pub enum RedisConnErr {
     ConnectionErr { addr: String, inner: std::io::Error },
     InvalidRedisReply(String),
     UnknownRedisErr(std::io::Error),
-    IncorrectPassword(String),
+    IncorrectPassword,
+    WrongPass(Option<String>),
+    NoPerm(String),
     MissingPassword,
     NotRedis(String),
+    NoSentinelMaster(String),
+    TlsErr(String),
     TimelineErr(request::TimelineErr),
 }
 
@@ -20,6 +24,12 @@ impl RedisConnErr {
             inner,
         }
     }
+
+    #[allow(unused)] // Not used during testing due to conditional compilation
+    pub(super) fn no_perm(reply: &str) -> Self {
+        let msg = reply.trim_end_matches(char::from(0)).trim();
+        Self::NoPerm(msg.trim_start_matches("-NOPERM").trim().to_string())
+    }
 }
 
 impl fmt::Display for RedisConnErr {
@@ -38,10 +48,26 @@ impl fmt::Display for RedisConnErr {
             UnknownRedisErr(io_err) => {
                 format!("Unexpected failure communicating with Redis: {}", io_err)
             }
-            IncorrectPassword(attempted_password) => format!(
-                "Incorrect Redis password.  You supplied `{}`.\n \
-                 Please supply correct password with REDIS_PASSWORD environmental variable.",
-                attempted_password
+            IncorrectPassword => "Incorrect Redis password.\n \
+                                  Please supply the correct password with the REDIS_PASSWORD \
+                                  environmental variable."
+                .to_string(),
+            WrongPass(Some(user)) => format!(
+                "Redis rejected the password for user `{}`.\n \
+                 Please supply the correct user and password with the REDIS_USER and \
+                 REDIS_PASSWORD environmental variables.",
+                user
+            ),
+            WrongPass(None) => "Redis rejected the password for the default user.\n \
+                                Please supply the correct password with the REDIS_PASSWORD \
+                                environmental variable (and a user with REDIS_USER if \
+                                needed)."
+                .to_string(),
+            NoPerm(msg) => format!(
+                "The Redis user does not have permission to run a command Flodgatt needs: {}\n \
+                 Flodgatt needs AUTH, PING, CLIENT SETNAME, SUBSCRIBE, UNSUBSCRIBE, and MSET \
+                 (on `subscribed:*` keys).",
+                msg
             ),
             MissingPassword => "Invalid authentication for Redis.  Redis is configured to require \
                                 a password, but you did not provide one. \n\
@@ -52,6 +78,16 @@ impl fmt::Display for RedisConnErr {
                  REDIS_PORT environmental variables and try again.",
                 addr
             ),
+            NoSentinelMaster(master) => format!(
+                "None of the Redis Sentinels could provide the address of master `{}`.  Please \
+                 check the REDIS_SENTINELS and REDIS_SENTINEL_MASTER environmental variables.",
+                master
+            ),
+            TlsErr(msg) => format!(
+                "Could not establish a TLS connection to Redis: {}\n\
+                 Please check the REDIS_TLS* environmental variables.",
+                msg
+            ),
             TimelineErr(inner) => format!("{}", inner),
         };
         write!(f, "{}", msg)
@@ -64,6 +100,12 @@ impl From<request::TimelineErr> for RedisConnErr {
     }
 }
 
+impl From<openssl::error::ErrorStack> for RedisConnErr {
+    fn from(e: openssl::error::ErrorStack) -> RedisConnErr {
+        RedisConnErr::TlsErr(e.to_string())
+    }
+}
+
 impl From<std::io::Error> for RedisConnErr {
     fn from(e: std::io::Error) -> RedisConnErr {
         RedisConnErr::UnknownRedisErr(e)
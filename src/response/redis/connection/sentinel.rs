@@ -0,0 +1,147 @@
+//! Discovering the current Redis master from Redis Sentinel
+use super::err::RedisConnErr;
+use super::stream::{self, Addr};
+use crate::config::Redis;
+
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, RedisConnErr>;
+
+/// The master most recently reported by the Sentinels (updated by `Sentinels::watch`)
+pub(super) type ReportedMaster = Arc<Mutex<Option<Addr>>>;
+
+#[derive(Debug, Clone)]
+pub(super) struct Sentinels {
+    addrs: Vec<String>,
+    master: String,
+}
+
+impl Sentinels {
+    /// How often `watch` asks the Sentinels for the current master
+    #[cfg(not(test))]
+    const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+    #[cfg(test)]
+    const WATCH_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// The Sentinels configured with `REDIS_SENTINELS`, if any
+    pub(super) fn from_cfg(redis_cfg: &Redis) -> Option<Self> {
+        if redis_cfg.sentinels.is_empty() {
+            None
+        } else {
+            Some(Self {
+                addrs: redis_cfg.sentinels.clone().0,
+                master: redis_cfg.sentinel_master.clone().0,
+            })
+        }
+    }
+
+    /// Ask each Sentinel in turn for the address of the current master, returning the first
+    /// address we receive.
+    pub(super) fn master_addr(&self) -> Result<Addr> {
+        for sentinel in &self.addrs {
+            match self.ask(sentinel) {
+                Ok(Some(addr)) => return Ok(Addr::Tcp(addr)),
+                Ok(None) => {
+                    log::warn!("Sentinel {} does not know master {}", sentinel, self.master);
+                }
+                Err(e) => log::warn!("Could not query Sentinel {}: {}", sentinel, e),
+            }
+        }
+        Err(RedisConnErr::NoSentinelMaster(self.master.clone()))
+    }
+
+    /// Ask the Sentinels for the current master every `WATCH_INTERVAL` on a thread of its
+    /// own, so that checking for a failover never blocks.  The thread stops once the returned
+    /// `ReportedMaster` is dropped.
+    pub(super) fn watch(&self) -> ReportedMaster {
+        let reported = Arc::new(Mutex::new(None));
+        let (sentinels, watched) = (self.clone(), Arc::downgrade(&reported));
+        thread::spawn(move || loop {
+            thread::sleep(Self::WATCH_INTERVAL);
+            let master = match sentinels.master_addr() {
+                Ok(master) => master,
+                Err(e) => {
+                    log::warn!("{}", e);
+                    continue;
+                }
+            };
+            match watched.upgrade() {
+                Some(reported) => {
+                    *reported.lock().unwrap_or_else(PoisonError::into_inner) = Some(master);
+                }
+                None => return,
+            }
+        });
+        reported
+    }
+
+    fn ask(&self, sentinel: &str) -> Result<Option<String>> {
+        let mut conn =
+            stream::connect_tcp(sentinel).map_err(|e| RedisConnErr::with_addr(sentinel, e))?;
+        conn.set_read_timeout(Some(stream::TIMEOUT))
+            .and_then(|()| conn.set_write_timeout(Some(stream::TIMEOUT)))
+            .map_err(|e| RedisConnErr::with_addr(sentinel, e))?;
+        conn.write_all(
+            &[
+                b"*3\r\n$8\r\nSENTINEL\r\n$23\r\nget-master-addr-by-name\r\n$",
+                self.master.len().to_string().as_bytes(),
+                b"\r\n",
+                self.master.as_bytes(),
+                b"\r\n",
+            ]
+            .concat(),
+        )
+        .map_err(|e| RedisConnErr::with_addr(sentinel, e))?;
+
+        // The reply may arrive in several reads
+        let mut reply = Vec::new();
+        let mut buffer = vec![0_u8; 512];
+        loop {
+            let n = conn
+                .read(&mut buffer)
+                .map_err(|e| RedisConnErr::with_addr(sentinel, e))?;
+            if n == 0 {
+                Err(RedisConnErr::InvalidRedisReply(
+                    String::from_utf8_lossy(&reply).to_string(),
+                ))?;
+            }
+            reply.extend_from_slice(&buffer[..n]);
+            if let Some(master) = parse_reply(&String::from_utf8_lossy(&reply)) {
+                return master;
+            }
+        }
+    }
+}
+
+/// Parse a reply to `SENTINEL get-master-addr-by-name`, which is either a nil array (`*-1`) or
+/// an array of host and port: `*2\r\n$9\r\n127.0.0.1\r\n$4\r\n6379\r\n`.
+///
+/// Returns `None` if the reply is incomplete.
+fn parse_reply(reply: &str) -> Option<Result<Option<String>>> {
+    if !reply.ends_with("\r\n") {
+        return None;
+    }
+    let invalid = || Some(Err(RedisConnErr::InvalidRedisReply(reply.to_string())));
+    match reply.split("\r\n").collect::<Vec<_>>()[..] {
+        ["*-1", ""] => Some(Ok(None)),
+        ["*2", host_len, host, port_len, port, ""] => {
+            if host_len != format!("${}", host.len()) || port_len != format!("${}", port.len()) {
+                return invalid();
+            }
+            // IPv6 addresses need brackets to be combined with a port
+            if host.contains(':') {
+                Some(Ok(Some(format!("[{}]:{}", host, port))))
+            } else {
+                Some(Ok(Some(format!("{}:{}", host, port))))
+            }
+        }
+        ["*2", ..] if reply.matches("\r\n").count() < 5 => None,
+        _ => invalid(),
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,55 @@
+use super::*;
+use std::net::TcpListener;
+
+#[test]
+fn sentinel_replies_are_parsed_once_complete() {
+    let reply = "*2\r\n$9\r\n127.0.0.1\r\n$4\r\n6379\r\n";
+    for end in 0..reply.len() {
+        assert!(parse_reply(&reply[..end]).is_none(), "`{}`", &reply[..end]);
+    }
+    assert_eq!(
+        parse_reply(reply).map(Result::ok),
+        Some(Some(Some("127.0.0.1:6379".into())))
+    );
+    assert_eq!(parse_reply("*-1\r\n").map(Result::ok), Some(Some(None)));
+    assert!(matches!(parse_reply("-ERR unknown\r\n"), Some(Err(_))));
+    assert!(matches!(
+        parse_reply("*2\r\n$3\r\n127.0.0.1\r\n$4\r\n6379\r\n"),
+        Some(Err(_))
+    ));
+}
+
+#[test]
+fn sentinel_ipv6_masters_are_bracketed() {
+    let reply = "*2\r\n$3\r\n::1\r\n$4\r\n6379\r\n";
+    assert_eq!(
+        parse_reply(reply).map(Result::ok),
+        Some(Some(Some("[::1]:6379".into())))
+    );
+}
+
+#[test]
+fn sentinel_reply_can_span_several_reads() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let sentinel = listener.local_addr()?.to_string();
+    let server = thread::spawn(move || -> std::io::Result<()> {
+        let (mut conn, _) = listener.accept()?;
+        let mut buffer = [0_u8; 512];
+        let _ = conn.read(&mut buffer)?;
+        conn.write_all(b"*2\r\n$9\r\n127.0")?;
+        conn.flush()?;
+        thread::sleep(Duration::from_millis(50));
+        conn.write_all(b".0.1\r\n$4\r\n6379\r\n")
+    });
+
+    let sentinels = Sentinels {
+        addrs: vec![sentinel],
+        master: "mymaster".to_string(),
+    };
+    assert_eq!(
+        sentinels.master_addr().map_err(|e| e.to_string())?,
+        Addr::Tcp("127.0.0.1:6379".to_string())
+    );
+    server.join().expect("the Sentinel not to panic")?;
+    Ok(())
+}
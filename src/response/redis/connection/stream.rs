@@ -0,0 +1,275 @@
+//! The streams underlying a `RedisConn`, which may be TCP (optionally encrypted with TLS) or
+//! Unix sockets
+use super::err::RedisConnErr;
+use crate::config::Redis;
+
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{self, ToSocketAddrs};
+use std::os::unix::net as unix;
+use std::time::Duration;
+use tokio::net::{TcpStream, UnixStream};
+use tokio::reactor::Handle;
+
+type Result<T> = std::result::Result<T, RedisConnErr>;
+
+/// How long to wait for Redis (or a Sentinel) to accept a connection, and then for each of its
+/// replies while connecting, before giving up
+pub(super) const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where Redis is listening
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Addr {
+    /// A `host:port` address
+    Tcp(String),
+    /// The path to a Unix socket
+    Unix(String),
+}
+
+impl AsRef<str> for Addr {
+    fn as_ref(&self) -> &str {
+        match self {
+            Addr::Tcp(addr) | Addr::Unix(addr) => addr,
+        }
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_ref())
+    }
+}
+
+/// A socket that blocks while we connect to (and authenticate with) Redis and that is then
+/// registered with the tokio reactor.
+#[derive(Debug)]
+pub(super) enum Socket {
+    Tcp(net::TcpStream),
+    Unix(unix::UnixStream),
+    AsyncTcp(TcpStream),
+    AsyncUnix(UnixStream),
+}
+
+impl Socket {
+    fn make_async(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(conn) => {
+                let conn = conn.try_clone()?;
+                conn.set_nonblocking(true)?;
+                *self = Socket::AsyncTcp(TcpStream::from_std(conn, &Handle::default())?);
+            }
+            Socket::Unix(conn) => {
+                let conn = conn.try_clone()?;
+                conn.set_nonblocking(true)?;
+                *self = Socket::AsyncUnix(UnixStream::from_std(conn, &Handle::default())?);
+            }
+            Socket::AsyncTcp(_) | Socket::AsyncUnix(_) => (),
+        }
+        Ok(())
+    }
+
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(conn) => {
+                conn.set_read_timeout(timeout)?;
+                conn.set_write_timeout(timeout)
+            }
+            Socket::Unix(conn) => {
+                conn.set_read_timeout(timeout)?;
+                conn.set_write_timeout(timeout)
+            }
+            Socket::AsyncTcp(_) | Socket::AsyncUnix(_) => Ok(()),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(conn) => conn.read(buf),
+            Socket::Unix(conn) => conn.read(buf),
+            Socket::AsyncTcp(conn) => conn.read(buf),
+            Socket::AsyncUnix(conn) => conn.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(conn) => conn.write(buf),
+            Socket::Unix(conn) => conn.write(buf),
+            Socket::AsyncTcp(conn) => conn.write(buf),
+            Socket::AsyncUnix(conn) => conn.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(conn) => conn.flush(),
+            Socket::Unix(conn) => conn.flush(),
+            Socket::AsyncTcp(conn) => conn.flush(),
+            Socket::AsyncUnix(conn) => conn.flush(),
+        }
+    }
+}
+
+/// Connect to the first of the addresses that `addr` resolves to that accepts a connection
+/// within the `TIMEOUT`
+pub(super) fn connect_tcp(addr: &str) -> io::Result<net::TcpStream> {
+    let mut last_err = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        match net::TcpStream::connect_timeout(&socket_addr, TIMEOUT) {
+            Ok(conn) => return Ok(conn),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "the address did not resolve")
+    }))
+}
+
+/// Write as much of `pending` to `conn` as it accepts without blocking, removing what was
+/// written.  The rest stays at the front of `pending`, so the next attempt starts with the
+/// same bytes (as a TLS stream requires after `WouldBlock`).
+pub(super) fn write_pending<W: Write>(conn: &mut W, pending: &mut Vec<u8>) -> io::Result<()> {
+    while !pending.is_empty() {
+        match conn.write(pending) {
+            Ok(0) => Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "Redis is no longer accepting commands",
+            ))?,
+            Ok(n) => {
+                pending.drain(..n);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e)?,
+        }
+    }
+    Ok(())
+}
+
+/// The TLS settings for connecting to Redis
+#[derive(Clone)]
+pub(super) struct Tls {
+    connector: SslConnector,
+    verify: bool,
+}
+
+impl Tls {
+    /// The TLS settings from the `REDIS_TLS*` environmental variables (`None` if TLS is off)
+    pub(super) fn from_cfg(redis_cfg: &Redis) -> Result<Option<Self>> {
+        if !*redis_cfg.tls {
+            return Ok(None);
+        }
+
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+        if let Some(ca_file) = &*redis_cfg.tls_ca_file {
+            builder.set_ca_file(ca_file)?;
+        }
+        match (&*redis_cfg.tls_cert_file, &*redis_cfg.tls_key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                builder.set_certificate_chain_file(cert_file)?;
+                builder.set_private_key_file(key_file, SslFiletype::PEM)?;
+                builder.check_private_key()?;
+            }
+            (None, None) => (),
+            _ => Err(RedisConnErr::TlsErr(
+                "REDIS_TLS_CERT_FILE and REDIS_TLS_KEY_FILE must be set together".to_string(),
+            ))?,
+        }
+
+        let verify = !*redis_cfg.tls_insecure;
+        if !verify {
+            log::warn!("Not verifying the TLS certificate presented by Redis");
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+        Ok(Some(Self {
+            connector: builder.build(),
+            verify,
+        }))
+    }
+}
+
+impl std::fmt::Debug for Tls {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Tls {{ verify: {} }}", self.verify)
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum RedisStream {
+    Plain(Socket),
+    Tls(SslStream<Socket>),
+}
+
+impl RedisStream {
+    /// Open a blocking connection to `addr`, completing the TLS handshake if `tls` is set.
+    ///
+    /// Connecting, and every read or write until the stream is made async, times out after
+    /// the `TIMEOUT`.
+    pub(super) fn connect(addr: &Addr, tls: Option<&Tls>) -> Result<Self> {
+        let socket = match addr {
+            Addr::Tcp(addr) => connect_tcp(addr).map(Socket::Tcp),
+            Addr::Unix(path) => unix::UnixStream::connect(path).map(Socket::Unix),
+        }
+        .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+        socket
+            .set_timeouts(Some(TIMEOUT))
+            .map_err(|e| RedisConnErr::with_addr(addr, e))?;
+
+        match (addr, tls) {
+            (_, None) => Ok(RedisStream::Plain(socket)),
+            (Addr::Unix(_), Some(_)) => Err(RedisConnErr::TlsErr(
+                "TLS is not supported for connections over a Unix socket".to_string(),
+            )),
+            (Addr::Tcp(addr), Some(tls)) => {
+                let host = addr.rsplitn(2, ':').last().unwrap_or(addr);
+                let host = host.trim_start_matches('[').trim_end_matches(']'); // IPv6
+                let stream = tls
+                    .connector
+                    .configure()?
+                    .verify_hostname(tls.verify)
+                    .connect(host, socket)
+                    .map_err(|e| RedisConnErr::TlsErr(format!("{} ({})", e, addr)))?;
+                Ok(RedisStream::Tls(stream))
+            }
+        }
+    }
+
+    /// Register the stream with the tokio reactor; reads that would block will then return
+    /// `WouldBlock` and wake the current task once the stream is ready.
+    pub(super) fn make_async(&mut self) -> io::Result<()> {
+        match self {
+            RedisStream::Plain(socket) => socket.make_async(),
+            RedisStream::Tls(stream) => stream.get_mut().make_async(),
+        }
+    }
+}
+
+impl Read for RedisStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            RedisStream::Plain(socket) => socket.read(buf),
+            RedisStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for RedisStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RedisStream::Plain(socket) => socket.write(buf),
+            RedisStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RedisStream::Plain(socket) => socket.flush(),
+            RedisStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
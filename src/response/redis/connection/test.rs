@@ -0,0 +1,333 @@
+use super::stream::{self, Addr, RedisStream};
+use super::{RedisConn, RedisConnErr};
+use crate::config;
+use crate::request::{Subscription, Timeline};
+use crate::response::redis::Manager;
+use crate::response::EventSource;
+
+use futures::future::{self, Future};
+use lru::LruCache;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc as tokio_mpsc;
+
+type TestResult = std::result::Result<(), Box<dyn std::error::Error>>;
+
+/// Parse the first complete command (an array of bulk strings or an inline command) in `buf`,
+/// returning its arguments and length
+fn next_cmd(buf: &str) -> Option<(Vec<String>, usize)> {
+    let line_end = buf.find("\r\n")?;
+    let first_line = &buf[..line_end];
+    if !first_line.starts_with('*') {
+        let args = first_line.split(' ').map(String::from).collect();
+        return Some((args, line_end + 2));
+    }
+
+    let mut pos = line_end + 2;
+    let mut args = Vec::new();
+    for _ in 0..first_line[1..].parse().ok()? {
+        let len_end = pos + buf[pos..].find("\r\n")?;
+        let len: usize = buf[pos + 1..len_end].parse().ok()?;
+        let start = len_end + 2;
+        args.push(buf.get(start..start + len)?.to_string());
+        pos = start + len + 2;
+    }
+    if buf.len() < pos {
+        return None;
+    }
+    Some((args, pos))
+}
+
+/// A stand-in for Redis (version 5, so without RESP3) that completes the handshake on each
+/// connection and sends every other command it receives to the returned channel
+fn fake_redis() -> io::Result<(String, Receiver<Vec<String>>)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port().to_string();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            let (mut conn, tx) = match conn {
+                Ok(conn) => (conn, tx.clone()),
+                Err(_) => return,
+            };
+            thread::spawn(move || -> io::Result<()> {
+                let (mut input, mut buffer) = (String::new(), [0_u8; 1024]);
+                loop {
+                    let n = conn.read(&mut buffer)?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    input.push_str(&String::from_utf8_lossy(&buffer[..n]));
+                    while let Some((cmd, len)) = next_cmd(&input) {
+                        input.drain(..len);
+                        match cmd[0].to_uppercase().as_str() {
+                            "PING" => conn.write_all(b"+PONG\r\n")?,
+                            "HELLO" => conn.write_all(b"-ERR unknown command `HELLO`\r\n")?,
+                            "CLIENT" => conn.write_all(b"+OK\r\n")?,
+                            _ => {
+                                let _ = tx.send(cmd);
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+    Ok((port, rx))
+}
+
+fn redis_cfg(port: &str) -> Result<config::Redis, config::Error> {
+    let env = vec![("REDIS_HOST", "127.0.0.1"), ("REDIS_PORT", port)];
+    let env = env.into_iter().map(|(k, v)| (k.to_string(), v.to_string()));
+    let (_, redis_cfg, _) = config::from_env(env.collect())?;
+    Ok(redis_cfg)
+}
+
+/// A socket with room for only a few bytes at a time, which (like a TLS stream) requires that
+/// a write that would block is retried with the same bytes
+#[derive(Default)]
+struct CongestedSocket {
+    received: Vec<u8>,
+    room: usize,
+    refused: Option<Vec<u8>>,
+}
+
+impl Write for CongestedSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(refused) = self.refused.take() {
+            assert!(buf.starts_with(&refused), "a refused write was not retried");
+        }
+        if self.room == 0 {
+            self.refused = Some(buf.to_vec());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = self.room.min(buf.len());
+        self.received.extend_from_slice(&buf[..n]);
+        self.room -= n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn commands_that_would_block_are_finished_on_later_writes() -> io::Result<()> {
+    let subscribe = b"*2\r\n$9\r\nsubscribe\r\n$15\r\ntimeline:public\r\n";
+    let unsubscribe = b"*2\r\n$11\r\nunsubscribe\r\n$15\r\ntimeline:public\r\n";
+    let mut socket = CongestedSocket {
+        room: 10,
+        ..CongestedSocket::default()
+    };
+
+    let mut pending = subscribe.to_vec();
+    stream::write_pending(&mut socket, &mut pending)?;
+    assert_eq!(socket.received, &subscribe[..10]);
+    assert_eq!(pending, &subscribe[10..]);
+
+    // Another command is queued before the socket has room again
+    pending.extend_from_slice(unsubscribe);
+    let mut writes = 0;
+    while !pending.is_empty() {
+        writes += 1;
+        socket.room = 7;
+        stream::write_pending(&mut socket, &mut pending)?;
+    }
+    assert!(writes > 1);
+    assert_eq!(socket.received, [&subscribe[..], &unsubscribe[..]].concat());
+    Ok(())
+}
+
+#[test]
+fn a_socket_that_accepts_nothing_is_an_error() {
+    struct Closed;
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut pending = b"PING\r\n".to_vec();
+    let err = stream::write_pending(&mut Closed, &mut pending).expect_err("an error");
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    assert_eq!(pending, b"PING\r\n");
+}
+
+#[test]
+fn unsubscribe_all_deletes_every_subscribed_key() -> TestResult {
+    let (port, commands) = fake_redis()?;
+    let mut manager = Manager::try_from(&redis_cfg(&port)?)?;
+    let (event_tx, _event_rx) = tokio_mpsc::channel(10);
+    let client_id = manager.add_client(event_tx);
+    for tl in &["public", "public:local"] {
+        let timeline = Timeline::from_redis_text(tl, &mut LruCache::new(1))?;
+        let subscription = Subscription {
+            timeline,
+            ..Subscription::default()
+        };
+        manager.subscribe(client_id, &subscription);
+    }
+
+    manager.unsubscribe_all();
+    future::poll_fn(|| manager.poll_flush()).wait()?;
+
+    let del = loop {
+        let cmd = commands.recv_timeout(Duration::from_secs(5))?;
+        if cmd[0] == "DEL" {
+            break cmd;
+        }
+    };
+    let mut keys = del[1..].to_vec();
+    keys.sort();
+    assert_eq!(
+        keys,
+        vec![
+            "subscribed:timeline:public",
+            "subscribed:timeline:public:local"
+        ]
+    );
+    Ok(())
+}
+
+/// Authenticate against a server that sends `reply` to `AUTH` in two parts
+fn auth_with_reply(reply: &'static [u8]) -> Result<(), RedisConnErr> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = Addr::Tcp(listener.local_addr()?.to_string());
+    thread::spawn(move || -> io::Result<()> {
+        let (mut conn, _) = listener.accept()?;
+        let mut buffer = [0_u8; 1024];
+        let _ = conn.read(&mut buffer)?;
+        let (start, end) = reply.split_at(reply.len() / 2);
+        conn.write_all(start)?;
+        thread::sleep(Duration::from_millis(50));
+        conn.write_all(end)
+    });
+
+    let mut conn = RedisStream::connect(&addr, None)?;
+    let user = String::from("flodgatt");
+    RedisConn::auth_connection(&mut conn, &addr, Some(&user), "hunter2")
+}
+
+#[test]
+fn auth_accepts_ok_even_when_split_across_reads() {
+    assert!(auth_with_reply(b"+OK\r\n").is_ok());
+}
+
+#[test]
+fn auth_reports_a_wrong_password() {
+    let reply = b"-WRONGPASS invalid username-password pair or user is disabled.\r\n";
+    match auth_with_reply(reply) {
+        Err(RedisConnErr::WrongPass(Some(user))) => assert_eq!(user, "flodgatt"),
+        other => panic!("expected WrongPass, got {:?}", other),
+    }
+}
+
+#[test]
+fn auth_reports_a_missing_permission() {
+    let reply = b"-NOPERM this user has no permissions to run the 'auth' command\r\n";
+    match auth_with_reply(reply) {
+        Err(RedisConnErr::NoPerm(msg)) => {
+            assert_eq!(
+                msg,
+                "this user has no permissions to run the 'auth' command"
+            );
+        }
+        other => panic!("expected NoPerm, got {:?}", other),
+    }
+}
+
+#[test]
+fn auth_reports_an_unexpected_reply_without_the_password() {
+    match auth_with_reply(b"-ERR something else went wrong\r\n") {
+        Err(e @ RedisConnErr::InvalidRedisReply(_)) => {
+            assert!(e.to_string().contains("-ERR something else went wrong"));
+            assert!(!e.to_string().contains("hunter2"));
+        }
+        other => panic!("expected InvalidRedisReply, got {:?}", other),
+    }
+}
+
+/// A stand-in for a Redis Sentinel that reports `master` (a `host:port` address) as the
+/// current master
+fn fake_sentinel(master: Arc<Mutex<String>>) -> io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?.to_string();
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            let mut conn = match conn {
+                Ok(conn) => conn,
+                Err(_) => return,
+            };
+            let mut buffer = [0_u8; 512];
+            let _ = conn.read(&mut buffer);
+            let master = master
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone();
+            let mut parts = master.splitn(2, ':');
+            let (host, port) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            let reply = format!(
+                "*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
+                host.len(),
+                host,
+                port.len(),
+                port
+            );
+            let _ = conn.write_all(reply.as_bytes());
+        }
+    });
+    Ok(addr)
+}
+
+#[test]
+fn connection_follows_the_master_reported_by_the_sentinels() -> TestResult {
+    let (old_port, old_commands) = fake_redis()?;
+    let (new_port, new_commands) = fake_redis()?;
+    let master = Arc::new(Mutex::new(format!("127.0.0.1:{}", old_port)));
+    let sentinel = fake_sentinel(master.clone())?;
+    let env = vec![
+        ("REDIS_SENTINELS", sentinel.as_str()),
+        ("REDIS_SENTINEL_MASTER", "mymaster"),
+    ];
+    let env = env.into_iter().map(|(k, v)| (k.to_string(), v.to_string()));
+    let (_, redis_cfg, _) = config::from_env(env.collect())?;
+
+    let mut conn = RedisConn::new(&redis_cfg).map_err(|e| e.to_string())?;
+    thread::sleep(Duration::from_millis(100)); // the Sentinels report the same master
+    assert!(!conn.needs_reconnect());
+
+    // After a failover, the Sentinels are asked for the master on their own thread
+    *master.lock().unwrap_or_else(PoisonError::into_inner) = format!("127.0.0.1:{}", new_port);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !conn.needs_reconnect() {
+        assert!(
+            Instant::now() < deadline,
+            "the new master was never reported"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    future::poll_fn(|| conn.reconnect()).wait()?;
+    assert!(!conn.needs_reconnect());
+    let tl = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    conn.subscribe(&[tl])?;
+    future::poll_fn(|| conn.poll_flush()).wait()?;
+
+    let subscribe = loop {
+        let cmd = new_commands.recv_timeout(Duration::from_secs(5))?;
+        if cmd[0] == "subscribe" {
+            break cmd;
+        }
+    };
+    assert_eq!(subscribe, vec!["subscribe", "timeline:public"]);
+    assert!(old_commands.try_iter().all(|cmd| cmd[0] != "subscribe"));
+    Ok(())
+}
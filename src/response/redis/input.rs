@@ -0,0 +1,273 @@
+//! The input read from Redis, which is parsed into events as soon as complete messages
+//! arrive.
+use super::msg::{self, RedisMsg, RedisParseErr, RedisParseOutput, StreamEntry};
+use super::streams::Streams;
+use super::{Error, Event};
+use crate::config;
+use crate::request::Timeline;
+
+use futures::{Async, Poll};
+use hashbrown::HashSet;
+use lru::LruCache;
+use std::collections::VecDeque;
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Read};
+use std::sync::Arc;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Input read from Redis that has not yet been parsed into events
+#[derive(Debug)]
+pub struct RedisInput {
+    buffer: Vec<u8>,
+    unread_idx: (usize, usize),
+    namespace: Option<String>,
+    pub(super) tag_id_cache: LruCache<String, i64>,
+    /// In pattern mode, we receive events for every timeline; this holds the timelines
+    /// that anyone actually wants events for
+    pub(super) pattern_timelines: Option<HashSet<Timeline>>,
+    /// The streams we read from (if we read from Redis Streams instead of pub/sub)
+    pub(super) streams: Option<Streams>,
+    /// Events from an `XREAD` reply that have not yet been returned
+    parsed: VecDeque<(Timeline, Arc<Event>)>,
+}
+
+impl RedisInput {
+    pub fn new(redis_cfg: &config::Redis) -> Self {
+        let streams = Streams::from_cfg(redis_cfg);
+        let receives_every_timeline = match &streams {
+            Some(streams) => streams.sharded(),
+            None => *redis_cfg.psubscribe,
+        };
+        Self {
+            buffer: vec![0; 4096 * 4],
+            unread_idx: (0, 0),
+            namespace: redis_cfg.namespace.clone().0,
+            tag_id_cache: LruCache::new(1000),
+            pattern_timelines: if receives_every_timeline {
+                Some(HashSet::new())
+            } else {
+                None
+            },
+            streams,
+            parsed: VecDeque::new(),
+        }
+    }
+
+    /// Read from `redis` into the end of the buffer (growing the buffer if needed).  Returns
+    /// the number of bytes read; `0` means that Redis closed the connection.
+    pub(super) fn read_from(&mut self, redis: &mut impl Read) -> io::Result<usize> {
+        const BLOCK: usize = 4096 * 2;
+        let i = self.unread_idx.1;
+        if self.buffer.len() < i + BLOCK {
+            self.buffer.resize(self.buffer.len() * 2, 0);
+            log::info!("Resizing input buffer to {} KiB.", self.buffer.len() / 1024);
+        }
+
+        let n = redis.read(&mut self.buffer[i..i + BLOCK])?;
+        self.unread_idx.1 += n;
+        Ok(n)
+    }
+
+    /// Add `input` as though it had been read from Redis
+    pub fn add(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            self.read_from(&mut input)
+                .expect("reading from a slice can't fail");
+        }
+    }
+
+    /// Discard any partial message (e.g., one left over from a closed connection)
+    pub(super) fn clear(&mut self) {
+        self.unread_idx = (0, 0);
+        if let Some(streams) = &mut self.streams {
+            streams.reset();
+        }
+    }
+
+    /// The number of bytes that have been read but not yet parsed
+    pub(super) fn unread_len(&self) -> usize {
+        self.unread_idx.1 - self.unread_idx.0
+    }
+
+    /// Parse the next event out of the input that has already been read, skipping any
+    /// replies that aren't events.  Returns `Ok(None)` once more input is needed.
+    pub fn next_event(&mut self) -> Result<Option<(Timeline, Arc<Event>)>> {
+        if let Some(event) = self.parsed.pop_front() {
+            return Ok(Some(event));
+        }
+        loop {
+            match self.poll_buffer()? {
+                Async::Ready(Some(event)) => return Ok(Some(event)),
+                Async::Ready(None) => continue, // not an event (e.g., a subscription confirmation)
+                Async::NotReady => return Ok(None),
+            }
+        }
+    }
+
+    /// Parse the next message out of the input.
+    ///
+    /// Returns `Ready(None)` for messages that aren't events and `NotReady` when more input is
+    /// needed.
+    fn poll_buffer(&mut self) -> Poll<Option<(Timeline, Arc<Event>)>, Error> {
+        let input = &self.buffer[self.unread_idx.0..self.unread_idx.1];
+        if input.is_empty() {
+            self.unread_idx = (0, 0);
+            return Ok(Async::NotReady);
+        }
+
+        use RedisParseOutput::*;
+        match RedisParseOutput::try_from(input) {
+            Ok(Msg(msg)) => {
+                // If we get a message and it matches the redis_namespace, get the msg's
+                // Event and send it to all channels matching the msg's Timeline
+                self.unread_idx.0 = self.unread_idx.1 - msg.leftover_input.len();
+                let tl = match msg.timeline_matching_ns(&self.namespace) {
+                    Some(tl) => tl,
+                    None => return Ok(Async::Ready(None)),
+                };
+                let event = Self::parse_event(
+                    tl,
+                    msg.event_txt,
+                    &mut self.tag_id_cache,
+                    &self.pattern_timelines,
+                )?;
+                Ok(Async::Ready(event))
+            }
+            Ok(StreamEntries(entries, leftover_input)) => {
+                self.unread_idx.0 = self.unread_idx.1 - leftover_input.len();
+                let sharded = self.streams.as_ref().is_some_and(Streams::sharded);
+                for entry in entries {
+                    if let Some(streams) = &mut self.streams {
+                        streams.read_entry(entry.stream, entry.id);
+                    }
+                    match Self::parse_stream_entry(
+                        &entry,
+                        sharded,
+                        &self.namespace,
+                        &mut self.tag_id_cache,
+                        &self.pattern_timelines,
+                    ) {
+                        Ok(Some(event)) => self.parsed.push_back(event),
+                        Ok(None) => (),
+                        // Keep going, so that one bad entry doesn't prevent reading the rest
+                        Err(e) => log::error!("{}", e),
+                    }
+                }
+                if let Some(streams) = &mut self.streams {
+                    streams.replied(true);
+                }
+                Ok(Async::Ready(self.parsed.pop_front()))
+            }
+            Ok(reply) => {
+                self.unread_idx.0 = self.unread_idx.1 - reply.leftover_input().len();
+                // With Redis Streams, every reply is to an `XREAD` (which replies with null
+                // when no entries arrived before it stopped blocking)
+                if let Some(streams) = &mut self.streams {
+                    streams.replied(!matches!(reply, RedisErr(..)));
+                }
+                match reply {
+                    Subscription(reply, _) => log::info!(
+                        "Redis confirmed {:?} for {:?} ({} active subscriptions)",
+                        reply.kind,
+                        reply.channel.unwrap_or("all channels"),
+                        reply.count
+                    ),
+                    RedisErr(msg, _) => log::error!("Redis replied with an error: {}", msg),
+                    Pong(_) | NonMsg(_) | Msg(_) | StreamEntries(..) => (),
+                }
+                Ok(Async::Ready(None))
+            }
+            Err(RedisParseErr::Incomplete) => {
+                self.copy_partial_msg();
+                Ok(Async::NotReady)
+            }
+            Err(e) => match msg::skip_reply(input) {
+                // Skip only the malformed reply, so that the replies after it are still read
+                Ok(leftover_input) => {
+                    let reply = &input[..input.len() - leftover_input.len()];
+                    let reply = String::from_utf8_lossy(reply).to_string();
+                    self.unread_idx.0 = self.unread_idx.1 - leftover_input.len();
+                    if let Some(streams) = &mut self.streams {
+                        streams.replied(false);
+                    }
+                    Err(Error::RedisParseErr(e, reply))
+                }
+                // We can't find the end of a malformed reply, so discard the input
+                Err(_) => {
+                    let input = String::from_utf8_lossy(input).to_string();
+                    self.unread_idx = (0, 0);
+                    Err(Error::RedisParseErr(e, input))
+                }
+            },
+        }
+    }
+
+    /// Parse an event published to the timeline `tl_txt` (without its namespace), returning
+    /// `None` if no one wants events for that timeline
+    fn parse_event(
+        tl_txt: &str,
+        event_txt: &str,
+        tag_id_cache: &mut LruCache<String, i64>,
+        pattern_timelines: &Option<HashSet<Timeline>>,
+    ) -> Result<Option<(Timeline, Arc<Event>)>> {
+        let tl = match (
+            Timeline::from_redis_text(tl_txt, tag_id_cache),
+            pattern_timelines,
+        ) {
+            (Ok(tl), Some(wanted)) if !wanted.contains(&tl) => {
+                return Ok(None); // skip parsing events no one wants
+            }
+            (Ok(tl), _) => tl,
+            // With a pattern subscription, we receive timelines we can't parse
+            // (e.g., hashtags no client has asked for); no one wants those events
+            (Err(_), Some(_)) => return Ok(None),
+            (Err(e), None) => Err(e)?,
+        };
+        let event: Arc<Event> = Arc::new(event_txt.try_into()?);
+        Ok(Some((tl, event)))
+    }
+
+    /// Parse the event in a stream entry.  Entries in sharded streams must name their
+    /// timeline; otherwise, the stream is named after the timeline (like a pub/sub channel).
+    fn parse_stream_entry(
+        entry: &StreamEntry,
+        sharded: bool,
+        namespace: &Option<String>,
+        tag_id_cache: &mut LruCache<String, i64>,
+        pattern_timelines: &Option<HashSet<Timeline>>,
+    ) -> Result<Option<(Timeline, Arc<Event>)>> {
+        let invalid = |e| Error::RedisParseErr(e, format!("{:?}", entry));
+        let msg = RedisMsg {
+            timeline_txt: match entry.field("timeline") {
+                Err(RedisParseErr::MissingField) if !sharded => entry.stream,
+                tl => tl.map_err(invalid)?,
+            },
+            event_txt: entry.field("event").map_err(invalid)?,
+            leftover_input: &[],
+        };
+        match msg.timeline_matching_ns(namespace) {
+            Some(tl) => Self::parse_event(tl, msg.event_txt, tag_id_cache, pattern_timelines),
+            None => Ok(None),
+        }
+    }
+
+    fn copy_partial_msg(&mut self) {
+        if self.unread_idx.0 == 0 {
+            // msg already first; no copying needed
+        } else if self.unread_idx.0 >= (self.unread_idx.1 - self.unread_idx.0) {
+            let (read, unread) = self.buffer[..self.unread_idx.1].split_at_mut(self.unread_idx.0);
+            for (i, b) in unread.iter().enumerate() {
+                read[i] = *b;
+            }
+        } else {
+            // Less efficient, but should never occur in production
+            log::warn!("Moving partial input requires heap allocation");
+            self.buffer = self.buffer[self.unread_idx.0..].into();
+        }
+        self.unread_idx = (0, self.unread_idx.1 - self.unread_idx.0);
+    }
+}
+
+#[cfg(test)]
+mod test;
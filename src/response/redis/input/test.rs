@@ -0,0 +1,257 @@
+use super::*;
+use crate::config;
+use crate::response::event::checked_event::{
+    account::{Account, Field},
+    status::attachment::{Attachment, AttachmentType::*},
+    status::Status,
+    tag::Tag,
+    visibility::Visibility::*,
+    CheckedEvent::*,
+};
+use crate::Id;
+use serde_json::json;
+use std::fs;
+use std::str;
+
+type TestResult = std::result::Result<(), Box<dyn std::error::Error>>;
+
+fn input(i: usize) -> Vec<u8> {
+    fs::read_to_string(format!("test_data/redis_input_{:03}.resp", i))
+        .expect("test input not found")
+        .as_bytes()
+        .to_vec()
+}
+fn output(i: usize) -> Arc<Event> {
+    vec![
+        Arc::new(include!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_data/event_001.rs"
+        ))),
+        Arc::new(include!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_data/event_002.rs"
+        ))),
+        Arc::new(include!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_data/event_003.rs"
+        ))),
+        Arc::new(include!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_data/event_004.rs"
+        ))),
+        Arc::new(include!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_data/event_005.rs"
+        ))),
+        Arc::new(include!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test_data/event_006.rs"
+        ))),
+    ][i]
+        .clone()
+}
+
+#[test]
+fn redis_input_matches_six_events() -> TestResult {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    for i in 1..=6 {
+        redis.add(&input(i));
+    }
+    let mut i = 0;
+    while let Some((_tl, event)) = redis.next_event()? {
+        println!("Parsing Event #{:03}", i + 1);
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+    Ok(assert_eq!(i, 6))
+}
+
+#[test]
+fn redis_input_handles_non_utf8() -> TestResult {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    let mut input_txt = Vec::new();
+    for i in 1..=6 {
+        input_txt.extend_from_slice(&input(i));
+    }
+
+    let invalid_idx = str::from_utf8(&input_txt)?
+        .chars()
+        .take_while(|char| char.len_utf8() == 1)
+        .collect::<Vec<_>>()
+        .len()
+        + 1;
+
+    redis.add(&input_txt[..invalid_idx]);
+
+    let mut i = 0;
+    while let Some((_tl, event)) = redis.next_event()? {
+        println!("Parsing Event #{:03}", i + 1);
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+
+    redis.add(&input_txt[invalid_idx..]);
+
+    while let Some((_tl, event)) = redis.next_event()? {
+        println!("Parsing Event #{:03}", i + 1);
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+
+    Ok(assert_eq!(i, 6))
+}
+
+#[test]
+fn redis_input_matches_six_events_in_batches() -> TestResult {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    for i in 1..=3 {
+        redis.add(&input(i));
+    }
+    let mut i = 0;
+    while let Some((_tl, event)) = redis.next_event()? {
+        println!("Parsing Event #{:03}", i + 1);
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+
+    for i in 4..=6 {
+        redis.add(&input(i));
+    }
+    while let Some((_tl, event)) = redis.next_event()? {
+        println!("Parsing Event #{:03}", i + 1);
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+    Ok(assert_eq!(i, 6))
+}
+
+#[test]
+fn redis_input_handles_non_events() -> TestResult {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    for i in 1..=6 {
+        redis.add(&input(i));
+        redis.add(b"*3\r\n$9\r\nsubscribe\r\n$12\r\ntimeline:308\r\n:1\r\n");
+    }
+    let mut i = 0;
+
+    while let Some((_tl, event)) = redis.next_event()? {
+        println!("Parsing Event #{:03}", i + 1);
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+    Ok(assert_eq!(i, 6))
+}
+
+#[test]
+fn redis_input_handles_partial_events() -> TestResult {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    for i in 1..=3 {
+        redis.add(&input(i));
+    }
+    redis.add(&input(4)[..50]);
+    let mut i = 0;
+
+    while let Some((_tl, event)) = redis.next_event()? {
+        println!("Parsing Event #{:03}", i + 1);
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+    assert_eq!(i, 3);
+
+    redis.add(&input(4)[50..]);
+    redis.add(&input(5));
+    redis.add(&input(6));
+    while let Some((_tl, event)) = redis.next_event()? {
+        println!("Parsing Event #{:03}", i + 1);
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+
+    Ok(assert_eq!(i, 6))
+}
+
+#[test]
+fn redis_input_in_pattern_mode_drops_unsubscribed_timelines() -> TestResult {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    redis.pattern_timelines = Some(HashSet::new());
+    let unknown_tag =
+        "*4\r\n$8\r\npmessage\r\n$10\r\ntimeline:*\r\n$18\r\ntimeline:hashtag:a\r\n$2\r\n{}\r\n";
+    for i in 1..=3 {
+        redis.add(&input(i));
+        redis.add(unknown_tag.as_bytes());
+    }
+    assert!(redis.next_event()?.is_none());
+
+    let tl = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    redis.pattern_timelines = Some(std::iter::once(tl).collect());
+    for i in 4..=6 {
+        redis.add(unknown_tag.as_bytes());
+        redis.add(&input(i));
+    }
+    let mut i = 3;
+    while let Some((_tl, event)) = redis.next_event()? {
+        assert_eq!(event, output(i));
+        i += 1;
+    }
+    Ok(assert_eq!(i, 6))
+}
+
+#[test]
+fn redis_input_parses_stream_entries() -> TestResult {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    let public = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    let entry = |id: &str, fields: &str| format!("*2\r\n${}\r\n{}\r\n{}", id.len(), id, fields);
+    let delete = r#"{"event":"delete","payload":"1038647"}"#;
+    let event_field = format!("$5\r\nevent\r\n${}\r\n{}\r\n", delete.len(), delete);
+    let xread = format!(
+        "*1\r\n*2\r\n$15\r\ntimeline:public\r\n*3\r\n{}{}{}",
+        entry("1-0", &format!("*2\r\n{}", event_field)),
+        entry("1-1", "*2\r\n$5\r\nother\r\n$1\r\nx\r\n"), // no event, so skipped
+        entry("1-2", &format!("*2\r\n{}", event_field)),
+    );
+    redis.add(xread.as_bytes());
+
+    for _ in 0..2 {
+        let (tl, event) = redis.next_event()?.expect("an event");
+        assert_eq!(tl, public);
+        assert_eq!(*event, Event::try_from(delete)?);
+    }
+    Ok(assert!(redis.next_event()?.is_none()))
+}
+
+#[test]
+fn redis_input_skips_only_the_malformed_message() -> TestResult {
+    let mut redis = RedisInput::new(&config::Redis::default());
+    redis.add(b"*3\r\n$7\r\nmessage\r\n$15\r\ntimeline:public\r\n$2\r\n\xff\xfe\r\n");
+    redis.add(&input(1));
+
+    assert!(redis.next_event().is_err()); // the payload isn't UTF-8
+    match redis.next_event()? {
+        Some((_tl, event)) => assert_eq!(event, output(0)),
+        None => panic!("expected the message after the malformed one"),
+    }
+    Ok(assert_eq!(redis.unread_len(), 0))
+}
+
+#[test]
+fn redis_input_rejects_sharded_stream_entries_without_a_timeline() -> TestResult {
+    let delete = r#"{"event":"delete","payload":"1038647"}"#;
+    let entry = |stream| StreamEntry {
+        stream,
+        id: "1-0",
+        fields: vec![(b"event", delete.as_bytes())],
+    };
+    let mut cache = LruCache::new(1);
+
+    // A sharded stream's name isn't a timeline
+    let sharded_entry = entry("timeline:shard:0");
+    let parsed = RedisInput::parse_stream_entry(&sharded_entry, true, &None, &mut cache, &None);
+    assert!(parsed.is_err());
+
+    let public_entry = entry("timeline:public");
+    let parsed = RedisInput::parse_stream_entry(&public_entry, false, &None, &mut cache, &None);
+    let (tl, event) = parsed?.expect("an event");
+    assert_eq!(tl, Timeline::from_redis_text("public", &mut cache)?);
+    assert_eq!(*event, Event::try_from(delete)?);
+    Ok(())
+}
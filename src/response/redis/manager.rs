@@ -1,185 +1,330 @@
-//! Receives data from Redis, sorts it by `ClientAgent`, and stores it until
-//! polled by the correct `ClientAgent`.  Also manages sububscriptions and
+//! Receives data from Redis (or another `EventSource`), sorts it by `ClientAgent`, and
+//! stores it until polled by the correct `ClientAgent`.  Also manages sububscriptions and
 //! unsubscriptions to/from Redis.
 mod err;
+mod subscriber;
 pub use err::Error;
 
-use super::msg::{RedisParseErr, RedisParseOutput};
-use super::{Event, RedisCmd, RedisConn};
+use super::{Event, EventSource, RedisConn};
 use crate::config;
 use crate::request::{Subscription, Timeline};
+use subscriber::{SlowConsumer, Subscriber};
 
 pub(self) use super::EventErr;
 
-use futures::{Async, Poll, Stream};
+use futures::{Async, Future, Poll, Stream};
 use hashbrown::{HashMap, HashSet};
-use lru::LruCache;
-use std::convert::{TryFrom, TryInto};
-use std::str;
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Sender;
+use tokio::timer::{Delay, Interval};
 
 type Result<T> = std::result::Result<T, Error>;
-type EventChannel = Sender<Arc<Event>>;
+type EventChannel = Sender<(Timeline, u64, Arc<Event>)>;
 
 /// The item that streams from Redis and is polled by the `ClientAgent`
 pub struct Manager {
-    pub redis_conn: RedisConn,
-    timelines: HashMap<Timeline, HashMap<u32, EventChannel>>,
-    ping_time: Instant,
-    channel_id: u32,
-    pub unread_idx: (usize, usize),
-    tag_id_cache: LruCache<String, i64>,
+    source: Box<dyn EventSource>,
+    timelines: HashMap<Timeline, HashSet<u32>>,
+    /// Timelines without any clients that we remain subscribed to for the `unsubscribe_delay`
+    idle_since: HashMap<Timeline, Instant>,
+    unsubscribe_delay: Duration,
+    clients: HashMap<u32, Subscriber>,
+    /// Clients with events queued because their channel was full
+    backlogged: HashSet<u32>,
+    slow_consumer: SlowConsumer,
+    ping_interval: Interval,
+    reconnect_delay: Option<Delay>,
+    reconnect_backoff: Duration,
+    /// Whether we are waiting for the source to finish reconnecting
+    reconnecting: bool,
+    client_id: u32,
+    event_id: u64,
+    replay_buffers: HashMap<Timeline, VecDeque<(u64, Arc<Event>)>>,
+    replay_buffer_size: usize,
 }
 
 impl Stream for Manager {
     type Item = (Timeline, Arc<Event>);
     type Error = Error;
 
+    /// Yield the next `Event` from the `EventSource`.
+    ///
+    /// Returns `Ready(None)` once the source has been disconnected; returns `NotReady` (with
+    /// the current task registered to be woken) when waiting on the source.
     fn poll(&mut self) -> Poll<Option<Self::Item>, Error> {
-        let input = &self.redis_conn.input[self.unread_idx.0..self.unread_idx.1];
-        let (valid, invalid) = str::from_utf8(input)
-            .map(|v| (v, &b""[..]))
-            .unwrap_or_else(|e| {
-                // NOTE - this bounds check occurs more often than necessary; it could occur only when
-                // polling Redis.  However, benchmarking with Criterion shows it to be *very*
-                // inexpensive (<1 us) and thus not worth removing (doing so would require `unsafe`).
-                let (valid, invalid) = input.split_at(e.valid_up_to());
-                (str::from_utf8(valid).expect("split_at"), invalid)
-            });
+        self.source.poll_event()
+    }
+}
 
-        if !valid.is_empty() {
-            use RedisParseOutput::*;
-            match RedisParseOutput::try_from(valid) {
-                Ok(Msg(msg)) => {
-                    // If we get a message and it matches the redis_namespace, get the msg's
-                    // Event and send it to all channels matching the msg's Timeline
-                    if let Some(tl) = msg.timeline_matching_ns(&self.redis_conn.namespace) {
-                        self.unread_idx.0 =
-                            self.unread_idx.1 - msg.leftover_input.len() - invalid.len();
-
-                        let tl = Timeline::from_redis_text(tl, &mut self.tag_id_cache)?;
-                        let event: Arc<Event> = Arc::new(msg.event_txt.try_into()?);
-                        Ok(Async::Ready(Some((tl, event))))
-                    } else {
-                        Ok(Async::Ready(None))
+impl Manager {
+    /// Send all available events to the subscribed clients (and pings, if it's time for them).
+    ///
+    /// Returns `NotReady` once all available input has been processed; the current task is
+    /// woken when Redis sends more input, when the next ping is due, or when it is time to
+    /// try reconnecting to Redis.
+    pub fn send_msgs(&mut self) -> Poll<(), Error> {
+        self.flush_backlogged();
+        loop {
+            match self.ping_interval.poll() {
+                Ok(Async::Ready(Some(_))) => {
+                    if self.source.needs_reconnect() {
+                        log::warn!(
+                            "Reconnecting to the event source (e.g., after a Redis failover)"
+                        );
+                        self.reconnecting = true;
                     }
+                    self.send_pings()?;
                 }
-                Ok(NonMsg(leftover_input)) => {
-                    self.unread_idx.0 = self.unread_idx.1 - leftover_input.len();
-                    Ok(Async::Ready(None))
+                Ok(_) => break,
+                Err(e) => {
+                    log::error!("Could not schedule pings: {}", e);
+                    break;
                 }
-                Err(RedisParseErr::Incomplete) => {
-                    self.copy_partial_msg();
-                    Ok(Async::NotReady)
+            }
+        }
+
+        loop {
+            if let Some(delay) = &mut self.reconnect_delay {
+                match delay.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => (),
+                    Err(e) => log::error!("Could not wait before reconnecting to Redis: {}", e),
+                }
+                self.reconnect_delay = None;
+                self.reconnecting = true;
+            }
+            if self.reconnecting {
+                match self.reconnect() {
+                    Async::Ready(()) => continue,
+                    Async::NotReady => return Ok(Async::NotReady),
                 }
-                Err(e) => Err(Error::RedisParseErr(e, valid.to_string()))?,
             }
-        } else {
-            self.unread_idx = (0, 0);
-            Ok(Async::NotReady)
+
+            match self.poll() {
+                Ok(Async::Ready(Some((tl, event)))) => self.send_event(tl, event),
+                Ok(Async::Ready(None)) => {
+                    log::error!("Lost the connection to Redis.  Reconnecting...");
+                    self.schedule_reconnect();
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => log::error!("{}", e), // the bad input has been consumed; keep going
+            }
         }
     }
-}
 
-impl Manager {
-    // untested
-    pub fn send_msgs(&mut self) -> Poll<(), Error> {
-        if self.ping_time.elapsed() > Duration::from_secs(30) {
-            self.send_pings()?
-        }
+    const MIN_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+    const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
 
-        while let Ok(Async::Ready(Some(msg_len))) = self.redis_conn.poll_redis(self.unread_idx.1) {
-            self.unread_idx.1 += msg_len;
+    fn schedule_reconnect(&mut self) {
+        self.reconnect_delay = Some(Delay::new(Instant::now() + self.reconnect_backoff));
+        self.reconnect_backoff = (self.reconnect_backoff * 2).min(Self::MAX_RECONNECT_BACKOFF);
+    }
 
-            while let Ok(Async::Ready(msg)) = self.poll() {
-                if let Some((tl, event)) = msg {
-                    for channel in self.timelines.entry(tl).or_default().values_mut() {
-                        if let Ok(Async::NotReady) = channel.poll_ready() {
-                            log::warn!("{:?} channel full\ncan't send:{:?}", tl, event);
-                            self.rewind_to_prev_msg();
-                            return Ok(Async::NotReady);
-                        }
+    /// Reconnect to Redis and resubscribe to every timeline that has clients, so that clients
+    /// only experience a delay.  Schedules another attempt if Redis is still unavailable.
+    ///
+    /// Returns `NotReady` while the source is still connecting (without blocking).
+    fn reconnect(&mut self) -> Async<()> {
+        match self.source.reconnect() {
+            Ok(Async::Ready(())) => self.reconnecting = false,
+            Ok(Async::NotReady) => return Async::NotReady,
+            Err(e) => {
+                log::error!("{}", e);
+                self.reconnecting = false;
+                self.schedule_reconnect();
+                return Async::Ready(());
+            }
+        }
+        self.reconnect_backoff = Self::MIN_RECONNECT_BACKOFF;
 
-                        let _ = channel.try_send(event.clone()); // err just means channel will be closed
-                    }
-                }
+        let timelines: Vec<Timeline> = self.timelines.keys().copied().collect();
+        if !timelines.is_empty() {
+            if let Err(e) = self.source.subscribe(&timelines) {
+                log::error!("Could not resubscribe to Redis: {}", e);
+                self.schedule_reconnect();
+                return Async::Ready(());
             }
         }
-        Ok(Async::Ready(()))
+        log::info!("Reconnected to Redis and resubscribed to {:?}", timelines);
+        Async::Ready(())
     }
 
-    fn rewind_to_prev_msg(&mut self) {
-        self.unread_idx.0 = loop {
-            let input = &self.redis_conn.input[..self.unread_idx.0];
-            let input = str::from_utf8(input).unwrap_or_else(|e| {
-                str::from_utf8(input.split_at(e.valid_up_to()).0).expect("guaranteed by `split_at`")
+    fn send_event(&mut self, tl: Timeline, event: Arc<Event>) {
+        self.event_id += 1;
+        let (id, slow_consumer) = (self.event_id, self.slow_consumer);
+        let (clients, backlogged) = (&mut self.clients, &mut self.backlogged);
+        if let Some(client_ids) = self.timelines.get_mut(&tl) {
+            // A slow client only ever fills its own queue; it never holds up others
+            client_ids.retain(|client_id| {
+                let client = match clients.get_mut(client_id) {
+                    Some(client) => client,
+                    None => return false,
+                };
+                if !client.send((tl, id, event.clone()), slow_consumer) {
+                    clients.remove(client_id);
+                    return false;
+                }
+                if !client.queue.is_empty() {
+                    backlogged.insert(*client_id);
+                }
+                true
             });
-
-            let index = if let Some(i) = input.rfind("\r\n*") {
-                i + "\r\n".len()
-            } else {
-                0
-            };
-            self.unread_idx.0 = index;
-
-            if let Ok(Async::Ready(Some(_))) = self.poll() {
-                break index;
-            }
         }
+        self.buffer_for_replay(tl, id, event);
     }
 
-    fn copy_partial_msg(&mut self) {
-        if self.unread_idx.0 == 0 {
-            // msg already first; no copying needed
-        } else if self.unread_idx.0 >= (self.unread_idx.1 - self.unread_idx.0) {
-            let (read, unread) =
-                self.redis_conn.input[..self.unread_idx.1].split_at_mut(self.unread_idx.0);
-            for (i, b) in unread.iter().enumerate() {
-                read[i] = *b;
+    /// Send the queued events of clients whose channels were full.  Finding a channel full
+    /// registers the current task to be woken once it has room, so this runs on each wake
+    /// (rather than waiting for the next event or ping).
+    fn flush_backlogged(&mut self) {
+        let clients = &mut self.clients;
+        self.backlogged.retain(|client_id| {
+            let flushed = clients
+                .get_mut(client_id)
+                .map(|client| (client.flush(), client.queue.is_empty()));
+            match flushed {
+                Some((true, emptied)) => !emptied,
+                Some((false, _)) => {
+                    clients.remove(client_id);
+                    false
+                }
+                None => false,
             }
-        } else {
-            // Less efficient, but should never occur in production
-            log::warn!("Moving partial input requires heap allocation");
-            self.redis_conn.input = self.redis_conn.input[self.unread_idx.0..].into();
+        });
+    }
+
+    /// Keep the most recent events for each timeline so that clients that reconnect with a
+    /// `Last-Event-ID` can receive the events they missed
+    fn buffer_for_replay(&mut self, tl: Timeline, id: u64, event: Arc<Event>) {
+        if self.replay_buffer_size == 0 {
+            return;
+        }
+        let buffer = self.replay_buffers.entry(tl).or_default();
+        if buffer.len() == self.replay_buffer_size {
+            buffer.pop_front();
         }
-        self.unread_idx = (0, self.unread_idx.1 - self.unread_idx.0);
-        &self.unread_idx;
+        buffer.push_back((id, event));
     }
+
+    /// Return all buffered events for `tl` that are more recent than `last_event_id`
+    pub fn replay(&self, tl: Timeline, last_event_id: u64) -> Vec<(Timeline, u64, Arc<Event>)> {
+        self.replay_buffers
+            .get(&tl)
+            .map_or_else(Vec::new, |buffer| {
+                buffer
+                    .iter()
+                    .filter(|(id, _)| *id > last_event_id)
+                    .map(|(id, event)| (tl, *id, event.clone()))
+                    .collect()
+            })
+    }
+
     /// Create a new `Manager`, with its own Redis connections (but no active subscriptions).
     pub fn try_from(redis_cfg: &config::Redis) -> Result<Self> {
-        Ok(Self {
-            redis_conn: RedisConn::new(redis_cfg)?,
+        Ok(Self::with_source(RedisConn::new(redis_cfg)?, redis_cfg))
+    }
+
+    /// Create a new `Manager` that receives its events from `source` instead of Redis.  The
+    /// Redis settings for queuing events (e.g., the `SLOW_CONSUMER_POLICY`) still apply.
+    pub fn with_source(source: impl EventSource + 'static, redis_cfg: &config::Redis) -> Self {
+        Self {
+            source: Box::new(source),
             timelines: HashMap::new(),
-            ping_time: Instant::now(),
-            channel_id: 0,
-            unread_idx: (0, 0),
-            tag_id_cache: LruCache::new(1000),
-        })
+            idle_since: HashMap::new(),
+            unsubscribe_delay: *redis_cfg.unsubscribe_delay,
+            clients: HashMap::new(),
+            backlogged: HashSet::new(),
+            slow_consumer: SlowConsumer {
+                policy: *redis_cfg.slow_consumer_policy,
+                queue_size: *redis_cfg.slow_consumer_queue,
+                max_dropped: *redis_cfg.slow_consumer_max_dropped,
+            },
+            ping_interval: Interval::new_interval(Duration::from_secs(30)),
+            reconnect_delay: None,
+            reconnect_backoff: Self::MIN_RECONNECT_BACKOFF,
+            reconnecting: false,
+            client_id: 0,
+            event_id: 0,
+            replay_buffers: HashMap::new(),
+            replay_buffer_size: *redis_cfg.replay_buffer,
+        }
     }
 
     pub fn into_arc(self) -> Arc<Mutex<Self>> {
         Arc::new(Mutex::new(self))
     }
 
-    pub fn subscribe(&mut self, subscription: &Subscription, channel: EventChannel) {
+    /// Register a client that will receive its events through `channel`, returning the ID
+    /// used to `subscribe` that client to timelines.
+    pub fn add_client(&mut self, channel: EventChannel) -> u32 {
+        let client_id = self.client_id;
+        self.clients.insert(client_id, Subscriber::new(channel));
+        self.client_id += 1;
+        client_id
+    }
+
+    /// Send all events for the `Subscription`'s timeline to the client with `client_id`
+    pub fn subscribe(&mut self, client_id: u32, subscription: &Subscription) {
         let (tag, tl) = (subscription.hashtag_name.clone(), subscription.timeline);
         if let (Some(hashtag), Some(id)) = (tag, tl.tag()) {
-            self.tag_id_cache.put(hashtag.clone(), id);
-            self.redis_conn.tag_name_cache.put(id, hashtag);
-        };
+            self.source.cache_hashtag(id, &hashtag);
+        }
 
-        let channels = self.timelines.entry(tl).or_default();
-        channels.insert(self.channel_id, channel);
-        self.channel_id += 1;
+        let client_ids = self.timelines.entry(tl).or_default();
+        client_ids.insert(client_id);
 
-        if channels.len() == 1 {
-            self.redis_conn
-                .send_cmd(RedisCmd::Subscribe, &[tl])
+        if self.idle_since.remove(&tl).is_some() {
+            log::info!("Resumed idle subscription to {:?}", tl); // still subscribed in Redis
+        } else if client_ids.len() == 1 {
+            self.source
+                .subscribe(&[tl])
                 .unwrap_or_else(|e| log::error!("Could not subscribe to the Redis channel: {}", e));
             log::info!("Subscribed to {:?}", tl);
+        }
+    }
+
+    /// Stop sending events for `tl` to the client with `client_id`
+    pub fn unsubscribe(&mut self, client_id: u32, tl: Timeline) {
+        let client_ids = match self.timelines.get_mut(&tl) {
+            Some(client_ids) => client_ids,
+            None => return,
         };
+        client_ids.remove(&client_id);
+
+        if client_ids.is_empty() {
+            self.idle_since.entry(tl).or_insert_with(Instant::now);
+            self.unsubscribe_idle_timelines()
+                .unwrap_or_else(|e| log::error!("Could not unsubscribe from Redis: {}", e));
+        }
+    }
+
+    /// Unsubscribe from timelines that have had no clients for longer than the
+    /// `unsubscribe_delay`.  Keeping idle timelines subscribed for a while means that a client
+    /// that reconnects (e.g., when a user refreshes the page) doesn't cause any Redis commands.
+    fn unsubscribe_idle_timelines(&mut self) -> Result<()> {
+        let delay = self.unsubscribe_delay;
+        let mut expired = Vec::new();
+        self.idle_since.retain(|tl, idle_since| {
+            if idle_since.elapsed() >= delay {
+                expired.push(*tl);
+                false
+            } else {
+                true
+            }
+        });
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        for tl in &expired {
+            self.timelines.remove(tl);
+            self.replay_buffers.remove(tl);
+        }
+        self.source.unsubscribe(&expired)?;
+        log::info!("Unsubscribed from {:?}", expired);
+        Ok(())
     }
 
     fn send_pings(&mut self) -> Result<()> {
@@ -188,44 +333,65 @@ impl Manager {
         // that thread fatally errors sending to the client.  On the *second* cycle, this
         // gets the error.  This isn't ideal, but is harmless.
 
-        self.ping_time = Instant::now();
-        let mut subscriptions_to_close = HashSet::new();
-        self.timelines.retain(|tl, channels| {
-            channels.retain(|_, chan| chan.try_send(Arc::new(Event::Ping)).is_ok());
+        let ping = (Timeline::empty(), self.event_id, Arc::new(Event::Ping));
+        self.clients.retain(|_, client| client.ping(ping.clone()));
 
-            if channels.is_empty() {
-                subscriptions_to_close.insert(*tl);
-                false
-            } else {
-                true
+        let (clients, idle_since) = (&self.clients, &mut self.idle_since);
+        for (tl, client_ids) in &mut self.timelines {
+            client_ids.retain(|client_id| clients.contains_key(client_id));
+            if client_ids.is_empty() {
+                idle_since.entry(*tl).or_insert_with(Instant::now);
             }
-        });
-        if !subscriptions_to_close.is_empty() {
-            let timelines: Vec<_> = subscriptions_to_close.into_iter().collect();
-            &self
-                .redis_conn
-                .send_cmd(RedisCmd::Unsubscribe, &timelines[..])?;
-            log::info!("Unsubscribed from {:?}", timelines);
+        }
+        self.unsubscribe_idle_timelines()?;
+
+        // Keep the `subscribed:` keys for our remaining timelines from expiring
+        let timelines: Vec<_> = self.timelines.keys().copied().collect();
+        if !timelines.is_empty() {
+            self.source.refresh(&timelines)?;
         }
         Ok(())
     }
 
+    /// Unsubscribe from every timeline (and remove their `subscribed:` keys, so that Mastodon
+    /// stops publishing to them) before shutting down
+    pub fn unsubscribe_all(&mut self) {
+        let timelines: Vec<_> = self.timelines.drain().map(|(tl, _)| tl).collect();
+        self.idle_since.clear();
+        if timelines.is_empty() {
+            return;
+        }
+        match self.source.unsubscribe(&timelines) {
+            Ok(()) => log::info!("Unsubscribed from {:?}", timelines),
+            Err(e) => log::error!("Could not unsubscribe from Redis: {}", e),
+        }
+    }
+
+    /// Finish sending any commands still queued for the event source (such as those sent by
+    /// `unsubscribe_all`)
+    pub fn poll_flush(&mut self) -> Poll<(), Error> {
+        self.source.poll_flush()
+    }
+
     pub fn recover(poisoned: PoisonError<MutexGuard<Self>>) -> MutexGuard<Self> {
         log::error!("{}", &poisoned);
         poisoned.into_inner()
     }
 
     pub fn count(&self) -> String {
-        format!(
-            "Current connections: {}",
-            self.timelines.values().map(HashMap::len).sum::<usize>()
-        )
+        format!("Current connections: {}", self.clients.len())
     }
 
     pub fn backpresure(&self) -> String {
         format!(
-            "Input buffer size: {} KiB",
-            (self.unread_idx.1 - self.unread_idx.0) / 1024
+            "Input buffer size: {} KiB\n\
+             Slow consumer policy: {}\n\
+             Queued events: {}\n\
+             Dropped events: {}",
+            self.source.unread_len() / 1024,
+            self.slow_consumer,
+            self.clients.values().map(|c| c.queue.len()).sum::<usize>(),
+            self.clients.values().map(|c| c.dropped).sum::<usize>(),
         )
     }
 
@@ -236,9 +402,9 @@ impl Manager {
             .fold(0, |acc, el| acc.max(format!("{:?}:", el).len()));
         self.timelines
             .iter()
-            .map(|(tl, channel_map)| {
+            .map(|(tl, client_ids)| {
                 let tl_txt = format!("{:?}:", tl);
-                format!("{:>1$} {2}\n", tl_txt, max_len, channel_map.len())
+                format!("{:>1$} {2}\n", tl_txt, max_len, client_ids.len())
             })
             .chain(std::iter::once(
                 "\n*may include recently disconnected clients".to_string(),
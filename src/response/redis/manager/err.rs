@@ -1,6 +1,6 @@
 use super::super::{RedisConnErr, RedisParseErr};
 use super::{Event, EventErr};
-use crate::request::TimelineErr;
+use crate::request::{self, Timeline, TimelineErr};
 
 use std::fmt;
 use std::sync::Arc;
@@ -12,7 +12,9 @@ pub enum Error {
     EventErr(EventErr),
     RedisParseErr(RedisParseErr, String),
     RedisConnErr(RedisConnErr),
-    ChannelSendErr(tokio::sync::mpsc::error::TrySendError<Arc<Event>>),
+    PgErr(String),
+    InvalidNotification(String),
+    ChannelSendErr(tokio::sync::mpsc::error::TrySendError<(Timeline, u64, Arc<Event>)>),
 }
 
 impl std::error::Error for Error {}
@@ -28,6 +30,12 @@ impl fmt::Display for Error {
             EventErr(inner) => write!(f, "{}", inner),
             RedisParseErr(inner, input) => write!(f, "error parsing {}\n{}", input, inner),
             RedisConnErr(inner) => write!(f, "{}", inner),
+            PgErr(inner) => write!(f, "{}", inner),
+            InvalidNotification(payload) => write!(
+                f,
+                "expected a Postgres notification with a timeline and an event, got: {}",
+                payload
+            ),
             TimelineErr(inner) => write!(f, "{}", inner),
             ChannelSendErr(inner) => write!(f, "{}", inner),
         }?;
@@ -35,8 +43,8 @@ impl fmt::Display for Error {
     }
 }
 
-impl From<tokio::sync::mpsc::error::TrySendError<Arc<Event>>> for Error {
-    fn from(error: tokio::sync::mpsc::error::TrySendError<Arc<Event>>) -> Self {
+impl From<tokio::sync::mpsc::error::TrySendError<(Timeline, u64, Arc<Event>)>> for Error {
+    fn from(error: tokio::sync::mpsc::error::TrySendError<(Timeline, u64, Arc<Event>)>) -> Self {
         Self::ChannelSendErr(error)
     }
 }
@@ -53,6 +61,12 @@ impl From<RedisConnErr> for Error {
     }
 }
 
+impl From<request::Error> for Error {
+    fn from(e: request::Error) -> Self {
+        Self::PgErr(e.to_string())
+    }
+}
+
 impl From<TimelineErr> for Error {
     fn from(e: TimelineErr) -> Self {
         Self::TimelineErr(e)
@@ -0,0 +1,111 @@
+//! A connected client, along with the events queued for it while its channel is full.
+use super::{Event, EventChannel};
+use crate::config::SlowConsumerInner as Policy;
+use crate::request::Timeline;
+
+use futures::Async;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+
+type Msg = (Timeline, u64, Arc<Event>);
+
+/// How the `Manager` treats a client that isn't reading its events as fast as they arrive
+#[derive(Debug, Clone, Copy)]
+pub(super) struct SlowConsumer {
+    pub(super) policy: Policy,
+    pub(super) queue_size: usize,
+    pub(super) max_dropped: usize,
+}
+
+impl fmt::Display for SlowConsumer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.policy {
+            Policy::Disconnect => write!(
+                f,
+                "{:?} (queue size: {}, disconnect after: {} dropped events)",
+                self.policy, self.queue_size, self.max_dropped
+            ),
+            _ => write!(f, "{:?} (queue size: {})", self.policy, self.queue_size),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Subscriber {
+    channel: EventChannel,
+    pub(super) queue: VecDeque<Msg>,
+    pub(super) dropped: usize,
+}
+
+impl Subscriber {
+    pub(super) fn new(channel: EventChannel) -> Self {
+        Self {
+            channel,
+            queue: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Send `msg` to the client, queuing it if the client's channel is full and applying the
+    /// `SlowConsumer` policy if the queue is full as well.
+    ///
+    /// Returns `false` if the client has disconnected or should be disconnected.
+    pub(super) fn send(&mut self, msg: Msg, slow_consumer: SlowConsumer) -> bool {
+        self.queue.push_back(msg);
+        if !self.flush() {
+            return false;
+        }
+        if self.queue.len() <= slow_consumer.queue_size {
+            return true;
+        }
+
+        self.dropped += 1;
+        match slow_consumer.policy {
+            Policy::DropOldest => self.queue.pop_front().is_some(),
+            Policy::DropNewest => self.queue.pop_back().is_some(),
+            Policy::Disconnect => {
+                self.queue.pop_back();
+                if self.dropped >= slow_consumer.max_dropped {
+                    log::warn!("Disconnecting client after {} dropped events", self.dropped);
+                    false
+                } else {
+                    true
+                }
+            }
+        }
+    }
+
+    /// Check that the client is still connected, sending it `ping` if it has room for one.
+    pub(super) fn ping(&mut self, ping: Msg) -> bool {
+        if !self.flush() {
+            return false;
+        }
+        match self.channel.poll_ready() {
+            Ok(Async::Ready(())) => self.channel.try_send(ping).is_ok(),
+            Ok(Async::NotReady) => true, // a slow client is still a connected client
+            Err(_closed) => false,
+        }
+    }
+
+    /// Move as many queued events as the client's channel has room for into the channel.
+    ///
+    /// Returns `false` if the client has disconnected.
+    pub(super) fn flush(&mut self) -> bool {
+        while let Some(msg) = self.queue.pop_front() {
+            match self.channel.poll_ready() {
+                Ok(Async::Ready(())) => {
+                    if self.channel.try_send(msg).is_err() {
+                        return false;
+                    }
+                }
+                Ok(Async::NotReady) => {
+                    self.queue.push_front(msg);
+                    return true;
+                }
+                Err(_closed) => return false,
+            }
+        }
+        true
+    }
+}
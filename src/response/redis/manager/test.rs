@@ -1,3 +1,4 @@
+use super::super::RedisConnErr;
 use super::*;
 use crate::config;
 use crate::response::event::checked_event::{
@@ -8,18 +9,16 @@ use crate::response::event::checked_event::{
     visibility::Visibility::*,
     CheckedEvent::*,
 };
+use crate::response::MemorySource;
 use crate::Id;
+use futures::future::{self, Future};
+use lru::LruCache;
 use serde_json::json;
-use std::fs;
+use tokio::prelude::FutureExt;
+use tokio::sync::mpsc;
 
 type TestResult = std::result::Result<(), Box<dyn std::error::Error>>;
 
-fn input(i: usize) -> Vec<u8> {
-    fs::read_to_string(format!("test_data/redis_input_{:03}.resp", i))
-        .expect("test input not found")
-        .as_bytes()
-        .to_vec()
-}
 fn output(i: usize) -> Arc<Event> {
     vec![
         Arc::new(include!(concat!(
@@ -51,195 +50,328 @@ fn output(i: usize) -> Arc<Event> {
 }
 
 #[test]
-fn manager_poll_matches_six_events() -> TestResult {
-    let mut manager = Manager::try_from(&config::Redis::default())?;
-    for i in 1..=6 {
-        manager.redis_conn.add(&input(i));
-    }
-    let mut i = 0;
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx = (0, manager.unread_idx.1 + len);
-        while let Ok(Async::Ready(Some((_tl, event)))) = manager.poll() {
-            println!("Parsing Event #{:03}", i + 1);
-            assert_eq!(event, output(i));
-            i += 1;
+fn manager_buffers_events_for_replay() -> TestResult {
+    let (source, sender) = MemorySource::new();
+    let mut manager = Manager::with_source(source, &config::Redis::default());
+    let tl = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    let (tx, _rx) = mpsc::channel(10);
+    let client_id = manager.add_client(tx);
+    manager.subscribe(client_id, &subscription(tl));
+
+    let events: Vec<_> = (0..6).map(output).collect();
+    future::lazy(|| {
+        for event in &events {
+            sender.send(tl, (**event).clone());
         }
+        manager.send_msgs()
+    })
+    .wait()?;
+
+    let replayed = manager.replay(tl, 0);
+    assert_eq!(replayed.len(), 6);
+    for (i, (_tl, id, event)) in replayed.into_iter().enumerate() {
+        assert_eq!(id, i as u64 + 1);
+        assert_eq!(event, events[i]);
     }
-    Ok(assert_eq!(i, 6))
+
+    let missed: Vec<_> = manager.replay(tl, 4).into_iter().map(|msg| msg.1).collect();
+    Ok(assert_eq!(missed, vec![5, 6]))
 }
 
 #[test]
-fn manager_poll_handles_non_utf8() -> TestResult {
-    let mut manager = Manager::try_from(&config::Redis::default())?;
-    let mut input_txt = Vec::new();
-    for i in 1..=6 {
-        input_txt.extend_from_slice(&input(i))
-    }
+fn manager_sends_events_only_for_subscribed_timelines() -> TestResult {
+    let (source, sender) = MemorySource::new();
+    let mut manager = Manager::with_source(source, &config::Redis::default());
+    let public = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    let local = Timeline::from_redis_text("public:local", &mut LruCache::new(1))?;
+    manager.subscribe(0, &subscription(public));
 
-    let invalid_idx = str::from_utf8(&input_txt)?
-        .chars()
-        .take_while(|char| char.len_utf8() == 1)
-        .collect::<Vec<_>>()
-        .len()
-        + 1;
-
-    manager.redis_conn.add(&input_txt[..invalid_idx]);
-
-    let mut i = 0;
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx.1 += len;
-        while let Ok(Async::Ready(Some((_tl, event)))) = manager.poll() {
-            println!("Parsing Event #{:03}", i + 1);
-            assert_eq!(event, output(i));
-            i += 1;
+    sender.send(local, Event::Ping);
+    sender.send(public, Event::Ping);
+    future::lazy(|| {
+        match manager.poll() {
+            Ok(Async::Ready(Some((tl, _event)))) => assert_eq!(tl, public),
+            other => panic!("expected an event, got {:?}", other),
         }
+        assert!(matches!(manager.poll(), Ok(Async::NotReady)));
+        Ok::<_, ()>(())
+    })
+    .wait()
+    .expect("test");
+    Ok(())
+}
+
+fn subscription(timeline: Timeline) -> Subscription {
+    Subscription {
+        timeline,
+        ..Subscription::default()
     }
+}
+
+#[test]
+fn manager_delays_unsubscribing_from_idle_timelines() -> TestResult {
+    let (source, _sender) = MemorySource::new();
+    let mut manager = Manager::with_source(source, &config::Redis::default());
+    let tl = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    let subscription = subscription(tl);
 
-    manager.redis_conn.add(&input_txt[invalid_idx..]);
+    manager.subscribe(0, &subscription);
+    manager.unsubscribe(0, tl);
+    assert!(manager.timelines.contains_key(&tl));
+    assert!(manager.idle_since.contains_key(&tl));
 
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx.1 += len;
-        while let Ok(Async::Ready(Some((_tl, event)))) = manager.poll() {
-            println!("Parsing Event #{:03}", i + 1);
-            assert_eq!(event, output(i));
-            i += 1;
-        }
+    manager.subscribe(1, &subscription);
+    assert!(manager.idle_since.is_empty());
+
+    manager.unsubscribe_delay = Duration::from_secs(0);
+    manager.unsubscribe(1, tl);
+    assert!(!manager.timelines.contains_key(&tl));
+    Ok(assert!(manager.idle_since.is_empty()))
+}
+
+fn slow_consumer(policy: config::SlowConsumerInner) -> SlowConsumer {
+    SlowConsumer {
+        policy,
+        queue_size: 2,
+        max_dropped: 3,
     }
+}
 
-    Ok(assert_eq!(i, 6))
+fn msg(id: u64) -> (Timeline, u64, Arc<Event>) {
+    (Timeline::empty(), id, Arc::new(Event::Ping))
 }
 
 #[test]
-fn manager_poll_matches_six_events_in_batches() -> TestResult {
-    let mut manager = Manager::try_from(&config::Redis::default())?;
-    for i in 1..=3 {
-        manager.redis_conn.add(&input(i))
-    }
-    let mut i = 0;
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx.1 += len;
-        while let Ok(Async::Ready(Some((_tl, event)))) = manager.poll() {
-            println!("Parsing Event #{:03}", i + 1);
-            assert_eq!(event, output(i));
-            i += 1;
+fn slow_subscriber_drops_oldest_events() -> TestResult {
+    let policy = slow_consumer(config::SlowConsumerInner::DropOldest);
+    let (tx, mut rx) = mpsc::channel(1);
+    let mut subscriber = Subscriber::new(tx);
+
+    future::lazy(|| {
+        for id in 1..=10 {
+            assert!(subscriber.send(msg(id), policy));
         }
-    }
+        let queued: Vec<_> = subscriber.queue.iter().map(|msg| msg.1).collect();
+        assert_eq!(queued, vec![9, 10]);
+        assert_eq!(subscriber.dropped, 7);
 
-    for i in 4..=6 {
-        manager.redis_conn.add(&input(i));
-    }
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx.1 += len;
-        while let Ok(Async::Ready(Some((_tl, event)))) = manager.poll() {
-            println!("Parsing Event #{:03}", i + 1);
-            assert_eq!(event, output(i));
-            i += 1;
+        match rx.poll() {
+            Ok(Async::Ready(Some((_tl, id, _event)))) => assert_eq!(id, 1),
+            other => panic!("expected the first event, got {:?}", other),
         }
-    }
-    Ok(assert_eq!(i, 6))
+        Ok::<_, ()>(())
+    })
+    .wait()
+    .expect("test");
+    Ok(())
 }
 
 #[test]
-fn manager_poll_handles_non_events() -> TestResult {
-    let mut manager = Manager::try_from(&config::Redis::default())?;
-    for i in 1..=6 {
-        manager.redis_conn.add(&input(i));
-        manager
-            .redis_conn
-            .add(b"*3\r\n$9\r\nsubscribe\r\n$12\r\ntimeline:308\r\n:1\r\n");
-    }
-    let mut i = 0;
-
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx.1 += len;
-        while let Ok(Async::Ready(msg)) = manager.poll() {
-            if let Some((_tl, event)) = msg {
-                println!("Parsing Event #{:03}", i + 1);
-                assert_eq!(event, output(i));
-                i += 1;
-            }
+fn slow_subscriber_drops_newest_events() -> TestResult {
+    let policy = slow_consumer(config::SlowConsumerInner::DropNewest);
+    let (tx, mut rx) = mpsc::channel(1);
+    let mut subscriber = Subscriber::new(tx);
+
+    future::lazy(|| {
+        for id in 1..=10 {
+            assert!(subscriber.send(msg(id), policy));
         }
-    }
-    Ok(assert_eq!(i, 6))
+        // The first event fills the channel and the next two fill the queue
+        let queued: Vec<_> = subscriber.queue.iter().map(|msg| msg.1).collect();
+        assert_eq!(queued, vec![2, 3]);
+        assert_eq!(subscriber.dropped, 7);
+
+        match rx.poll() {
+            Ok(Async::Ready(Some((_tl, id, _event)))) => assert_eq!(id, 1),
+            other => panic!("expected the first event, got {:?}", other),
+        }
+        Ok::<_, ()>(())
+    })
+    .wait()
+    .expect("test");
+    Ok(())
 }
 
 #[test]
-fn manager_poll_handles_partial_events() -> TestResult {
-    let mut manager = Manager::try_from(&config::Redis::default())?;
-    for i in 1..=3 {
-        manager.redis_conn.add(&input(i));
+fn slow_subscriber_is_disconnected() -> TestResult {
+    let policy = slow_consumer(config::SlowConsumerInner::Disconnect);
+    let (tx, _rx) = mpsc::channel(1);
+    let mut subscriber = Subscriber::new(tx);
+
+    future::lazy(|| {
+        let sent = (1..=10)
+            .take_while(|id| subscriber.send(msg(*id), policy))
+            .count();
+        assert_eq!(subscriber.dropped, 3);
+        assert!(sent < 10);
+        Ok::<_, ()>(())
+    })
+    .wait()
+    .expect("test");
+    Ok(())
+}
+
+#[test]
+fn manager_sends_queued_events_once_a_client_has_room() -> TestResult {
+    let (source, sender) = MemorySource::new();
+    let mut manager = Manager::with_source(source, &config::Redis::default());
+    let tl = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    let (tx, rx) = mpsc::channel(1);
+    let client_id = manager.add_client(tx);
+    manager.subscribe(client_id, &subscription(tl));
+    for i in 0..2 {
+        sender.send(tl, (*output(i)).clone());
+    }
+
+    // After the first ping, the next is 30 seconds away and no further events are sent, so
+    // only the client reading from its (full) channel can wake the `Manager`
+    let mut runtime = tokio::runtime::current_thread::Runtime::new()?;
+    runtime.spawn(future::poll_fn(move || manager.send_msgs()).map_err(|e| panic!("{}", e)));
+    let received = rx
+        .filter(|(_tl, _id, event)| **event != Event::Ping)
+        .take(2)
+        .collect()
+        .timeout(Duration::from_secs(5));
+    let received = runtime
+        .block_on(received)
+        .map_err(|_| "the queued event was never sent")?;
+
+    let ids: Vec<_> = received.into_iter().map(|msg| msg.1).collect();
+    Ok(assert_eq!(ids, vec![1, 2]))
+}
+
+/// A source that is disconnected until it has failed to `reconnect` `failures` times, which
+/// records when it was asked to reconnect and each set of timelines it was subscribed to
+#[derive(Clone, Default)]
+struct FlakySource(Arc<Mutex<Flaky>>);
+
+#[derive(Default)]
+struct Flaky {
+    connected: bool,
+    needs_reconnect: bool,
+    failures: usize,
+    reconnects: Vec<Instant>,
+    subscribed: Vec<HashSet<Timeline>>,
+}
+
+impl FlakySource {
+    fn state(&self) -> MutexGuard<'_, Flaky> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl EventSource for FlakySource {
+    fn subscribe(&mut self, timelines: &[Timeline]) -> Result<()> {
+        let subscribed = timelines.iter().copied().collect();
+        self.state().subscribed.push(subscribed);
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, _timelines: &[Timeline]) -> Result<()> {
+        Ok(())
     }
-    manager.redis_conn.add(&input(4)[..50]);
-    let mut i = 0;
-
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx.1 += len;
-        while let Ok(Async::Ready(msg)) = manager.poll() {
-            if let Some((_tl, event)) = msg {
-                println!("Parsing Event #{:03}", i + 1);
-                assert_eq!(event, output(i));
-                i += 1;
-            }
+
+    fn poll_event(&mut self) -> Poll<Option<(Timeline, Arc<Event>)>, Error> {
+        match self.state().connected {
+            true => Ok(Async::NotReady),
+            false => Ok(Async::Ready(None)),
         }
     }
-    assert_eq!(i, 3);
-
-    manager.redis_conn.add(&input(4)[50..]);
-    manager.redis_conn.add(&input(5));
-    manager.redis_conn.add(&input(6));
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx.1 += len;
-        while let Ok(Async::Ready(msg)) = manager.poll() {
-            if let Some((_tl, event)) = msg {
-                println!("Parsing Event #{:03}", i + 1);
-                assert_eq!(event, output(i));
-                i += 1;
-            }
+
+    fn reconnect(&mut self) -> Poll<(), Error> {
+        let mut state = self.state();
+        state.reconnects.push(Instant::now());
+        if state.failures > 0 {
+            state.failures -= 1;
+            let refused = std::io::ErrorKind::ConnectionRefused.into();
+            return Err(Error::RedisConnErr(RedisConnErr::UnknownRedisErr(refused)));
         }
+        state.connected = true;
+        state.needs_reconnect = false;
+        Ok(Async::Ready(()))
     }
 
-    Ok(assert_eq!(i, 6))
+    fn needs_reconnect(&self) -> bool {
+        self.state().needs_reconnect
+    }
+}
+
+/// Run the `manager` until its `source` is connected
+fn run_until_connected(manager: &mut Manager, source: &FlakySource) -> TestResult {
+    let mut runtime = tokio::runtime::current_thread::Runtime::new()?;
+    let connected = future::poll_fn(|| -> Poll<(), Error> {
+        manager.send_msgs()?;
+        match source.state().connected {
+            true => Ok(Async::Ready(())),
+            false => Ok(Async::NotReady),
+        }
+    });
+    runtime
+        .block_on(connected.timeout(Duration::from_secs(5)))
+        .map_err(|e| format!("the source never reconnected: {:?}", e))?;
+    Ok(())
 }
 
 #[test]
-fn manager_poll_handles_full_channel() -> TestResult {
-    let mut manager = Manager::try_from(&config::Redis::default())?;
-    for i in 1..=6 {
-        manager.redis_conn.add(&input(i));
+fn manager_backs_off_between_reconnections_and_resubscribes() -> TestResult {
+    let source = FlakySource::default();
+    source.state().failures = 2;
+    let mut manager = Manager::with_source(source.clone(), &config::Redis::default());
+    let timelines = vec![
+        Timeline::from_redis_text("public", &mut LruCache::new(1))?,
+        Timeline::from_redis_text("1", &mut LruCache::new(1))?,
+    ];
+    for (client_id, tl) in (0..).zip(&timelines) {
+        manager.subscribe(client_id, &subscription(*tl));
     }
-    let (mut i, channel_full) = (0, 3);
-    'outer: loop {
-        while let Ok(Async::Ready(Some(n))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-            manager.unread_idx.1 += n;
-            while let Ok(Async::Ready(msg)) = manager.poll() {
-                if let Some((_tl, event)) = msg {
-                    println!("Parsing Event #{:03}", i + 1);
-                    assert_eq!(event, output(i));
-                    i += 1;
-                }
-                // Simulates a `ChannelFull` error after sending `channel_full` msgs
-                if i == channel_full {
-                    break 'outer;
-                }
-            }
-        }
+
+    let start = Instant::now();
+    run_until_connected(&mut manager, &source)?;
+
+    // Each failed attempt doubles the delay before the next one
+    let state = source.state();
+    let attempts: Vec<Instant> = vec![start]
+        .into_iter()
+        .chain(state.reconnects.iter().copied())
+        .collect();
+    let delays: Vec<Duration> = attempts.windows(2).map(|w| w[1] - w[0]).collect();
+    assert_eq!(delays.len(), 3);
+    for (delay, min) in delays.iter().zip(&[100, 200, 400]) {
+        assert!(*delay >= Duration::from_millis(*min), "{:?}", delays);
     }
 
-    let _rewind = (|| {
-        manager.rewind_to_prev_msg();
-        i -= 1;
-    })();
-
-    while let Ok(Async::Ready(Some(len))) = manager.redis_conn.poll_redis(manager.unread_idx.1) {
-        manager.unread_idx.1 += len;
-        while let Ok(Async::Ready(msg)) = manager.poll() {
-            if let Some((_tl, event)) = msg {
-                println!("Parsing Event #{:03}", i + 1);
-                assert_eq!(event, output(i));
-                i += 1;
-            }
-        }
+    // A successful attempt resets the backoff and resubscribes to every timeline
+    assert_eq!(manager.reconnect_backoff, Manager::MIN_RECONNECT_BACKOFF);
+    assert!(manager.reconnect_delay.is_none());
+    let resubscribed: HashSet<Timeline> = timelines.into_iter().collect();
+    assert_eq!(state.subscribed.last(), Some(&resubscribed));
+    Ok(())
+}
+
+#[test]
+fn manager_reconnects_when_the_source_asks_to() -> TestResult {
+    let source = FlakySource::default();
+    {
+        let mut state = source.state();
+        state.connected = true;
+        state.needs_reconnect = true;
     }
+    let mut manager = Manager::with_source(source.clone(), &config::Redis::default());
+    let tl = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    manager.subscribe(0, &subscription(tl));
+    // Check whether the source needs reconnecting right away instead of in 30 seconds
+    manager.ping_interval = Interval::new(Instant::now(), Duration::from_secs(30));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new()?;
+    runtime.block_on(future::lazy(|| manager.send_msgs()))?;
 
-    Ok(assert_eq!(i, 6))
+    let state = source.state();
+    assert_eq!(state.reconnects.len(), 1);
+    assert!(!state.needs_reconnect);
+    assert_eq!(manager.reconnect_backoff, Manager::MIN_RECONNECT_BACKOFF);
+    assert_eq!(
+        state.subscribed.last(),
+        Some(&vec![tl].into_iter().collect())
+    );
+    Ok(())
 }
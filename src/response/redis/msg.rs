@@ -1,8 +1,8 @@
-//! Methods for parsing input in the small subset of the Redis Serialization Protocol we
-//! support.
+//! Methods for parsing input in the Redis Serialization Protocol (both RESP2 and RESP3).
 //!
-//! Every message Flodgatt receives from Redis is a Redis Array; the elements in the array
-//! will be either Bulk Strings or Integers (as Redis defines those terms).  See the
+//! Every pub/sub message Flodgatt receives from Redis is a Redis Array (or, with RESP3, a
+//! Push frame); the elements in the array will be either Bulk Strings or Integers (as Redis
+//! defines those terms).  See the
 //! [Redis protocol documentation](https://redis.io/topics/protocol) for details. A raw
 //! message might look slightly like this (simplified, with line brakes added between
 //! fields):
@@ -17,8 +17,12 @@
 //! ```
 //!
 //! Read that as: an array with three elements: the first element is a bulk string with
-//! three characters, the second is a bulk string with ten characters, and the third is a
-//! bulk string with 1,386 characters.
+//! three bytes, the second is a bulk string with ten bytes, and the third is a bulk string
+//! with 1,386 bytes.
+//!
+//! Because Redis counts lengths in bytes (and a read from Redis can end partway through a
+//! multi-byte character), we parse the raw bytes and only validate the UTF-8 of the
+//! timeline and payload we extract.
 use self::RedisParseOutput::*;
 pub use err::RedisParseErr;
 use std::convert::{TryFrom, TryInto};
@@ -26,146 +30,392 @@ use std::str;
 
 mod err;
 
+/// A parsed reply from Redis, along with any input that remains after that reply
 #[derive(Debug, Clone, PartialEq)]
 pub enum RedisParseOutput<'a> {
+    /// A `message` (or `pmessage`) published to a channel we are subscribed to
     Msg(RedisMsg<'a>),
-    NonMsg(&'a str),
+    /// Confirmation that we have subscribed to (or unsubscribed from) a channel or pattern
+    Subscription(SubscriptionReply<'a>, &'a [u8]),
+    /// The entries read from Redis Streams with `XREAD` (with `REDIS_STREAMS`)
+    StreamEntries(Vec<StreamEntry<'a>>, &'a [u8]),
+    /// A reply to `PING`
+    Pong(&'a [u8]),
+    /// An error reply (for example, `-NOPERM` when subscribing to a forbidden channel)
+    RedisErr(&'a str, &'a [u8]),
+    /// Any other reply (for example, the map that `HELLO` replies with)
+    NonMsg(&'a [u8]),
+}
+
+impl<'a> RedisParseOutput<'a> {
+    /// The input that remains after the parsed reply
+    pub(super) fn leftover_input(&self) -> &'a [u8] {
+        match self {
+            Msg(msg) => msg.leftover_input,
+            Subscription(_, leftover) | Pong(leftover) | RedisErr(_, leftover) => leftover,
+            StreamEntries(_, leftover) => leftover,
+            NonMsg(leftover) => leftover,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscriptionKind {
+    Subscribe,
+    Unsubscribe,
+    Psubscribe,
+    Punsubscribe,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriptionReply<'a> {
+    pub kind: SubscriptionKind,
+    /// The channel (or pattern) subscribed to; `None` when unsubscribing from everything
+    pub channel: Option<&'a str>,
+    /// The number of channels and patterns we remain subscribed to
+    pub count: i64,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RedisMsg<'a> {
     pub timeline_txt: &'a str,
     pub event_txt: &'a str,
-    pub(crate) leftover_input: &'a str,
+    pub(crate) leftover_input: &'a [u8],
 }
 
-impl<'a> RedisMsg<'a> {
+impl RedisMsg<'_> {
+    /// The timeline (e.g., `public:local`) that the message was published to, or `None` if its
+    /// channel isn't a timeline in our namespace
     pub(super) fn timeline_matching_ns(&self, namespace: &Option<String>) -> Option<&str> {
-        match namespace {
-            Some(ns) if self.timeline_txt.starts_with(ns) => {
-                Some(&self.timeline_txt[ns.len() + ":timeline:".len()..])
-            }
-            None => Some(&self.timeline_txt["timeline:".len()..]),
-            Some(_non_matching_ns) => None,
-        }
+        let channel = match namespace {
+            Some(ns) => self
+                .timeline_txt
+                .strip_prefix(ns.as_str())?
+                .strip_prefix(':')?,
+            None => self.timeline_txt,
+        };
+        channel.strip_prefix("timeline:")
     }
 }
 
-impl<'a> TryFrom<&'a str> for RedisParseOutput<'a> {
+/// An entry in a Redis Stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEntry<'a> {
+    pub stream: &'a str,
+    pub id: &'a str,
+    /// The entry's fields and their values, in order
+    pub fields: Vec<(&'a [u8], &'a [u8])>,
+}
+
+impl<'a> StreamEntry<'a> {
+    /// The text of the field called `name`
+    pub fn field(&self, name: &str) -> Result<&'a str, RedisParseErr> {
+        let value = self
+            .fields
+            .iter()
+            .find(|(field, _)| *field == name.as_bytes())
+            .map(|(_, value)| *value)
+            .ok_or(MissingField)?;
+        Ok(str::from_utf8(value)?)
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for RedisParseOutput<'a> {
     type Error = RedisParseErr;
-    fn try_from(utf8: &'a str) -> Result<RedisParseOutput<'a>, Self::Error> {
-        let (structured_txt, leftover_utf8) = utf8_to_redis_data(utf8)?;
+    fn try_from(input: &'a [u8]) -> Result<RedisParseOutput<'a>, Self::Error> {
+        let (structured_txt, leftover_input) = bytes_to_redis_data(input)?;
         let structured_txt = RedisStructuredText {
             structured_txt,
-            leftover_input: leftover_utf8,
+            leftover_input,
         };
-        Ok(structured_txt.try_into()?)
+        structured_txt.try_into()
     }
 }
 
+/// Return the input that follows the first complete reply in `input`, even if that reply
+/// can't be parsed into a `RedisParseOutput` (for example, because it isn't valid UTF-8).
+pub(super) fn skip_reply(input: &[u8]) -> Result<&[u8], RedisParseErr> {
+    bytes_to_redis_data(input).map(|(_, leftover_input)| leftover_input)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 struct RedisStructuredText<'a> {
     structured_txt: RedisData<'a>,
-    leftover_input: &'a str,
+    leftover_input: &'a [u8],
 }
 #[derive(Debug, Clone, PartialEq)]
 enum RedisData<'a> {
     RedisArray(Vec<RedisData<'a>>),
-    BulkString(&'a str),
-    Integer(usize),
+    Push(Vec<RedisData<'a>>),
+    Map(Vec<(RedisData<'a>, RedisData<'a>)>),
+    BulkString(&'a [u8]),
+    SimpleString(&'a [u8]),
+    Error(&'a [u8]),
+    Integer(i64),
+    Null,
     Uninitilized,
 }
 
 use RedisData::*;
 use RedisParseErr::*;
-type RedisParser<'a, Item> = Result<Item, RedisParseErr>;
-fn utf8_to_redis_data<'a>(s: &'a str) -> Result<(RedisData, &'a str), RedisParseErr> {
-    if s.len() < 4 {
-        Err(Incomplete)?
-    };
-    let (first_char, s) = s.split_at(1);
-    match first_char {
-        ":" => parse_redis_int(s),
-        "$" => parse_redis_bulk_string(s),
-        "*" => parse_redis_array(s),
-        e => Err(InvalidLineStart(e.to_string())),
+type RedisParser<Item> = Result<Item, RedisParseErr>;
+fn bytes_to_redis_data(s: &[u8]) -> Result<(RedisData<'_>, &[u8]), RedisParseErr> {
+    if s.len() < 3 {
+        Err(Incomplete)?;
+    }
+    let (first_byte, s) = (s[0], &s[1..]);
+    match first_byte {
+        b':' => parse_redis_int(s),
+        b'$' => parse_redis_bulk_string(s),
+        b'*' => parse_redis_array(s).map(|(inner, rest)| (RedisArray(inner), rest)),
+        // RESP3 types (https://github.com/antirez/RESP3/blob/master/spec.md)
+        b'>' => parse_redis_array(s).map(|(inner, rest)| (Push(inner), rest)),
+        b'~' => parse_redis_array(s).map(|(inner, rest)| (RedisArray(inner), rest)),
+        b'%' => parse_redis_map(s),
+        b'|' => {
+            // Attributes are metadata about the reply that follows them; we don't need them
+            let (_attributes, rest) = parse_redis_map(s)?;
+            bytes_to_redis_data(rest)
+        }
+        b'=' => parse_redis_bulk_string(s).map(|(verbatim, rest)| match verbatim {
+            BulkString(txt) => (BulkString(txt.get("txt:".len()..).unwrap_or(txt)), rest),
+            other => (other, rest),
+        }),
+        b'!' => parse_redis_bulk_string(s).map(|(err, rest)| match err {
+            BulkString(txt) => (RedisData::Error(txt), rest),
+            other => (other, rest),
+        }),
+        b'+' | b',' | b'(' | b'#' => parse_line(s).map(|(line, rest)| (SimpleString(line), rest)),
+        b'-' => parse_line(s).map(|(line, rest)| (RedisData::Error(line), rest)),
+        b'_' => Ok((Null, skip_line(s, 0)?)),
+        e => Err(InvalidLineStart(char::from(e).to_string())),
     }
 }
-fn skip_line(s: &str, len: usize) -> RedisParser<&str> {
-    let line = s.get(..len + 2).ok_or(Incomplete)?;
-    if !line.ends_with("\r\n") {
-        Err(InvalidLineEnd(len, s.to_string()))?;
+fn skip_line(s: &[u8], len: usize) -> RedisParser<&[u8]> {
+    let line_end = s.get(len..len + "\r\n".len()).ok_or(Incomplete)?;
+    if line_end != b"\r\n" {
+        Err(InvalidLineEnd(len, String::from_utf8_lossy(s).to_string()))?;
     }
-    Ok(s.get(len + "\r\n".len()..).ok_or(Incomplete)?)
+    Ok(&s[len + "\r\n".len()..])
 }
 
-fn parse_number_at<'a>(s: &'a str) -> RedisParser<(usize, &'a str)> {
-    let len = s.chars().position(|c| !c.is_numeric()).ok_or(Incomplete)?;
-    Ok((s[..len].parse()?, skip_line(s, len)?))
+/// Return the bytes up to the next `\r\n` and the remainder after it
+fn parse_line(s: &[u8]) -> RedisParser<(&[u8], &[u8])> {
+    let len = s.windows(2).position(|w| w == b"\r\n").ok_or(Incomplete)?;
+    Ok((&s[..len], skip_line(s, len)?))
+}
+
+fn parse_number_at(s: &[u8]) -> RedisParser<(i64, &[u8])> {
+    let (line, rest) = parse_line(s)?;
+    Ok((str::from_utf8(line)?.parse()?, rest))
 }
 
 /// Parse a Redis bulk string and return the content of that string and the unparsed remainder.
 ///
-/// All bulk strings have the format `$[LENGTH_OF_ITEM_BODY]\r\n[ITEM_BODY]\r\n`
-fn parse_redis_bulk_string<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)> {
+/// All bulk strings have the format `$[LENGTH_OF_ITEM_BODY]\r\n[ITEM_BODY]\r\n` (or `$-1\r\n`
+/// for a null bulk string)
+fn parse_redis_bulk_string(s: &[u8]) -> RedisParser<(RedisData<'_>, &[u8])> {
     let (len, rest) = parse_number_at(s)?;
+    let len = match usize::try_from(len) {
+        Ok(len) => len,
+        Err(_) => return Ok((Null, rest)), // `$-1\r\n`
+    };
     let content = rest.get(..len).ok_or(Incomplete)?;
     Ok((BulkString(content), skip_line(rest, len)?))
 }
 
-fn parse_redis_int<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)> {
+fn parse_redis_int(s: &[u8]) -> RedisParser<(RedisData<'_>, &[u8])> {
     let (number, rest) = parse_number_at(s)?;
     Ok((Integer(number), rest))
 }
 
-fn parse_redis_array<'a>(s: &'a str) -> RedisParser<(RedisData, &'a str)> {
+/// Parse the elements of an array, push frame, or set (a null array is returned as empty).
+///
+/// The elements are returned in *reverse* order, so that they can be `pop`ed in order.
+fn parse_redis_array(s: &[u8]) -> RedisParser<(Vec<RedisData<'_>>, &[u8])> {
     let (number_of_elements, mut rest) = parse_number_at(s)?;
+    let number_of_elements = usize::try_from(number_of_elements).unwrap_or(0);
 
     let mut inner = Vec::with_capacity(number_of_elements);
     inner.resize(number_of_elements, RedisData::Uninitilized);
 
     for i in (0..number_of_elements).rev() {
-        let (next_el, new_rest) = utf8_to_redis_data(rest)?;
+        let (next_el, new_rest) = bytes_to_redis_data(rest)?;
         rest = new_rest;
         inner[i] = next_el;
     }
-    Ok((RedisData::RedisArray(inner), rest))
+    Ok((inner, rest))
 }
 
-impl<'a> TryFrom<RedisData<'a>> for &'a str {
+fn parse_redis_map(s: &[u8]) -> RedisParser<(RedisData<'_>, &[u8])> {
+    let (number_of_pairs, mut rest) = parse_number_at(s)?;
+    let mut pairs = Vec::with_capacity(usize::try_from(number_of_pairs).unwrap_or(0));
+    for _ in 0..number_of_pairs {
+        let (key, new_rest) = bytes_to_redis_data(rest)?;
+        let (value, new_rest) = bytes_to_redis_data(new_rest)?;
+        rest = new_rest;
+        pairs.push((key, value));
+    }
+    Ok((Map(pairs), rest))
+}
+
+impl<'a> TryFrom<RedisData<'a>> for &'a [u8] {
     type Error = RedisParseErr;
 
     fn try_from(val: RedisData<'a>) -> Result<Self, Self::Error> {
         match val {
-            RedisData::BulkString(inner) => Ok(inner),
+            RedisData::BulkString(inner) | RedisData::SimpleString(inner) => Ok(inner),
             _ => Err(IncorrectRedisType),
         }
     }
 }
 
+impl<'a> TryFrom<RedisData<'a>> for &'a str {
+    type Error = RedisParseErr;
+
+    /// Extract the text of a string, validating that it is UTF-8
+    fn try_from(val: RedisData<'a>) -> Result<Self, Self::Error> {
+        let bytes: &[u8] = val.try_into()?;
+        Ok(str::from_utf8(bytes)?)
+    }
+}
+
+impl<'a> TryFrom<RedisData<'a>> for Option<&'a str> {
+    type Error = RedisParseErr;
+
+    /// Extract the text of a string that may be null
+    fn try_from(val: RedisData<'a>) -> Result<Self, Self::Error> {
+        match val {
+            Null => Ok(None),
+            val => Ok(Some(val.try_into()?)),
+        }
+    }
+}
+
+/// Parse the entries of each stream in an `XREAD` reply, given as (stream, entries) pairs.
+///
+/// Each entry is an array of its ID and an array of alternating fields and values.
+fn parse_stream_entries<'a>(
+    streams: Vec<(RedisData<'a>, RedisData<'a>)>,
+) -> RedisParser<Vec<StreamEntry<'a>>> {
+    let mut stream_entries = Vec::new();
+    for (stream, entries) in streams {
+        let stream: &str = stream.try_into()?;
+        let entries = match entries {
+            RedisArray(entries) => entries,
+            _ => Err(IncorrectRedisType)?,
+        };
+        for entry in entries.into_iter().rev() {
+            let mut entry = match entry {
+                RedisArray(entry) => entry,
+                _ => Err(IncorrectRedisType)?,
+            };
+            let id = entry.pop().ok_or(MissingField)?.try_into()?;
+            let mut fields = match entry.pop().ok_or(MissingField)? {
+                RedisArray(fields) => fields,
+                Null => Vec::new(), // the entry has been deleted
+                _ => Err(IncorrectRedisType)?,
+            };
+            let mut pairs = Vec::with_capacity(fields.len() / 2);
+            while let (Some(field), Some(value)) = (fields.pop(), fields.pop()) {
+                pairs.push((field.try_into()?, value.try_into()?));
+            }
+            stream_entries.push(StreamEntry {
+                stream,
+                id,
+                fields: pairs,
+            });
+        }
+    }
+    Ok(stream_entries)
+}
+
 impl<'a> TryFrom<RedisStructuredText<'a>> for RedisParseOutput<'a> {
     type Error = RedisParseErr;
 
     fn try_from(input: RedisStructuredText<'a>) -> Result<RedisParseOutput<'a>, Self::Error> {
-        if let RedisData::RedisArray(mut redis_strings) = input.structured_txt {
-            let command = redis_strings.pop().ok_or(MissingField)?.try_into()?;
-            match command {
-                // subscription statuses look like:
-                // $14\r\ntimeline:local\r\n
-                // :47\r\n
-                "subscribe" | "unsubscribe" => Ok(NonMsg(input.leftover_input)),
-                // Messages look like;
-                // $10\r\ntimeline:4\r\n
-                // $1386\r\n{\"event\":\"update\",\"payload\"...\"queued_at\":1569623342825}\r\n
-                "message" => Ok(Msg(RedisMsg {
-                    timeline_txt: redis_strings.pop().ok_or(MissingField)?.try_into()?,
-                    event_txt: redis_strings.pop().ok_or(MissingField)?.try_into()?,
-                    leftover_input: input.leftover_input,
-                })),
-                _cmd => Err(Incomplete),
+        let leftover_input = input.leftover_input;
+        // With RESP3, pub/sub messages are push frames; with RESP2 they are arrays
+        let mut redis_strings = match input.structured_txt {
+            // With RESP2, `XREAD` replies with an array of (stream, entries) arrays
+            RedisArray(streams) if matches!(streams.last(), Some(RedisArray(_))) => {
+                let streams = streams
+                    .into_iter()
+                    .rev()
+                    .map(|stream| match stream {
+                        RedisArray(mut pair) if pair.len() == 2 => Ok((
+                            pair.pop().ok_or(MissingField)?,
+                            pair.pop().ok_or(MissingField)?,
+                        )),
+                        _ => Err(IncorrectRedisType),
+                    })
+                    .collect::<RedisParser<_>>()?;
+                return Ok(StreamEntries(
+                    parse_stream_entries(streams)?,
+                    leftover_input,
+                ));
+            }
+            // With RESP3, it replies with a map of streams to their entries
+            Map(streams) if matches!(streams.first(), Some((_, RedisArray(_)))) => {
+                return Ok(StreamEntries(
+                    parse_stream_entries(streams)?,
+                    leftover_input,
+                ));
+            }
+            RedisArray(redis_strings) | Push(redis_strings) => redis_strings,
+            SimpleString(b"PONG") => return Ok(Pong(leftover_input)),
+            RedisData::Error(err) => return Ok(RedisErr(str::from_utf8(err)?, leftover_input)),
+            // Replies to commands other than SUBSCRIBE (e.g., the map that HELLO returns)
+            _ => return Ok(NonMsg(leftover_input)),
+        };
+
+        let command: &[u8] = match redis_strings.pop() {
+            Some(BulkString(command) | SimpleString(command)) => command,
+            _ => return Ok(NonMsg(leftover_input)), // e.g., a reply to MSET or EXEC
+        };
+        let mut next = || redis_strings.pop().ok_or(MissingField);
+        let subscription = |kind, channel, count| -> Result<RedisParseOutput<'a>, RedisParseErr> {
+            let count = match count {
+                Integer(count) => count,
+                _ => Err(IncorrectRedisType)?,
+            };
+            let reply = SubscriptionReply {
+                kind,
+                channel,
+                count,
+            };
+            Ok(Subscription(reply, leftover_input))
+        };
+
+        use SubscriptionKind::*;
+        match command {
+            // subscription statuses look like:
+            // $14\r\ntimeline:local\r\n
+            // :47\r\n
+            b"subscribe" => subscription(Subscribe, next()?.try_into()?, next()?),
+            b"unsubscribe" => subscription(Unsubscribe, next()?.try_into()?, next()?),
+            b"psubscribe" => subscription(Psubscribe, next()?.try_into()?, next()?),
+            b"punsubscribe" => subscription(Punsubscribe, next()?.try_into()?, next()?),
+            // Messages look like;
+            // $10\r\ntimeline:4\r\n
+            // $1386\r\n{\"event\":\"update\",\"payload\"...\"queued_at\":1569623342825}\r\n
+            b"message" => Ok(Msg(RedisMsg {
+                timeline_txt: next()?.try_into()?,
+                event_txt: next()?.try_into()?,
+                leftover_input,
+            })),
+            // Pattern messages have the matching pattern before the channel
+            b"pmessage" => {
+                let _pattern: &[u8] = next()?.try_into()?;
+                Ok(Msg(RedisMsg {
+                    timeline_txt: next()?.try_into()?,
+                    event_txt: next()?.try_into()?,
+                    leftover_input,
+                }))
             }
-        } else {
-            Err(IncorrectRedisType)
+            // In subscribed mode, RESP2 replies to PING as `*2\r\n$4\r\npong\r\n$0\r\n\r\n`
+            b"pong" => Ok(Pong(leftover_input)),
+            _ => Ok(NonMsg(leftover_input)),
         }
     }
 }
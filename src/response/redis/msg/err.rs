@@ -4,6 +4,7 @@ use std::{error::Error, fmt};
 pub enum RedisParseErr {
     Incomplete,
     InvalidNumber(std::num::ParseIntError),
+    InvalidUtf8(std::str::Utf8Error),
     InvalidLineStart(String),
     InvalidLineEnd(usize, String),
     IncorrectRedisType,
@@ -23,6 +24,10 @@ impl fmt::Display for RedisParseErr {
                 parse_int_err
             ),
 
+            InvalidUtf8(utf8_err) => format!(
+                "Redis sent a timeline or message that is not valid UTF-8: {}",
+                utf8_err
+            ),
             InvalidLineStart(line_start_char) => format!(
                 "A line from Redis started with `{}`, which is not a valid character to indicate \
                 the type of the Redis line.",
@@ -46,6 +51,12 @@ impl fmt::Display for RedisParseErr {
 
 impl Error for RedisParseErr {}
 
+impl From<std::str::Utf8Error> for RedisParseErr {
+    fn from(error: std::str::Utf8Error) -> Self {
+        Self::InvalidUtf8(error)
+    }
+}
+
 impl From<std::num::ParseIntError> for RedisParseErr {
     fn from(error: std::num::ParseIntError) -> Self {
         Self::InvalidNumber(error)
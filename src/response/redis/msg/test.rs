@@ -5,12 +5,15 @@ use std::fs;
 fn parse_redis_subscribe() -> Result<(), RedisParseErr> {
     let input = "*3\r\n$9\r\nsubscribe\r\n$15\r\ntimeline:public\r\n:1\r\n";
 
-    let r_subscribe = match RedisParseOutput::try_from(input) {
-        Ok(NonMsg(leftover)) => leftover,
-        Ok(Msg(msg)) => panic!("unexpectedly got a msg: {:?}", msg),
+    let (r_subscribe, leftover) = match RedisParseOutput::try_from(input.as_bytes()) {
+        Ok(Subscription(reply, leftover)) => (reply, leftover),
+        Ok(other) => panic!("unexpectedly got {:?}", other),
         Err(e) => panic!("Error in parsing subscribe command: {}", e),
     };
-    assert!(r_subscribe.is_empty());
+    assert!(leftover.is_empty());
+    assert_eq!(r_subscribe.kind, SubscriptionKind::Subscribe);
+    assert_eq!(r_subscribe.channel, Some("timeline:public"));
+    assert_eq!(r_subscribe.count, 1);
 
     Ok(())
 }
@@ -20,17 +23,12 @@ fn parse_redis_detects_non_newline() -> Result<(), RedisParseErr> {
     let input =
         "*3QQ$7\r\nmessage\r\n$12\r\ntimeline:308\r\n$38\r\n{\"event\":\"delete\",\"payload\":\"1038647\"}\r\n";
 
-    match RedisParseOutput::try_from(input) {
-        Ok(NonMsg(leftover)) => panic!(
-            "Parsed an invalid msg as a non-msg.\nInput `{}` parsed to NonMsg({})",
-            &input, leftover
-        ),
-        Ok(Msg(msg)) => panic!(
-            "Parsed an invalid msg as a msg.\nInput `{}` parsed to {:?}",
-            &input, msg
-        ),
-        Err(_) => (), // should err
-    };
+    if let Ok(output) = RedisParseOutput::try_from(input.as_bytes()) {
+        panic!(
+            "Parsed an invalid msg.\nInput `{}` parsed to {:?}",
+            &input, output
+        );
+    }
 
     Ok(())
 }
@@ -40,12 +38,12 @@ fn parse_redis_msg() -> Result<(), RedisParseErr> {
     let input =
         "*3\r\n$7\r\nmessage\r\n$12\r\ntimeline:308\r\n$38\r\n{\"event\":\"delete\",\"payload\":\"1038647\"}\r\n";
 
-    let r_msg = match RedisParseOutput::try_from(input) {
-        Ok(NonMsg(leftover)) => panic!(
-            "Parsed a msg as a non-msg.\nInput `{}` parsed to NonMsg({:?})",
-            &input, leftover
-        ),
+    let r_msg = match RedisParseOutput::try_from(input.as_bytes()) {
         Ok(Msg(msg)) => msg,
+        Ok(other) => panic!(
+            "Parsed a msg as a non-msg.\nInput `{}` parsed to {:?}",
+            &input, other
+        ),
         Err(e) => panic!("Error in parsing subscribe command: {}", e),
     };
 
@@ -65,12 +63,12 @@ fn parse_long_redis_msg() -> Result<(), Box<dyn std::error::Error>> {
         println!("parsing `{:03}.resp`", test_num);
         test_num += 1;
 
-        let r_msg = match RedisParseOutput::try_from(input.as_str()) {
-            Ok(NonMsg(leftover)) => panic!(
-                "Parsed a msg as a non-msg.\nInput `{}` parsed to NonMsg({:?})",
-                &input, leftover
-            ),
+        let r_msg = match RedisParseOutput::try_from(input.as_bytes()) {
             Ok(Msg(msg)) => msg,
+            Ok(other) => panic!(
+                "Parsed a msg as a non-msg.\nInput `{}` parsed to {:?}",
+                &input, other
+            ),
             Err(e) => panic!("Error in parsing Redis input: {}", e),
         };
         assert!(r_msg.leftover_input.is_empty());
@@ -82,3 +80,241 @@ fn parse_long_redis_msg() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn parse_resp3_push_msg() -> Result<(), RedisParseErr> {
+    let input =
+        ">3\r\n$7\r\nmessage\r\n$12\r\ntimeline:308\r\n$38\r\n{\"event\":\"delete\",\"payload\":\"1038647\"}\r\n";
+
+    let r_msg = match RedisParseOutput::try_from(input.as_bytes())? {
+        Msg(msg) => msg,
+        other => panic!("Parsed a push msg as a non-msg: {:?}", other),
+    };
+
+    assert!(r_msg.leftover_input.is_empty());
+    assert_eq!(r_msg.timeline_txt, "timeline:308");
+    assert_eq!(r_msg.event_txt, r#"{"event":"delete","payload":"1038647"}"#);
+    Ok(())
+}
+
+#[test]
+fn parse_resp3_replies_as_non_msgs() -> Result<(), RedisParseErr> {
+    let hello = "%2\r\n$6\r\nserver\r\n$5\r\nredis\r\n$5\r\nproto\r\n:3\r\n";
+    let with_attribute = "|1\r\n+key-popularity\r\n%1\r\n$1\r\na\r\n,0.1923\r\n+OK\r\n";
+    let null = "_\r\n";
+
+    for input in &[hello, with_attribute, null] {
+        let next_input = "+PONG\r\n";
+        let input = [*input, next_input].concat();
+        match RedisParseOutput::try_from(input.as_bytes())? {
+            NonMsg(leftover) => assert_eq!(leftover, next_input.as_bytes()),
+            other => panic!("Expected a non-msg, got {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_resp3_detects_incomplete_map() {
+    let input = "%2\r\n$6\r\nserver\r\n$5\r\nredis\r\n$5\r\npro";
+
+    match RedisParseOutput::try_from(input.as_bytes()) {
+        Err(RedisParseErr::Incomplete) => (),
+        other => panic!("Expected an incomplete input, got {:?}", other),
+    }
+}
+
+#[test]
+fn parse_redis_msg_split_within_a_character() -> Result<(), RedisParseErr> {
+    let input = "*3\r\n$7\r\nmessage\r\n$15\r\ntimeline:public\r\n$4\r\n🐘\r\n".as_bytes();
+    let split_idx = input.len() - "\r\n".len() - 2; // halfway through the elephant
+
+    match RedisParseOutput::try_from(&input[..split_idx]) {
+        Err(RedisParseErr::Incomplete) => (),
+        other => panic!("Expected an incomplete input, got {:?}", other),
+    }
+    match RedisParseOutput::try_from(input)? {
+        Msg(msg) => assert_eq!(msg.event_txt, "🐘"),
+        other => panic!("Parsed a msg as a non-msg: {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_redis_msg_rejects_invalid_utf8_payload() {
+    let input = b"*3\r\n$7\r\nmessage\r\n$15\r\ntimeline:public\r\n$2\r\n\xF0\x9F\r\n";
+
+    match RedisParseOutput::try_from(&input[..]) {
+        Err(RedisParseErr::InvalidUtf8(_)) => (),
+        other => panic!("Expected invalid UTF-8, got {:?}", other),
+    }
+}
+
+#[test]
+fn parse_redis_errors() -> Result<(), RedisParseErr> {
+    let simple_err = "-NOPERM this user has no permissions to access one of the channels\r\n";
+    let blob_err = "!21\r\nSYNTAX invalid syntax\r\n";
+
+    for (input, expected) in &[
+        (
+            simple_err,
+            "NOPERM this user has no permissions to access one of the channels",
+        ),
+        (blob_err, "SYNTAX invalid syntax"),
+    ] {
+        match RedisParseOutput::try_from(input.as_bytes())? {
+            RedisErr(msg, leftover) => {
+                assert_eq!(&msg, expected);
+                assert!(leftover.is_empty());
+            }
+            other => panic!("Expected an error, got {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_redis_pattern_replies() -> Result<(), RedisParseErr> {
+    let psubscribe = "*3\r\n$10\r\npsubscribe\r\n$10\r\ntimeline:*\r\n:1\r\n";
+    match RedisParseOutput::try_from(psubscribe.as_bytes())? {
+        Subscription(reply, _) => {
+            assert_eq!(reply.kind, SubscriptionKind::Psubscribe);
+            assert_eq!(reply.channel, Some("timeline:*"));
+        }
+        other => panic!("Expected a subscription reply, got {:?}", other),
+    }
+
+    let pmessage = "*4\r\n$8\r\npmessage\r\n$10\r\ntimeline:*\r\n$12\r\ntimeline:308\r\n$38\r\n{\"event\":\"delete\",\"payload\":\"1038647\"}\r\n";
+    match RedisParseOutput::try_from(pmessage.as_bytes())? {
+        Msg(msg) => {
+            assert_eq!(msg.timeline_txt, "timeline:308");
+            assert_eq!(msg.event_txt, r#"{"event":"delete","payload":"1038647"}"#);
+        }
+        other => panic!("Expected a msg, got {:?}", other),
+    }
+
+    let unsubscribe_all = "*3\r\n$12\r\npunsubscribe\r\n$-1\r\n:0\r\n";
+    match RedisParseOutput::try_from(unsubscribe_all.as_bytes())? {
+        Subscription(reply, _) => {
+            assert_eq!(reply.kind, SubscriptionKind::Punsubscribe);
+            assert_eq!(reply.channel, None);
+            assert_eq!(reply.count, 0);
+        }
+        other => panic!("Expected a subscription reply, got {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_redis_pongs_and_unknown_replies() -> Result<(), RedisParseErr> {
+    let resp2_pong = "*2\r\n$4\r\npong\r\n$0\r\n\r\n";
+    let resp3_pong = "+PONG\r\n";
+    for input in &[resp2_pong, resp3_pong] {
+        match RedisParseOutput::try_from(input.as_bytes())? {
+            Pong(leftover) => assert!(leftover.is_empty()),
+            other => panic!("Expected a pong, got {:?}", other),
+        }
+    }
+
+    let unknown = "*2\r\n$8\r\nsmessage\r\n$3\r\nfoo\r\n+OK\r\n";
+    match RedisParseOutput::try_from(unknown.as_bytes())? {
+        NonMsg(leftover) => assert_eq!(leftover, b"+OK\r\n"),
+        other => panic!("Expected a non-msg, got {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_redis_xread_replies() -> Result<(), RedisParseErr> {
+    let event = r#"{"event":"delete","payload":"1038647"}"#;
+    let resp2 = format!(
+        "*1\r\n*2\r\n$15\r\ntimeline:public\r\n*2\r\n\
+         *2\r\n$15\r\n1590000000000-0\r\n*2\r\n$5\r\nevent\r\n$38\r\n{0}\r\n\
+         *2\r\n$15\r\n1590000000000-1\r\n*4\r\n$8\r\ntimeline\r\n$12\r\ntimeline:308\r\n$5\r\nevent\r\n$38\r\n{0}\r\n",
+        event
+    );
+    let resp3 = format!(
+        "%1\r\n$15\r\ntimeline:public\r\n*2\r\n\
+         *2\r\n$15\r\n1590000000000-0\r\n*2\r\n$5\r\nevent\r\n$38\r\n{0}\r\n\
+         *2\r\n$15\r\n1590000000000-1\r\n*4\r\n$8\r\ntimeline\r\n$12\r\ntimeline:308\r\n$5\r\nevent\r\n$38\r\n{0}\r\n",
+        event
+    );
+    for input in &[resp2, resp3] {
+        let entries = match RedisParseOutput::try_from(input.as_bytes())? {
+            StreamEntries(entries, []) => entries,
+            other => panic!("Expected stream entries, got {:?}", other),
+        };
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.stream == "timeline:public"));
+        assert_eq!(entries[0].id, "1590000000000-0");
+        assert_eq!(entries[0].field("event")?, event);
+        assert!(entries[0].field("timeline").is_err());
+        assert_eq!(entries[1].id, "1590000000000-1");
+        assert_eq!(entries[1].field("timeline")?, "timeline:308");
+    }
+
+    // When no entries arrive before `XREAD` stops blocking, it replies with null
+    for timeout in &["*-1\r\n", "_\r\n"] {
+        match RedisParseOutput::try_from(timeout.as_bytes())? {
+            NonMsg(leftover) => assert!(leftover.is_empty()),
+            other => panic!("Expected a non-msg, got {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn timelines_are_only_read_from_channels_in_our_namespace() {
+    let msg = |timeline_txt| RedisMsg {
+        timeline_txt,
+        event_txt: "{}",
+        leftover_input: &[],
+    };
+    let (no_ns, ns) = (None, Some("mastodon".to_string()));
+
+    assert_eq!(
+        msg("timeline:public").timeline_matching_ns(&no_ns),
+        Some("public")
+    );
+    assert_eq!(
+        msg("mastodon:timeline:public").timeline_matching_ns(&ns),
+        Some("public")
+    );
+    // Channel names that are too short, or that are from another app or namespace
+    for foreign in &[
+        "",
+        "time",
+        "timeline",
+        "mastodon",
+        "mastodon:",
+        "other:timeline:public",
+    ] {
+        assert_eq!(
+            msg(foreign).timeline_matching_ns(&ns),
+            None,
+            "`{}`",
+            foreign
+        );
+    }
+    for foreign in &[
+        "",
+        "time",
+        "timeline",
+        "mastodon:timeline:public",
+        "notifications:1",
+    ] {
+        assert_eq!(
+            msg(foreign).timeline_matching_ns(&no_ns),
+            None,
+            "`{}`",
+            foreign
+        );
+    }
+    assert_eq!(
+        msg("mastodonx:timeline:public").timeline_matching_ns(&ns),
+        None
+    );
+    assert_eq!(msg("mastodon:public").timeline_matching_ns(&ns), None);
+}
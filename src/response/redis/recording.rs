@@ -0,0 +1,266 @@
+//! Recordings of the input read from Redis, which can be replayed in place of Redis to
+//! reproduce problems (such as input that fails to parse) outside of production.
+//!
+//! A recording is two files: `<path>` holds every byte read from Redis (so it is a `.resp`
+//! file like those in `test_data`), and `<path>.timestamps` has a line for each read with the
+//! offset of its first byte and the milliseconds since recording started (e.g., `8192 350`).
+//!
+//! When we reconnect to Redis, any partial message from the old connection is discarded; the
+//! timestamps mark this with a line ending in `reconnected` (e.g., `9000 4000 reconnected`)
+//! so that replay discards it as well.
+use super::{Error, Event, EventSource, RedisInput};
+use crate::config;
+use crate::request::Timeline;
+
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use hashbrown::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+/// The last field of the timestamp lines that mark a reconnection
+const RECONNECTED: &str = "reconnected";
+
+fn timestamps_path(path: &str) -> String {
+    format!("{}.timestamps", path)
+}
+
+/// Writes everything read from Redis to a recording (if `RECORD_REDIS` is set)
+#[derive(Debug)]
+pub(super) struct Recorder {
+    resp: File,
+    timestamps: File,
+    started: Instant,
+    len: usize,
+}
+
+impl Recorder {
+    pub(super) fn create(path: &str) -> io::Result<Self> {
+        let recorder = Self {
+            resp: File::create(path)?,
+            timestamps: File::create(timestamps_path(path))?,
+            started: Instant::now(),
+            len: 0,
+        };
+        log::info!("Recording all input from Redis to {}", path);
+        Ok(recorder)
+    }
+
+    /// Wrap `reader` so that everything read from it is also recorded (if we are recording)
+    pub(super) fn tee<'a, R: Read>(
+        recorder: &'a mut Option<Self>,
+        reader: &'a mut R,
+    ) -> Tee<'a, R> {
+        Tee { recorder, reader }
+    }
+
+    /// Mark the point at which we reconnected to Redis (and discarded any unparsed input)
+    pub(super) fn reconnected(&mut self) -> io::Result<()> {
+        let ms = self.started.elapsed().as_millis();
+        writeln!(self.timestamps, "{} {} {}", self.len, ms, RECONNECTED)
+    }
+
+    fn record(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let ms = self.started.elapsed().as_millis();
+        self.resp.write_all(bytes)?;
+        writeln!(self.timestamps, "{} {}", self.len, ms)?;
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+
+/// A reader that records everything read through it
+pub(super) struct Tee<'a, R> {
+    recorder: &'a mut Option<Recorder>,
+    reader: &'a mut R,
+}
+
+impl<R: Read> Read for Tee<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if let (Some(recorder), true) = (self.recorder.as_mut(), n > 0) {
+            if let Err(e) = recorder.record(&buf[..n]) {
+                // A full disk shouldn't take down the server, so stop recording instead
+                log::error!("Could not record the input from Redis; stopping: {}", e);
+                *self.recorder = None;
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Replays a recording made with `RECORD_REDIS` as though it were being read from Redis, at
+/// `REPLAY_SPEED` times the speed it was recorded at.  Replay starts once the first client
+/// subscribes, and events for timelines without clients are discarded.
+///
+/// A `.resp` file without timestamps (such as those in `test_data`) is replayed all at once.
+#[derive(Debug)]
+pub struct ReplaySource {
+    recording: Vec<u8>,
+    /// The offset and time (since recording started) of each read from Redis, and whether
+    /// the input was discarded (after a reconnection) before that read
+    reads: Vec<(usize, Duration, bool)>,
+    next_read: usize,
+    speed: f64,
+    started: Option<Instant>,
+    /// The task to wake once replay starts
+    waiting: Option<Task>,
+    delay: Option<Delay>,
+    input: RedisInput,
+}
+
+impl ReplaySource {
+    /// Load the recording at `REPLAY_REDIS`
+    pub fn new(redis_cfg: &config::Redis) -> io::Result<Self> {
+        let path = redis_cfg.replay.clone().0.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "REPLAY_REDIS is not set")
+        })?;
+        let replay = Self::open(&path, *redis_cfg.replay_speed, RedisInput::new(redis_cfg))?;
+        log::info!(
+            "Loaded {} reads from {} to replay at {}x speed",
+            replay.reads.len(),
+            path,
+            replay.speed
+        );
+        Ok(replay)
+    }
+
+    fn open(path: &str, speed: f64, mut input: RedisInput) -> io::Result<Self> {
+        let recording = fs::read(path)?;
+        let reads = match fs::read_to_string(timestamps_path(path)) {
+            Ok(timestamps) => timestamps
+                .lines()
+                .map(|line| {
+                    let mut fields = line.split_whitespace();
+                    let offset = fields.next().map(str::parse::<usize>);
+                    let ms = fields.next().map(str::parse::<u64>);
+                    let reconnected = match (fields.next(), fields.next()) {
+                        (None, None) => Some(false),
+                        (Some(RECONNECTED), None) => Some(true),
+                        _ => None,
+                    };
+                    match (offset, ms, reconnected) {
+                        (Some(Ok(offset)), Some(Ok(ms)), Some(reconnected)) => {
+                            Ok((offset, Duration::from_millis(ms), reconnected))
+                        }
+                        _ => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("invalid line in {}: `{}`", timestamps_path(path), line),
+                        )),
+                    }
+                })
+                .collect::<io::Result<_>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                vec![(0, Duration::from_secs(0), false)]
+            }
+            Err(e) => Err(e)?,
+        };
+
+        // A recording can hold events for any timeline (even with `SUBSCRIBE`s, since clients
+        // come and go), so discard events for timelines without clients, as in pattern mode
+        input.pattern_timelines = Some(HashSet::new());
+        Ok(Self {
+            recording,
+            reads,
+            next_read: 0,
+            speed,
+            started: None,
+            waiting: None,
+            delay: None,
+            input,
+        })
+    }
+
+    /// Add the next read to the input once it is due.  Returns `NotReady` (with the current
+    /// task registered to be woken when the read is due) if it isn't yet.
+    fn poll_next_read(&mut self, started: Instant) -> Poll<(), Error> {
+        let (offset, at, reconnected) = match self.reads.get(self.next_read) {
+            Some(read) => *read,
+            None => return Ok(Async::NotReady), // the whole recording has been replayed
+        };
+
+        let due = started + at.div_f64(self.speed);
+        if Instant::now() < due {
+            let delay = self.delay.get_or_insert_with(|| Delay::new(due));
+            if delay.deadline() != due {
+                delay.reset(due);
+            }
+            match delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => (),
+                Err(e) => log::error!("Could not wait to replay the next read: {}", e),
+            }
+        }
+
+        self.next_read += 1;
+        if reconnected {
+            self.input.clear();
+        }
+        let end = self
+            .reads
+            .get(self.next_read)
+            .map_or(self.recording.len(), |(offset, _, _)| *offset);
+        self.input.add(&self.recording[offset..end]);
+        if self.next_read == self.reads.len() {
+            log::info!("Finished replaying the recording");
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl EventSource for ReplaySource {
+    fn subscribe(&mut self, timelines: &[Timeline]) -> Result<(), Error> {
+        if self.started.is_none() {
+            log::info!("Replaying the recording");
+            self.started = Some(Instant::now());
+            if let Some(task) = self.waiting.take() {
+                task.notify();
+            }
+        }
+        if let Some(wanted) = &mut self.input.pattern_timelines {
+            wanted.extend(timelines);
+        }
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, timelines: &[Timeline]) -> Result<(), Error> {
+        if let Some(wanted) = &mut self.input.pattern_timelines {
+            for tl in timelines {
+                wanted.remove(tl);
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Poll<Option<(Timeline, Arc<Event>)>, Error> {
+        let started = match self.started {
+            Some(started) => started,
+            None => {
+                self.waiting = Some(task::current());
+                return Ok(Async::NotReady);
+            }
+        };
+        loop {
+            if let Some(event) = self.input.next_event()? {
+                return Ok(Async::Ready(Some(event)));
+            }
+            if let Async::NotReady = self.poll_next_read(started)? {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+
+    fn cache_hashtag(&mut self, id: i64, name: &str) {
+        self.input.tag_id_cache.put(name.to_string(), id);
+    }
+
+    fn unread_len(&self) -> usize {
+        self.input.unread_len()
+    }
+}
+
+#[cfg(test)]
+mod test;
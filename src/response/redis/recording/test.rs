@@ -0,0 +1,78 @@
+use super::*;
+use futures::future;
+use lru::LruCache;
+use std::env;
+use std::process;
+
+type TestResult = std::result::Result<(), Box<dyn std::error::Error>>;
+
+#[test]
+fn replaying_a_recording_yields_the_recorded_events() -> TestResult {
+    let path = env::temp_dir().join(format!("flodgatt_recording_{}.resp", process::id()));
+    let path = path.to_str().expect("temp dir should be UTF-8");
+
+    let mut recorder = Some(Recorder::create(path)?);
+    for i in 1..=6 {
+        let input = fs::read(format!("test_data/redis_input_{:03}.resp", i))?;
+        let mut input = &input[..];
+        // Many small reads, so that events are split across reads as they are from Redis
+        let mut buffer = [0_u8; 1000];
+        while Recorder::tee(&mut recorder, &mut input).read(&mut buffer)? > 0 {}
+    }
+    drop(recorder);
+    let timestamps = fs::read_to_string(timestamps_path(path))?;
+    assert!(timestamps.lines().count() > 6);
+
+    let mut replay = ReplaySource::open(path, 1.0, RedisInput::new(&config::Redis::default()))?;
+    let public = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    future::lazy(|| -> TestResult {
+        assert_eq!(replay.poll_event()?, Async::NotReady); // no one has subscribed
+        replay.subscribe(&[public])?;
+
+        let mut events = 0;
+        while let Async::Ready(Some((tl, _event))) = replay.poll_event()? {
+            assert_eq!(tl, public);
+            events += 1;
+        }
+        Ok(assert_eq!(events, 6))
+    })
+    .wait()?;
+
+    fs::remove_file(path)?;
+    Ok(fs::remove_file(timestamps_path(path))?)
+}
+
+#[test]
+fn replay_discards_partial_input_from_before_a_reconnection() -> TestResult {
+    let path = env::temp_dir().join(format!("flodgatt_reconnection_{}.resp", process::id()));
+    let path = path.to_str().expect("temp dir should be UTF-8");
+
+    let mut recorder = Some(Recorder::create(path)?);
+    let partial = fs::read("test_data/redis_input_001.resp")?;
+    let mut partial = &partial[..partial.len() / 2];
+    Recorder::tee(&mut recorder, &mut partial).read_to_end(&mut Vec::new())?;
+    recorder.as_mut().expect("still recording").reconnected()?;
+    let mut whole = &fs::read("test_data/redis_input_002.resp")?[..];
+    Recorder::tee(&mut recorder, &mut whole).read_to_end(&mut Vec::new())?;
+    drop(recorder);
+    let timestamps = fs::read_to_string(timestamps_path(path))?;
+    assert!(timestamps
+        .lines()
+        .any(|line| line.ends_with(" reconnected")));
+
+    let mut replay = ReplaySource::open(path, 1.0, RedisInput::new(&config::Redis::default()))?;
+    let public = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    future::lazy(|| -> TestResult {
+        replay.subscribe(&[public])?;
+        let mut events = 0;
+        while let Async::Ready(Some((tl, _event))) = replay.poll_event()? {
+            assert_eq!(tl, public);
+            events += 1;
+        }
+        Ok(assert_eq!(events, 1))
+    })
+    .wait()?;
+
+    fs::remove_file(path)?;
+    Ok(fs::remove_file(timestamps_path(path))?)
+}
@@ -0,0 +1,163 @@
+//! Reading events from Redis Streams (with `REDIS_STREAMS`) instead of pub/sub.
+//!
+//! Unlike pub/sub messages, stream entries remain in Redis after they are published.  We
+//! remember the ID of the last entry we read from each stream and `XREAD` the entries after
+//! it, so the events published while we are reconnecting to Redis are read (in order) once
+//! we have reconnected.
+//!
+//! We start reading a stream from `$` (its last entry when Redis receives our `XREAD`).  Entry
+//! IDs start with Redis's clock, so once Redis replies with entries from any stream, streams
+//! without entries of their own continue from (and including) the millisecond of the newest
+//! entry.
+use crate::config;
+
+use hashbrown::HashMap;
+
+/// The streams we read from and the last entry read from each
+#[derive(Debug)]
+pub(super) struct Streams {
+    last_ids: HashMap<String, String>,
+    /// Whether all timelines share `REDIS_STREAM_SHARDS` streams (instead of each timeline
+    /// having a stream named like its pub/sub channel)
+    sharded: bool,
+    awaiting_reply: bool,
+    /// The millisecond part of the newest entry ID in the reply being read
+    newest_ms: Option<u64>,
+    /// Set when Redis replies to `XREAD` with an error, so that we don't retry until the
+    /// next ping cycle
+    paused: bool,
+}
+
+impl Streams {
+    /// How long each `XREAD` blocks for.  New timelines are added to the next `XREAD`, so this
+    /// is also the longest a new timeline's events can be delayed (but none are lost).
+    const BLOCK_MS: usize = 1000;
+    const COUNT: usize = 1000;
+
+    pub(super) fn from_cfg(redis_cfg: &config::Redis) -> Option<Self> {
+        if !*redis_cfg.streams {
+            return None;
+        }
+        let mut streams = Self {
+            last_ids: HashMap::new(),
+            sharded: *redis_cfg.stream_shards > 0,
+            awaiting_reply: false,
+            newest_ms: None,
+            paused: false,
+        };
+        let prefix = match &*redis_cfg.namespace {
+            Some(ns) => format!("{}:timeline:shard:", ns),
+            None => "timeline:shard:".to_string(),
+        };
+        let shards: Vec<String> = (0..*redis_cfg.stream_shards)
+            .map(|i| format!("{}{}", prefix, i))
+            .collect();
+        streams.start_reading(&shards);
+        Some(streams)
+    }
+
+    /// Whether all timelines share the same streams (and events for timelines without
+    /// clients thus need to be discarded)
+    pub(super) fn sharded(&self) -> bool {
+        self.sharded
+    }
+
+    /// Start reading the streams for `timelines` (unless all timelines share the same streams)
+    pub(super) fn subscribe(&mut self, timelines: &[String]) {
+        if !self.sharded {
+            self.start_reading(timelines);
+        }
+    }
+
+    pub(super) fn unsubscribe(&mut self, timelines: &[String]) {
+        if !self.sharded {
+            for tl in timelines {
+                self.last_ids.remove(tl);
+            }
+        }
+    }
+
+    /// Read from each stream starting now (according to Redis).  This keeps the last ID of
+    /// streams we are already reading from, so resubscribing after reconnecting doesn't skip
+    /// any entries.
+    fn start_reading(&mut self, streams: &[String]) {
+        for stream in streams {
+            self.last_ids
+                .entry(stream.clone())
+                .or_insert_with(|| "$".to_string());
+        }
+    }
+
+    /// Remember that we have read the entry with `id` from `stream`
+    pub(super) fn read_entry(&mut self, stream: &str, id: &str) {
+        if let Some(last_id) = self.last_ids.get_mut(stream) {
+            *last_id = id.to_string();
+        }
+        let ms = id.split('-').next().and_then(|ms| ms.parse().ok());
+        self.newest_ms = self.newest_ms.max(ms);
+    }
+
+    /// Note that Redis has replied to our `XREAD` (with an error, if `ok` is false)
+    pub(super) fn replied(&mut self, ok: bool) {
+        self.awaiting_reply = false;
+        self.paused = !ok;
+        // Any later entry in a stream we started reading at `$` is at least as new as the
+        // newest entry in this reply (but `$` would skip those added before our next `XREAD`).
+        // `XREAD` returns the entries after the ID we send, so we send the ID just before the
+        // first possible ID in that millisecond (`<ms>-0`).
+        if let Some(ms) = self.newest_ms.take() {
+            let before_ms = match ms.checked_sub(1) {
+                Some(prev_ms) => format!("{}-{}", prev_ms, u64::MAX),
+                None => "0-0".to_string(),
+            };
+            for last_id in self.last_ids.values_mut().filter(|id| *id == "$") {
+                last_id.clone_from(&before_ms);
+            }
+        }
+    }
+
+    /// Retry after an error (called each ping cycle)
+    pub(super) fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Forget about any `XREAD` sent on a connection that has been closed
+    pub(super) fn reset(&mut self) {
+        self.awaiting_reply = false;
+    }
+
+    /// The `XREAD` to send next, if we are reading from any streams and aren't already
+    /// waiting for the reply to one
+    pub(super) fn next_xread(&mut self) -> Option<Vec<u8>> {
+        if self.awaiting_reply || self.paused || self.last_ids.is_empty() {
+            return None;
+        }
+        self.awaiting_reply = true;
+
+        let (block, count) = (Self::BLOCK_MS.to_string(), Self::COUNT.to_string());
+        let mut args = vec![
+            "XREAD",
+            "COUNT",
+            count.as_str(),
+            "BLOCK",
+            block.as_str(),
+            "STREAMS",
+        ];
+        let (streams, ids): (Vec<&str>, Vec<&str>) = self
+            .last_ids
+            .iter()
+            .map(|(stream, id)| (stream.as_str(), id.as_str()))
+            .unzip();
+        args.extend(streams);
+        args.extend(ids);
+
+        let mut cmd = format!("*{}\r\n", args.len());
+        for arg in args {
+            cmd.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        Some(cmd.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,84 @@
+use super::*;
+
+fn streams(sharded: bool) -> Streams {
+    Streams {
+        last_ids: HashMap::new(),
+        sharded,
+        awaiting_reply: false,
+        newest_ms: None,
+        paused: false,
+    }
+}
+
+#[test]
+fn streams_xread_after_the_last_entry_read() {
+    let mut streams = streams(false);
+    assert_eq!(streams.next_xread(), None); // not reading from any streams yet
+
+    streams.subscribe(&["timeline:public".to_string()]);
+    streams.read_entry("timeline:public", "1590000000000-7");
+    streams.read_entry("timeline:local", "1590000000000-8"); // not a stream we read from
+    let xread = String::from_utf8(streams.next_xread().expect("an XREAD")).unwrap();
+    assert_eq!(
+        xread,
+        "*8\r\n$5\r\nXREAD\r\n$5\r\nCOUNT\r\n$4\r\n1000\r\n$5\r\nBLOCK\r\n$4\r\n1000\r\n\
+         $7\r\nSTREAMS\r\n$15\r\ntimeline:public\r\n$15\r\n1590000000000-7\r\n"
+    );
+    assert_eq!(streams.next_xread(), None); // still waiting for the reply
+
+    // Resubscribing (e.g., after reconnecting) keeps our place in the stream
+    streams.reset();
+    streams.subscribe(&["timeline:public".to_string()]);
+    assert_eq!(streams.last_ids["timeline:public"], "1590000000000-7");
+    assert!(streams.next_xread().is_some());
+
+    streams.replied(false); // an error; wait for the next ping cycle
+    assert_eq!(streams.next_xread(), None);
+    streams.resume();
+    assert!(streams.next_xread().is_some());
+
+    streams.replied(true);
+    streams.unsubscribe(&["timeline:public".to_string()]);
+    assert_eq!(streams.next_xread(), None);
+}
+
+#[test]
+fn sharded_streams_ignore_subscriptions() {
+    let mut streams = streams(true);
+    streams.start_reading(&["timeline:shard:0".to_string()]);
+
+    streams.subscribe(&["timeline:public".to_string()]);
+    streams.unsubscribe(&["timeline:shard:0".to_string()]);
+    assert_eq!(streams.last_ids.len(), 1);
+    assert!(streams.last_ids.contains_key("timeline:shard:0"));
+}
+
+#[test]
+fn streams_start_reading_at_the_time_redis_reports() {
+    let mut streams = streams(false);
+    streams.subscribe(&["timeline:public".to_string(), "timeline:local".to_string()]);
+    assert_eq!(streams.last_ids["timeline:public"], "$");
+    assert!(streams.next_xread().is_some());
+
+    streams.replied(true); // no entries before `XREAD` stopped blocking
+    assert_eq!(streams.last_ids["timeline:local"], "$");
+    assert!(streams.next_xread().is_some());
+
+    streams.read_entry("timeline:public", "1590000000000-7");
+    streams.replied(true);
+    assert_eq!(streams.last_ids["timeline:public"], "1590000000000-7");
+    // Just before `1590000000000-0`, so an entry with that ID isn't skipped
+    assert_eq!(
+        streams.last_ids["timeline:local"],
+        "1589999999999-18446744073709551615"
+    );
+}
+
+#[test]
+fn streams_at_the_start_of_redis_time_read_every_entry() {
+    let mut streams = streams(false);
+    streams.subscribe(&["timeline:public".to_string(), "timeline:local".to_string()]);
+    streams.read_entry("timeline:public", "0-1");
+    streams.replied(true);
+    assert_eq!(streams.last_ids["timeline:local"], "0-0");
+}
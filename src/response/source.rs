@@ -0,0 +1,64 @@
+//! Sources of the events that the `Manager` sends to its clients.
+//!
+//! In production, events come from Redis (or, optionally, from Postgres notifications); the
+//! in-memory source lets embedders (and tests) inject events directly.
+mod memory;
+mod postgres;
+pub use memory::{MemorySender, MemorySource};
+pub use postgres::PostgresSource;
+
+use super::{Error, Event};
+use crate::request::Timeline;
+
+use futures::{Async, Poll};
+use std::sync::Arc;
+
+/// Something the `Manager` can subscribe to timelines on and poll for their events.
+pub trait EventSource: Send {
+    /// Start receiving events for `timelines`
+    fn subscribe(&mut self, timelines: &[Timeline]) -> Result<(), Error>;
+
+    /// Stop receiving events for `timelines`
+    fn unsubscribe(&mut self, timelines: &[Timeline]) -> Result<(), Error>;
+
+    /// Called on every ping cycle with all the timelines that remain subscribed (for example,
+    /// to keep Redis's `subscribed:` keys from expiring)
+    fn refresh(&mut self, _timelines: &[Timeline]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Yield the next event, registering the current task to be woken when one is available.
+    ///
+    /// Returns `Ready(None)` once the source has been disconnected (and should `reconnect`).
+    fn poll_event(&mut self) -> Poll<Option<(Timeline, Arc<Event>)>, Error>;
+
+    /// Finish sending any commands that are still queued (for example, before shutting
+    /// down), registering the current task to be woken once more can be sent
+    fn poll_flush(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+
+    /// Reconnect after the source has been disconnected.  Subscriptions do not need to be
+    /// preserved; the `Manager` subscribes to all of its timelines again afterwards.
+    ///
+    /// Connecting must not block: return `NotReady` (and wake the current task once the
+    /// `Manager` should call `reconnect` again) until reconnected.
+    fn reconnect(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+
+    /// Whether the source should `reconnect` even though it is still connected (for example,
+    /// after a Redis failover)
+    fn needs_reconnect(&self) -> bool {
+        false
+    }
+
+    /// Remember the name of the hashtag with `id` (Redis timelines use hashtag names, but
+    /// `Timeline`s use IDs)
+    fn cache_hashtag(&mut self, _id: i64, _name: &str) {}
+
+    /// The number of bytes received but not yet parsed into events
+    fn unread_len(&self) -> usize {
+        0
+    }
+}
@@ -0,0 +1,67 @@
+//! An `EventSource` for events that are sent from within the same process.
+use super::{Error, Event, EventSource};
+use crate::request::Timeline;
+
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{Async, Poll, Stream};
+use hashbrown::HashSet;
+use std::sync::Arc;
+
+/// Receives the events sent with its `MemorySender`.  As with Redis, events for timelines
+/// that nothing has subscribed to are discarded.
+#[derive(Debug)]
+pub struct MemorySource {
+    events: UnboundedReceiver<(Timeline, Arc<Event>)>,
+    timelines: HashSet<Timeline>,
+}
+
+/// Sends events to a `MemorySource`
+#[derive(Debug, Clone)]
+pub struct MemorySender(UnboundedSender<(Timeline, Arc<Event>)>);
+
+impl MemorySource {
+    pub fn new() -> (Self, MemorySender) {
+        let (tx, rx) = mpsc::unbounded();
+        let source = Self {
+            events: rx,
+            timelines: HashSet::new(),
+        };
+        (source, MemorySender(tx))
+    }
+}
+
+impl MemorySender {
+    /// Send `event` to the clients subscribed to `timeline`.
+    ///
+    /// Returns `false` if the `MemorySource` has been dropped.
+    pub fn send(&self, timeline: Timeline, event: Event) -> bool {
+        self.0.unbounded_send((timeline, Arc::new(event))).is_ok()
+    }
+}
+
+impl EventSource for MemorySource {
+    fn subscribe(&mut self, timelines: &[Timeline]) -> Result<(), Error> {
+        self.timelines.extend(timelines);
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, timelines: &[Timeline]) -> Result<(), Error> {
+        for tl in timelines {
+            self.timelines.remove(tl);
+        }
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Poll<Option<(Timeline, Arc<Event>)>, Error> {
+        loop {
+            match self.events.poll() {
+                Ok(Async::Ready(Some((tl, event)))) if self.timelines.contains(&tl) => {
+                    return Ok(Async::Ready(Some((tl, event))))
+                }
+                Ok(Async::Ready(Some(_unsubscribed))) => continue,
+                // If all `MemorySender`s have been dropped, there will never be another event
+                Ok(Async::NotReady | Async::Ready(None)) | Err(()) => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
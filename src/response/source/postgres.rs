@@ -0,0 +1,160 @@
+//! An `EventSource` that receives events from Postgres `NOTIFY`s instead of from Redis.
+use super::{Error, Event, EventSource};
+use crate::config;
+use crate::request::{self, Listener, Timeline};
+
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll, Stream};
+use hashbrown::HashSet;
+use lru::LruCache;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::thread;
+
+/// Receives the payloads of notifications on the `DB_NOTIFY_CHANNELS` and parses them into
+/// events.  Each payload is a timeline and an event separated by a space (e.g.,
+/// `timeline:public {"event":"delete","payload":"1038647"}`).
+///
+/// Postgres rejects `NOTIFY` payloads of 8000 bytes or more, so events that are larger than
+/// that (such as statuses with many mentions or attachments) can't be sent this way.
+///
+/// The notifications are received on their own thread (the `postgres` client only blocks).
+#[derive(Debug)]
+pub struct PostgresSource {
+    payloads: UnboundedReceiver<String>,
+    timelines: HashSet<Timeline>,
+    tag_id_cache: LruCache<String, i64>,
+    pg_cfg: config::Postgres,
+    /// Whether the thread started by `reconnect` is listening yet
+    reconnecting: Option<oneshot::Receiver<Result<(), request::Error>>>,
+}
+
+impl PostgresSource {
+    /// Connect to Postgres and `LISTEN` on each of the `DB_NOTIFY_CHANNELS`
+    pub fn new(pg_cfg: &config::Postgres) -> Result<Self, request::Error> {
+        let channels = pg_cfg.notify_channels.clone().0;
+        let listener = Listener::new(pg_cfg, channels.clone())?;
+
+        log::info!("Listening for events on Postgres channels {:?}", channels);
+        let (tx, rx) = mpsc::unbounded();
+        thread::spawn(move || Self::receive(listener, &tx));
+
+        Ok(Self {
+            payloads: rx,
+            timelines: HashSet::new(),
+            tag_id_cache: LruCache::new(1000),
+            pg_cfg: pg_cfg.clone(),
+            reconnecting: None,
+        })
+    }
+
+    /// Send the payload of every notification to `tx` (stopping once the `PostgresSource` has
+    /// been dropped)
+    fn receive(listener: Listener, tx: &UnboundedSender<String>) {
+        listener.receive(
+            |payload| tx.unbounded_send(payload.to_string()).is_ok(),
+            || (),
+            || (),
+        );
+    }
+
+    /// Start a new listening thread (connecting blocks, so it happens on that thread), which
+    /// reports whether it could connect through the returned channel
+    fn listen_in_background(&mut self) -> oneshot::Receiver<Result<(), request::Error>> {
+        let (tx, rx) = mpsc::unbounded();
+        let (connected_tx, connected_rx) = oneshot::channel();
+        let pg_cfg = self.pg_cfg.clone();
+        thread::spawn(
+            move || match Listener::new(&pg_cfg, pg_cfg.notify_channels.clone().0) {
+                // Fails only if the `PostgresSource` has been dropped in the meantime
+                Ok(listener) => {
+                    if connected_tx.send(Ok(())).is_ok() {
+                        Self::receive(listener, &tx);
+                    }
+                }
+                Err(e) => {
+                    let _ = connected_tx.send(Err(e));
+                }
+            },
+        );
+        self.payloads = rx;
+        connected_rx
+    }
+
+    /// Parse a notification's payload, returning `None` if no one is subscribed to its timeline
+    fn parse(&mut self, payload: &str) -> Result<Option<(Timeline, Arc<Event>)>, Error> {
+        let mut parts = payload.splitn(2, ' ');
+        let (tl_txt, event_txt) = match (parts.next(), parts.next()) {
+            (Some(tl_txt), Some(event_txt)) if tl_txt.starts_with("timeline:") => {
+                (&tl_txt["timeline:".len()..], event_txt)
+            }
+            _ => Err(Error::InvalidNotification(payload.to_string()))?,
+        };
+
+        // We receive every timeline, including hashtags no client has asked for (and that
+        // thus aren't in the cache); no one wants those events
+        let tl = match Timeline::from_redis_text(tl_txt, &mut self.tag_id_cache) {
+            Ok(tl) if self.timelines.contains(&tl) => tl,
+            _ => return Ok(None),
+        };
+        let event = Event::try_from(event_txt)?;
+        Ok(Some((tl, Arc::new(event))))
+    }
+}
+
+impl EventSource for PostgresSource {
+    fn subscribe(&mut self, timelines: &[Timeline]) -> Result<(), Error> {
+        self.timelines.extend(timelines);
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, timelines: &[Timeline]) -> Result<(), Error> {
+        for tl in timelines {
+            self.timelines.remove(tl);
+        }
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Poll<Option<(Timeline, Arc<Event>)>, Error> {
+        loop {
+            match self.payloads.poll() {
+                Ok(Async::Ready(Some(payload))) => {
+                    if let Some(event) = self.parse(&payload)? {
+                        return Ok(Async::Ready(Some(event)));
+                    }
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // The listening thread reconnects on its own, so it has only stopped if it
+                // panicked (or could never connect after `reconnect`)
+                Ok(Async::Ready(None)) | Err(()) => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+
+    /// Start a new listening thread.  Returns `NotReady` (and wakes the current task once the
+    /// thread has connected) until it is listening, or an error if it could not connect.
+    fn reconnect(&mut self) -> Poll<(), Error> {
+        let mut pending = match self.reconnecting.take() {
+            Some(pending) => pending,
+            None => self.listen_in_background(),
+        };
+        match pending.poll() {
+            Ok(Async::Ready(connected)) => Ok(Async::Ready(connected?)),
+            Ok(Async::NotReady) => {
+                self.reconnecting = Some(pending);
+                Ok(Async::NotReady)
+            }
+            Err(oneshot::Canceled) => Err(Error::PgErr(
+                "the thread connecting to Postgres panicked".to_string(),
+            )),
+        }
+    }
+
+    fn cache_hashtag(&mut self, id: i64, name: &str) {
+        self.tag_id_cache.put(name.to_string(), id);
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,135 @@
+use super::*;
+use futures::future::{self, Future};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc as std_mpsc;
+use std::time::Duration;
+
+type TestResult = std::result::Result<(), Box<dyn std::error::Error>>;
+
+fn source() -> PostgresSource {
+    let (_tx, rx) = mpsc::unbounded();
+    let (pg_cfg, _, _) = config::from_env(hashbrown::HashMap::new()).expect("the default config");
+    PostgresSource {
+        payloads: rx,
+        timelines: HashSet::new(),
+        tag_id_cache: LruCache::new(1),
+        pg_cfg,
+        reconnecting: None,
+    }
+}
+
+/// A source that reconnects to Postgres on `port`
+fn source_for(port: u16) -> Result<PostgresSource, config::Error> {
+    let env = vec![
+        ("DB_HOST", "127.0.0.1".to_string()),
+        ("DB_PORT", port.to_string()),
+        ("DB_SSLMODE", "disable".to_string()),
+    ];
+    let env = env.into_iter().map(|(k, v)| (k.to_string(), v));
+    let (pg_cfg, _, _) = config::from_env(env.collect())?;
+    Ok(PostgresSource { pg_cfg, ..source() })
+}
+
+/// Read one message from a Postgres client (the first message has no type byte)
+fn read_msg(conn: &mut TcpStream, typed: bool) -> io::Result<Vec<u8>> {
+    let mut header = vec![0_u8; if typed { 5 } else { 4 }];
+    conn.read_exact(&mut header)?;
+    let len = header[header.len() - 4..]
+        .iter()
+        .fold(0, |len, byte| (len << 8) | usize::from(*byte));
+    let mut body = vec![0_u8; len - 4];
+    conn.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// A stand-in for Postgres that accepts one client (without authentication) once `connect`
+/// receives a message and then completes each query it is sent
+fn fake_postgres(connect: std_mpsc::Receiver<()>) -> io::Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    thread::spawn(move || -> io::Result<()> {
+        let (mut conn, _) = listener.accept()?;
+        read_msg(&mut conn, false)?; // the startup message
+        let _ = connect.recv();
+        let (auth_ok, ready) = (b"R\0\0\0\x08\0\0\0\0", b"Z\0\0\0\x05I");
+        conn.write_all(&[&auth_ok[..], &ready[..]].concat())?;
+        loop {
+            read_msg(&mut conn, true)?;
+            conn.write_all(b"C\0\0\0\x0bLISTEN\0Z\0\0\0\x05I")?;
+        }
+    });
+    Ok(port)
+}
+
+#[test]
+fn postgres_source_parses_notifications_for_subscribed_timelines() -> TestResult {
+    let mut source = source();
+    let public = Timeline::from_redis_text("public", &mut LruCache::new(1))?;
+    let delete = r#"{"event":"delete","payload":"1038647"}"#;
+
+    let payload = format!("timeline:public {}", delete);
+    assert!(source.parse(&payload)?.is_none());
+
+    source.subscribe(&[public])?;
+    let (tl, event) = source
+        .parse(&payload)?
+        .expect("subscribed to the public timeline");
+    assert_eq!(tl, public);
+    assert_eq!(*event, Event::try_from(delete)?);
+
+    let unknown_tag = format!("timeline:hashtag:a {}", delete);
+    Ok(assert!(source.parse(&unknown_tag)?.is_none()))
+}
+
+#[test]
+fn postgres_source_rejects_notifications_without_a_timeline() {
+    let mut source = source();
+    for payload in &[
+        "timeline:public",
+        r#"public {"event":"delete","payload":"1"}"#,
+    ] {
+        match source.parse(payload) {
+            Err(Error::InvalidNotification(p)) => assert_eq!(&p, payload),
+            other => panic!("expected an InvalidNotification error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn postgres_source_ends_when_the_listening_thread_stops() -> TestResult {
+    let mut source = source(); // the thread's end of the channel has already been dropped
+    let polled = future::lazy(|| source.poll_event()).wait()?;
+    Ok(assert_eq!(polled, Async::Ready(None)))
+}
+
+#[test]
+fn postgres_source_is_reconnecting_until_it_is_listening() -> TestResult {
+    let (connect, connect_rx) = std_mpsc::channel();
+    let mut source = source_for(fake_postgres(connect_rx)?)?;
+
+    future::lazy(|| {
+        for _ in 0..5 {
+            assert_eq!(source.reconnect()?, Async::NotReady);
+            thread::sleep(Duration::from_millis(20));
+        }
+        Ok::<_, Error>(())
+    })
+    .wait()?;
+
+    connect.send(())?;
+    future::poll_fn(|| source.reconnect()).wait()?;
+    Ok(())
+}
+
+#[test]
+fn postgres_source_reports_a_failure_to_reconnect() -> TestResult {
+    // Nothing listens on a port that has been released
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let mut source = source_for(port)?;
+
+    match future::poll_fn(|| source.reconnect()).wait() {
+        Err(Error::PgErr(_)) => Ok(()),
+        other => panic!("expected a Postgres error, got {:?}", other),
+    }
+}
@@ -1,7 +1,7 @@
 pub use sse::Sse;
 pub use ws::Ws;
 
-pub(self) use super::{Event, Payload};
+pub(self) use super::{Event, Payload, RedisManager};
 
 mod sse;
 mod ws;
@@ -1,14 +1,14 @@
 use super::{Event, Payload};
-use crate::request::Subscription;
+use crate::request::{Subscription, Timeline};
 
-use futures::stream::Stream;
+use futures::stream::{self, Stream};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::{error::RecvError, Receiver};
 use warp::reply::Reply;
 use warp::sse::Sse as WarpSse;
 
-type EventRx = Receiver<Arc<Event>>;
+type EventRx = Receiver<(Timeline, u64, Arc<Event>)>;
 
 pub struct Sse(Subscription);
 
@@ -17,14 +17,27 @@ impl Sse {
         Self(subscription)
     }
 
-    pub fn send_events(self, sse: WarpSse, event_rx: EventRx) -> impl Reply {
-        let event_stream = event_rx.filter_map(move |event| {
-            match (event.update_payload(), event.dyn_update_payload()) {
-                (Some(update), _) if self.update_not_filtered(update) => event.to_warp_reply(),
-                (_, Some(update)) if self.update_not_filtered(update) => event.to_warp_reply(),
-                (_, _) => event.to_warp_reply(), // send all non-updates
-            }
-        });
+    /// Send `missed` events (replayed for a client that reconnected with a `Last-Event-ID`)
+    /// followed by all new events from `event_rx`
+    pub fn send_events(
+        self,
+        sse: WarpSse,
+        event_rx: EventRx,
+        missed: Vec<(Timeline, u64, Arc<Event>)>,
+    ) -> impl Reply {
+        let event_stream = stream::iter_ok::<_, RecvError>(missed)
+            .chain(event_rx)
+            .filter_map(move |(_tl, id, event)| {
+                match (event.update_payload(), event.dyn_update_payload()) {
+                    (Some(update), _) if self.update_not_filtered(update) => {
+                        event.to_warp_reply(id)
+                    }
+                    (_, Some(update)) if self.update_not_filtered(update) => {
+                        event.to_warp_reply(id)
+                    }
+                    (_, _) => event.to_warp_reply(id), // send all non-updates
+                }
+            });
 
         sse.reply(
             warp::sse::keep_alive()
@@ -1,45 +1,133 @@
-use super::{Event, Payload};
-use crate::request::Subscription;
+use super::{Event, Payload, RedisManager};
+use crate::request::{Handler, Subscription, Timeline, WsCmd};
 
 use futures::future::Future;
-use futures::stream::Stream;
-use std::sync::Arc;
-use tokio::sync::mpsc::Receiver;
+use futures::stream::{self, Stream};
+use hashbrown::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockWriteGuard};
+use tokio::sync::mpsc::{self, Receiver, UnboundedReceiver, UnboundedSender};
 use warp::ws::{Message, WebSocket};
+use warp::Rejection;
 
-type EventRx = Receiver<Arc<Event>>;
+type EventRx = Receiver<(Timeline, u64, Arc<Event>)>;
+type Streams = Arc<RwLock<HashMap<Timeline, Vec<String>>>>;
 
-pub struct Ws(Subscription);
+pub struct Ws {
+    subscription: Subscription,
+    event_rx: EventRx,
+    err_rx: UnboundedReceiver<String>,
+    streams: Streams,
+    timelines: Timelines,
+    pg_handler: Handler,
+}
+
+/// The timelines a WebSocket is subscribed to, which the client can change by sending
+/// `subscribe` and `unsubscribe` messages over the open connection.
+///
+/// This deliberately does not hold a copy of the event channel's `Sender`: if the `Manager`
+/// disconnects a slow client, the channel closes and so does the WebSocket.  Messages that are
+/// rejected are answered with error frames, which are sent through `err_tx`.
+struct Timelines {
+    manager: Arc<Mutex<RedisManager>>,
+    client_id: u32,
+    subscribed: HashSet<Timeline>,
+    streams: Streams,
+    err_tx: UnboundedSender<String>,
+}
 
 impl Ws {
-    pub fn new(subscription: Subscription) -> Self {
-        Self(subscription)
+    pub fn new(
+        subscription: Subscription,
+        manager: Arc<Mutex<RedisManager>>,
+        pg_handler: Handler,
+    ) -> Self {
+        let (event_tx, event_rx) = mpsc::channel(10);
+        let client_id = manager
+            .lock()
+            .unwrap_or_else(RedisManager::recover)
+            .add_client(event_tx);
+        let streams = Streams::default();
+        let (err_tx, err_rx) = mpsc::unbounded_channel();
+        let mut timelines = Timelines {
+            manager,
+            client_id,
+            subscribed: HashSet::new(),
+            streams: streams.clone(),
+            err_tx,
+        };
+        if subscription.timeline != Timeline::empty() {
+            timelines.subscribe(&subscription);
+        }
+
+        Self {
+            subscription,
+            event_rx,
+            err_rx,
+            streams,
+            timelines,
+            pg_handler,
+        }
     }
 
-    pub fn send_to(
-        mut self,
-        ws: WebSocket,
-        event_rx: EventRx,
-    ) -> impl Future<Item = (), Error = ()> {
-        let (transmit_to_ws, _receive_from_ws) = ws.split();
-        event_rx
-            .filter_map(move |event| {
+    pub fn send_to(self, ws: WebSocket) -> impl Future<Item = (), Error = ()> {
+        let Self {
+            subscription,
+            event_rx,
+            err_rx,
+            streams,
+            mut timelines,
+            pg_handler,
+        } = self;
+        let (transmit_to_ws, receive_from_ws) = ws.split();
+        let conn = subscription.clone();
+
+        // Commands are authorized one at a time (without blocking the executor on Postgres),
+        // so they take effect in the order the client sent them
+        let incoming = receive_from_ws
+            .map_err(|e| log::warn!("WebSocket receive error: {}", e))
+            // only text messages can change subscriptions
+            .filter_map(|msg| msg.to_str().ok().map(String::from))
+            .and_then(move |txt| {
+                pg_handler
+                    .ws_cmd(&txt, &conn)
+                    .then(move |cmd| Ok((txt, cmd)))
+            })
+            .for_each(move |(txt, cmd)| {
+                timelines.handle(&txt, cmd);
+                Ok(())
+            });
+
+        let events = event_rx
+            .filter_map(move |(tl, _id, event)| {
+                let streams = streams.read().unwrap_or_else(PoisonError::into_inner);
+                let stream = streams.get(&tl).map(Vec::as_slice);
                 if matches!(*event, Event::Ping) {
-                    Some(Message::text(&event.to_json_string()))
+                    Some(Message::text(event.to_json_string(None)))
                 } else {
                     match (event.update_payload(), event.dyn_update_payload()) {
-                        (Some(update), _) if !self.filtered(update) => {
-                            Some(Message::text(&event.to_json_string()))
+                        (Some(update), _) if !filtered(&subscription, tl, update) => {
+                            Some(Message::text(event.to_json_string(stream)))
                         }
-                        (None, None) => Some(Message::text(&event.to_json_string())), // send all non-updates
-                        (_, Some(dyn_update)) if !self.filtered(dyn_update) => {
-                            Some(Message::text(&event.to_json_string()))
+                        (None, None) => Some(Message::text(event.to_json_string(stream))), // send all non-updates
+                        (_, Some(dyn_update)) if !filtered(&subscription, tl, dyn_update) => {
+                            Some(Message::text(event.to_json_string(stream)))
                         }
                         _ => None,
                     }
                 }
             })
-            .map_err(|_| -> warp::Error { unreachable!() })
+            .map_err(|_| -> warp::Error { unreachable!() });
+        let errors = err_rx
+            .map(Message::text)
+            .map_err(|_| -> warp::Error { unreachable!() });
+
+        // Error frames are sent between events, but only until the event channel closes
+        let outgoing = events
+            .map(Some)
+            .chain(stream::once(Ok(None)))
+            .select(errors.map(Some))
+            .take_while(|msg| Ok(msg.is_some()))
+            .filter_map(|msg| msg)
             .forward(transmit_to_ws)
             .map(|_r| ())
             // ignore errors that indicate normal disconnects.  TODO - once we upgrade our
@@ -48,29 +136,82 @@ impl Ws {
                 "IO error: Broken pipe (os error 32)"
                 | "IO error: Connection reset by peer (os error 104)" => (),
                 e => log::warn!("WebSocket send error: {}", e),
-            })
+            });
+
+        // The connection is finished as soon as either the client or the server hangs up
+        outgoing.select(incoming).map(|_| ()).map_err(|_| ())
     }
-    fn filtered<T: std::fmt::Debug + Payload>(&mut self, update: &T) -> bool {
-        let (blocks, allowed_langs) = (&self.0.blocks, &self.0.allowed_langs);
-        let skip = |msg| {
-            // Some(log::info!("{:?} msg skipped - {}\n{:?}", self.0.timeline, msg, update)).is_some()
-            Some(log::info!("{:?} msg skipped - {}", self.0.timeline, msg)).is_some()
-        };
+}
+
+fn filtered<T: std::fmt::Debug + Payload>(
+    subscription: &Subscription,
+    tl: Timeline,
+    update: &T,
+) -> bool {
+    let (blocks, allowed_langs) = (&subscription.blocks, &subscription.allowed_langs);
+    let skip = |msg| {
+        // log::info!("{:?} msg skipped - {}\n{:?}", tl, msg, update);
+        log::info!("{:?} msg skipped - {}", tl, msg);
+        true
+    };
 
-        match self.0.timeline {
-            tl if tl.is_public()
-                && !update.language_unset()
-                && !allowed_langs.is_empty()
-                && !allowed_langs.contains(&update.language()) =>
-            {
-                skip("disallowed language")
+    match tl {
+        tl if tl.is_public()
+            && !update.language_unset()
+            && !allowed_langs.is_empty()
+            && !allowed_langs.contains(&update.language()) =>
+        {
+            skip("disallowed language")
+        }
+        _ if !blocks.blocked_users.is_disjoint(&update.involved_users()) => {
+            skip("involves blocked user")
+        }
+        _ if blocks.blocking_users.contains(update.author()) => skip("from blocking user"),
+        _ if blocks.blocked_domains.contains(update.sent_from()) => skip("from blocked domain"),
+        _ => false,
+    }
+}
+
+impl Timelines {
+    fn handle(&mut self, txt: &str, cmd: Result<WsCmd, Rejection>) {
+        match cmd {
+            Ok(WsCmd::Subscribe(subscription)) => self.subscribe(&subscription),
+            Ok(WsCmd::Unsubscribe(tl)) => self.unsubscribe(tl),
+            Err(e) => {
+                log::info!("Rejecting WebSocket message `{}`", txt);
+                // This can only fail if the WebSocket is closing, in which case there's no one
+                // to tell
+                let _ = self.err_tx.try_send(Handler::ws_err(&e));
             }
-            _ if !blocks.blocked_users.is_disjoint(&update.involved_users()) => {
-                skip("involves blocked user")
+        }
+    }
+
+    fn subscribe(&mut self, subscription: &Subscription) {
+        if self.subscribed.contains(&subscription.timeline) {
+            return;
+        }
+        let tl = subscription.timeline;
+        match tl.to_stream(subscription.hashtag_name.as_ref()) {
+            Ok(stream) => {
+                self.streams().insert(tl, stream);
             }
-            _ if blocks.blocking_users.contains(update.author()) => skip("from blocking user"),
-            _ if blocks.blocked_domains.contains(update.sent_from()) => skip("from blocked domain"),
-            _ => false,
+            Err(e) => log::error!("Could not name the stream for {:?}: {}", tl, e),
         }
+
+        let mut manager = self.manager.lock().unwrap_or_else(RedisManager::recover);
+        manager.subscribe(self.client_id, subscription);
+        self.subscribed.insert(tl);
+    }
+
+    fn unsubscribe(&mut self, tl: Timeline) {
+        if self.subscribed.remove(&tl) {
+            let mut manager = self.manager.lock().unwrap_or_else(RedisManager::recover);
+            manager.unsubscribe(self.client_id, tl);
+            self.streams().remove(&tl);
+        }
+    }
+
+    fn streams(&self) -> RwLockWriteGuard<'_, HashMap<Timeline, Vec<String>>> {
+        self.streams.write().unwrap_or_else(PoisonError::into_inner)
     }
 }